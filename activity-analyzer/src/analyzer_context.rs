@@ -6,6 +6,10 @@ use crate::cadence_analyzer::CadenceAnalyzer;
 use crate::power_analyzer::PowerAnalyzer;
 use crate::temperature_analyzer::TemperatureAnalyzer;
 use crate::swim_analyzer::SwimAnalyzer;
+use crate::running_dynamics_analyzer::RunningDynamicsAnalyzer;
+use crate::gear_analyzer::{GearAnalyzer, GearChange};
+use crate::radar_analyzer::{RadarAnalyzer, RadarThreat};
+use crate::sample_table::SampleTable;
 use crate::event::Event;
 
 /// Context structure. An instance of this will be passed to the parser and ultimately to the callback function so we can use it for whatever.
@@ -17,6 +21,9 @@ pub struct AnalyzerContext {
     pub power_analyzer: PowerAnalyzer,
     pub temperature_analyzer: TemperatureAnalyzer,
     pub swim_analyzer: SwimAnalyzer,
+    pub running_dynamics_analyzer: RunningDynamicsAnalyzer,
+    pub gear_analyzer: GearAnalyzer,
+    pub radar_analyzer: RadarAnalyzer,
     pub events: Vec<Event>,
 }
 
@@ -31,9 +38,69 @@ impl AnalyzerContext {
             power_analyzer: PowerAnalyzer::new(),
             temperature_analyzer: TemperatureAnalyzer::new(),
             swim_analyzer: SwimAnalyzer::new(),
+            running_dynamics_analyzer: RunningDynamicsAnalyzer::new(),
+            gear_analyzer: GearAnalyzer::new(),
+            radar_analyzer: RadarAnalyzer::new(),
             events: Vec::new()
         }
     }
+
+    /// Returns all the readings in a single, time-aligned table.
+    pub fn sample_table(&self) -> SampleTable {
+        SampleTable::from_context(self)
+    }
+
+    /// Returns the start of the activity: the first location, or the first reading of any kind for indoor activities.
+    pub fn start_time_ms(&self) -> u64 {
+        let start_time_ms = self.location_analyzer.start_time_ms;
+
+        if start_time_ms == 0 {
+            let power_data = &self.power_analyzer;
+            let sensor_times = [
+                &self.hr_analyzer.time_readings,
+                &self.cadence_analyzer.time_readings,
+                &power_data.time_readings,
+                &power_data.cycling_dynamics_times,
+                &self.temperature_analyzer.time_readings,
+            ];
+            return sensor_times.iter().filter_map(|times| times.first()).min().copied().unwrap_or(0);
+        }
+        start_time_ms
+    }
+
+    /// Feeds the event to the analyzer it came from, as the file readers do, and records it.
+    pub fn replay_event(&mut self, event: &Event) {
+        match event {
+            Event::TimerStart { timestamp_ms } => self.location_analyzer.start_timer(*timestamp_ms),
+            Event::TimerStop { timestamp_ms } => self.location_analyzer.stop_timer(*timestamp_ms),
+            Event::Lap { timestamp_ms } => self.location_analyzer.append_lap(*timestamp_ms),
+            Event::GearChange { timestamp_ms, front_gear_num, front_gear_teeth, rear_gear_num, rear_gear_teeth } => {
+                self.gear_analyzer.append_gear_change(GearChange{ timestamp_ms: *timestamp_ms, front_gear_num: *front_gear_num, front_gear_teeth: *front_gear_teeth,
+                    rear_gear_num: *rear_gear_num, rear_gear_teeth: *rear_gear_teeth });
+            }
+            Event::RadarThreat { timestamp_ms, threat_level, threat_count, approach_speed } => {
+                self.radar_analyzer.append_threat(RadarThreat{ timestamp_ms: *timestamp_ms, threat_level: *threat_level, threat_count: *threat_count,
+                    approach_speed: *approach_speed, latitude: None, longitude: None });
+            }
+            _ => {}
+        }
+        self.events.push(event.clone());
+    }
+
+    /// For calculations that only make sense once all the points have been added.
+    pub fn analyze(&mut self) {
+        self.location_analyzer.analyze();
+        self.power_analyzer.analyze();
+        self.running_dynamics_analyzer.analyze(&self.location_analyzer);
+
+        // The last gear is held until the end of the activity.
+        let mut end_time_ms = self.location_analyzer.last_time_ms;
+        if let Some(last_cadence_time_ms) = self.cadence_analyzer.time_readings.last() {
+            end_time_ms = u64::max(end_time_ms, *last_cadence_time_ms);
+        }
+        self.gear_analyzer.analyze(end_time_ms, &self.cadence_analyzer, &self.location_analyzer.speed_times, &self.location_analyzer.speed_graph);
+        self.radar_analyzer.analyze(&self.location_analyzer);
+    }
 }
 
 impl Default for AnalyzerContext {
@@ -41,3 +108,26 @@ impl Default for AnalyzerContext {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::analyzer_context::AnalyzerContext;
+
+    const START_TIME_MS: u64 = 1600000000000;
+
+    #[test]
+    fn test_start_time() {
+        let mut context = AnalyzerContext::new();
+        assert_eq!(context.start_time_ms(), 0);
+
+        // Without a track, the earliest sensor reading starts the activity.
+        context.hr_analyzer.append_sensor_value(START_TIME_MS + 2000, 120.0);
+        context.power_analyzer.append_sensor_value(START_TIME_MS + 1000, 200.0);
+        context.temperature_analyzer.append_sensor_value(START_TIME_MS + 3000, 20.0);
+        assert_eq!(context.start_time_ms(), START_TIME_MS + 1000);
+
+        // The first location wins once there is one.
+        context.location_analyzer.append_location(START_TIME_MS + 5000, 45.0, -75.0, None);
+        assert_eq!(context.start_time_ms(), START_TIME_MS + 5000);
+    }
+}
@@ -0,0 +1,331 @@
+// Copyright (c) 2021 Michael J. Simms. All rights reserved.
+
+extern crate chrono;
+
+use chrono::*;
+
+const MIN_EPOCH_SECONDS: f64 = 1.0e9; // Numeric times at least this large are seconds since the epoch, rather than since the start
+const MIN_EPOCH_MS: f64 = 1.0e11; // Numeric times at least this large are milliseconds since the epoch
+
+/// The columns we know how to read.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum CsvColumn {
+    Time,
+    Latitude,
+    Longitude,
+    Altitude,
+    HeartRate,
+    Cadence,
+    Power,
+    Temperature,
+    Distance,
+}
+
+/// Header names (lower case, with the units and punctuation removed) that we recognize for each column.
+const COLUMN_ALIASES: &[(CsvColumn, &[&str])] = &[
+    (CsvColumn::Time, &["time", "timestamp", "datetime", "elapsedtime", "seconds"]),
+    (CsvColumn::Latitude, &["latitude", "lat", "positionlat"]),
+    (CsvColumn::Longitude, &["longitude", "lon", "lng", "long", "positionlong"]),
+    (CsvColumn::Altitude, &["altitude", "alt", "elevation", "ele"]),
+    (CsvColumn::HeartRate, &["heartrate", "hr", "heartratebpm", "bpm", "pulse"]),
+    (CsvColumn::Cadence, &["cadence", "cad", "rpm"]),
+    (CsvColumn::Power, &["power", "watts", "pwr"]),
+    (CsvColumn::Temperature, &["temperature", "temp", "atemp"]),
+    (CsvColumn::Distance, &["distance", "dist", "totaldistance"]),
+];
+
+/// One line of the file. Values are None where the cell was empty or isn't a number.
+#[derive(Debug, Default)]
+pub struct CsvRow {
+    pub time_ms: u64,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub altitude: Option<f64>, // Meters
+    pub heart_rate: Option<f64>, // Beats/minute
+    pub cadence: Option<f64>, // Revolutions (or steps)/minute
+    pub power: Option<f64>, // Watts
+    pub temperature: Option<f64>, // Degrees C
+    pub distance: Option<f64>, // Meters traveled since the start
+}
+
+/// The contents of the file.
+#[derive(Debug, Default)]
+pub struct CsvActivity {
+    pub rows: Vec<CsvRow>,
+    pub has_location: bool, // True if the file has both latitude and longitude columns
+}
+
+/// A time read from the file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum CsvTime {
+    Absolute(u64), // Milliseconds since the epoch
+    Elapsed(u64), // Milliseconds since the start of the activity
+}
+
+/// Ends the current row, skipping it if the line was blank.
+fn end_row(rows: &mut Vec<Vec<String>>, cells: &mut Vec<String>, cell: &mut String) {
+    cells.push(cell.trim().to_string());
+    cell.clear();
+
+    let row = std::mem::take(cells);
+    if row.len() > 1 || !row[0].is_empty() {
+        rows.push(row);
+    }
+}
+
+/// Splits the file into rows of cells, handling quoted cells (with doubled quotes for a literal quote) the way spreadsheets write them.
+/// Quoted cells can contain line breaks, so this has to work on the whole file rather than a line at a time. Blank lines are skipped.
+fn split_rows(s: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut in_quotes = false;
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    cell.push('"');
+                    chars.next();
+                }
+                else {
+                    in_quotes = false;
+                }
+            }
+            else {
+                cell.push(c);
+            }
+        }
+        else if c == '"' {
+            in_quotes = true;
+        }
+        else if c == ',' {
+            cells.push(cell.trim().to_string());
+            cell.clear();
+        }
+        else if c == '\n' || c == '\r' {
+            if c == '\r' && chars.peek() == Some(&'\n') {
+                chars.next();
+            }
+            end_row(&mut rows, &mut cells, &mut cell);
+        }
+        else {
+            cell.push(c);
+        }
+    }
+    end_row(&mut rows, &mut cells, &mut cell);
+    rows
+}
+
+/// Reduces a header to the form used in COLUMN_ALIASES, e.g. "Heart Rate (bpm)" becomes "heartrate".
+fn normalize_header(header: &str) -> String {
+    let without_units = match header.find(['(', '[']) {
+        Some(index) => &header[..index],
+        None => header,
+    };
+    without_units.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_lowercase()
+}
+
+/// Returns the column the header refers to, or None if it isn't one we know how to read.
+fn lookup_column(header: &str) -> Option<CsvColumn> {
+    let name = normalize_header(header);
+    COLUMN_ALIASES.iter().find(|(_, aliases)| aliases.contains(&name.as_str())).map(|(column, _)| *column)
+}
+
+/// Parses a duration of the form "h:mm:ss" or "mm:ss", with optional fractional seconds, returning the number of seconds.
+fn parse_duration(cell: &str) -> Option<f64> {
+    let mut seconds = 0.0;
+
+    for part in cell.split(':') {
+        let value = part.parse::<f64>().ok()?;
+        seconds = seconds * 60.0 + value;
+    }
+    Some(seconds)
+}
+
+/// Parses a timestamp. Accepts ISO 8601 (with or without a time zone, UTC is assumed if there isn't one), seconds or milliseconds
+/// since the epoch, and seconds (or "h:mm:ss") since the start.
+fn parse_time(cell: &str) -> Option<CsvTime> {
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(cell) {
+        return Some(CsvTime::Absolute(date_time.timestamp_millis() as u64));
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"] {
+        if let Ok(date_time) = NaiveDateTime::parse_from_str(cell, format) {
+            return Some(CsvTime::Absolute(Utc.from_utc_datetime(&date_time).timestamp_millis() as u64));
+        }
+    }
+    if let Ok(value) = cell.parse::<f64>() {
+        if value < 0.0 {
+            return None;
+        }
+        if value >= MIN_EPOCH_MS {
+            return Some(CsvTime::Absolute(value as u64));
+        }
+        if value >= MIN_EPOCH_SECONDS {
+            return Some(CsvTime::Absolute((value * 1000.0) as u64));
+        }
+        return Some(CsvTime::Elapsed((value * 1000.0) as u64));
+    }
+    if cell.contains(':') {
+        if let Some(seconds) = parse_duration(cell) {
+            return Some(CsvTime::Elapsed((seconds * 1000.0) as u64));
+        }
+    }
+    None
+}
+
+/// Parses a number, returning None for empty cells and anything that isn't a number.
+fn parse_value(cell: Option<&String>) -> Option<f64> {
+    cell.and_then(|cell| cell.parse::<f64>().ok()).filter(|value| value.is_finite())
+}
+
+/// Reads a CSV file with a header row. Columns are matched by their header, so they can be in any order,
+/// and columns we don't recognize are ignored. Rows without a valid time, or that are out of order, are skipped.
+/// Times relative to the start of the activity are added to the start time (in milliseconds since the epoch). The file doesn't
+/// say when such an activity happened, so it is an error for it to have relative times if the start time is zero.
+pub fn read(s: &str, start_time_ms: u64) -> Result<CsvActivity, String> {
+    let mut rows = split_rows(s.trim_start_matches('\u{feff}')).into_iter();
+
+    let header = match rows.next() {
+        Some(header) => header,
+        None => return Err("The CSV file is empty.".to_string()),
+    };
+
+    // Work out which cell holds each column. If a column appears more than once, the first one wins.
+    let mut indices: Vec<(CsvColumn, usize)> = Vec::new();
+    for (index, name) in header.iter().enumerate() {
+        if let Some(column) = lookup_column(name) {
+            if !indices.iter().any(|(existing, _)| *existing == column) {
+                indices.push((column, index));
+            }
+        }
+    }
+    let find = |column: CsvColumn| indices.iter().find(|(existing, _)| *existing == column).map(|(_, index)| *index);
+
+    let time_index = match find(CsvColumn::Time) {
+        Some(index) => index,
+        None => return Err("The CSV file does not have a time column.".to_string()),
+    };
+
+    let mut activity = CsvActivity{ rows: Vec::new(), has_location: find(CsvColumn::Latitude).is_some() && find(CsvColumn::Longitude).is_some() };
+    let mut last_time_ms = 0;
+
+    for cells in rows {
+        let value = |column: CsvColumn| find(column).and_then(|index| parse_value(cells.get(index)));
+
+        if let Some(time) = cells.get(time_index).and_then(|cell| parse_time(cell)) {
+            let time_ms = match time {
+                CsvTime::Absolute(time_ms) => time_ms,
+                CsvTime::Elapsed(_) if start_time_ms == 0 => {
+                    return Err("The CSV file has times relative to the start of the activity, so the start time is needed.".to_string());
+                }
+                CsvTime::Elapsed(elapsed_ms) => start_time_ms + elapsed_ms,
+            };
+            if time_ms < last_time_ms {
+                continue;
+            }
+            last_time_ms = time_ms;
+
+            activity.rows.push(CsvRow{
+                time_ms,
+                latitude: value(CsvColumn::Latitude),
+                longitude: value(CsvColumn::Longitude),
+                altitude: value(CsvColumn::Altitude),
+                heart_rate: value(CsvColumn::HeartRate),
+                cadence: value(CsvColumn::Cadence),
+                power: value(CsvColumn::Power),
+                temperature: value(CsvColumn::Temperature),
+                distance: value(CsvColumn::Distance),
+            });
+        }
+    }
+
+    if activity.rows.is_empty() {
+        return Err("The CSV file does not have any rows with a valid time.".to_string());
+    }
+    Ok(activity)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::csv_reader::{self, CsvTime};
+    use crate::csv_writer::CsvWriter;
+
+    const START_TIME_MS: u64 = 1600000000000;
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(csv_reader::parse_time("2020-09-13T12:26:40Z"), Some(CsvTime::Absolute(START_TIME_MS)));
+        assert_eq!(csv_reader::parse_time("2020-09-13T14:26:40.500+02:00"), Some(CsvTime::Absolute(START_TIME_MS + 500)));
+        assert_eq!(csv_reader::parse_time("2020-09-13 12:26:40"), Some(CsvTime::Absolute(START_TIME_MS)));
+        assert_eq!(csv_reader::parse_time("1600000000000"), Some(CsvTime::Absolute(START_TIME_MS)));
+        assert_eq!(csv_reader::parse_time("1600000000.5"), Some(CsvTime::Absolute(START_TIME_MS + 500)));
+        assert_eq!(csv_reader::parse_time("90.5"), Some(CsvTime::Elapsed(90500)));
+        assert_eq!(csv_reader::parse_time("1:02:03"), Some(CsvTime::Elapsed(3723000)));
+        assert_eq!(csv_reader::parse_time("02:03.5"), Some(CsvTime::Elapsed(123500)));
+        assert_eq!(csv_reader::parse_time("-1"), None);
+        assert_eq!(csv_reader::parse_time("yesterday"), None);
+        assert_eq!(csv_reader::parse_time(""), None);
+    }
+
+    #[test]
+    fn test_split_rows() {
+        let rows = csv_reader::split_rows("a, b ,\"c, d\"\r\n\n\"say \"\"hi\"\"\",\"two\nlines\",\n");
+        assert_eq!(rows, vec![vec!["a", "b", "c, d"], vec!["say \"hi\"", "two\nlines", ""]]);
+    }
+
+    #[test]
+    fn test_read() {
+        let s = "\u{feff}Time,Heart Rate (bpm),Notes,Latitude,Longitude,Power (W)\n\
+            2020-09-13T12:26:40Z,120,,45.0,-75.0,200\n\
+            2020-09-13T12:26:39Z,121,Out of order,45.0,-75.0,\n\
+            ,122,No time,45.0,-75.0,\n\
+            \n\
+            2020-09-13T12:26:41Z,abc,,45.0001,-75.0,210\n";
+        let activity = csv_reader::read(s, 0).unwrap();
+
+        assert!(activity.has_location);
+        assert_eq!(activity.rows.len(), 2);
+        assert_eq!(activity.rows[0].time_ms, START_TIME_MS);
+        assert_eq!(activity.rows[0].heart_rate, Some(120.0));
+        assert_eq!(activity.rows[0].power, Some(200.0));
+        assert_eq!(activity.rows[1].heart_rate, None);
+        assert_eq!(activity.rows[1].latitude, Some(45.0001));
+        assert_eq!(activity.rows[1].altitude, None);
+
+        assert!(csv_reader::read("", 0).is_err());
+        assert!(csv_reader::read("Heart Rate\n120\n", 0).is_err());
+        assert!(csv_reader::read("Time\nabc\n", 0).is_err());
+    }
+
+    #[test]
+    fn test_read_elapsed_times() {
+        let s = "Seconds,Distance (m),Watts\n0,0,150\n1,5,160\n2,10,170\n";
+
+        // Without a start time there's no way to know when the activity happened.
+        assert!(csv_reader::read(s, 0).is_err());
+
+        let activity = csv_reader::read(s, START_TIME_MS).unwrap();
+        assert!(!activity.has_location);
+        let times: Vec<u64> = activity.rows.iter().map(|row| row.time_ms).collect();
+        assert_eq!(times, vec![START_TIME_MS, START_TIME_MS + 1000, START_TIME_MS + 2000]);
+        assert_eq!(activity.rows[2].distance, Some(10.0));
+        assert_eq!(activity.rows[2].power, Some(170.0));
+    }
+
+    #[test]
+    fn test_read_written_file() {
+        let mut writer = CsvWriter::new();
+        writer.write_row(&["Time".to_string(), "Notes".to_string(), "Heart Rate (bpm)".to_string()]);
+        writer.write_row(&[START_TIME_MS.to_string(), "Felt \"great\", then\nnot so great".to_string(), "140".to_string()]);
+        writer.write_row(&[(START_TIME_MS + 1000).to_string(), "".to_string(), CsvWriter::format_value(Some(141.5))]);
+
+        let activity = csv_reader::read(&writer.close(), 0).unwrap();
+        assert_eq!(activity.rows.len(), 2);
+        assert_eq!(activity.rows[0].time_ms, START_TIME_MS);
+        assert_eq!(activity.rows[0].heart_rate, Some(140.0));
+        assert_eq!(activity.rows[1].time_ms, START_TIME_MS + 1000);
+        assert_eq!(activity.rows[1].heart_rate, Some(141.5));
+    }
+}
@@ -0,0 +1,44 @@
+// Copyright (c) 2021 Michael J. Simms. All rights reserved.
+
+pub struct CsvWriter {
+    data: String,
+}
+
+impl CsvWriter {
+    pub fn new() -> Self {
+        CsvWriter{ data: String::new() }
+    }
+
+    /// Quotes the cell if it contains anything that would otherwise break the row apart.
+    fn escape(cell: &str) -> String {
+        if cell.contains(',') || cell.contains('"') || cell.contains('\n') || cell.contains('\r') {
+            return format!("\"{}\"", cell.replace('"', "\"\""));
+        }
+        cell.to_string()
+    }
+
+    pub fn write_row(&mut self, cells: &[String]) {
+        let escaped: Vec<String> = cells.iter().map(|cell| CsvWriter::escape(cell)).collect();
+        self.data.push_str(&escaped.join(","));
+        self.data.push('\n');
+    }
+
+    /// Formats an optional value, leaving the cell empty if there isn't one.
+    pub fn format_value(value: Option<f64>) -> String {
+        match value {
+            Some(value) => value.to_string(),
+            None => String::new(),
+        }
+    }
+
+
+    pub fn close(self) -> String {
+        self.data
+    }
+}
+
+impl Default for CsvWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,204 @@
+// Copyright (c) 2021 Michael J. Simms. All rights reserved.
+
+// Reduces a series to a smaller number of points while keeping its shape. Both algorithms return the indices
+// of the points to keep, in order, so that any series sharing the same timestamps can be reduced the same way.
+
+/// Largest-Triangle-Three-Buckets. Keeps the first and last points and, from each bucket in between, the point
+/// that forms the largest triangle with the point kept from the previous bucket and the average of the next bucket.
+pub fn lttb(x: &[f64], y: &[f64], max_points: usize) -> Vec<usize> {
+    let num_points = usize::min(x.len(), y.len());
+
+    if max_points == 0 || num_points <= max_points {
+        return (0..num_points).collect();
+    }
+    if max_points < 3 {
+        return (0..max_points).map(|index| index * (num_points - 1)).collect();
+    }
+
+    let mut result = Vec::with_capacity(max_points);
+    let bucket_size = (num_points - 2) as f64 / (max_points - 2) as f64;
+    let mut prev_index = 0;
+
+    result.push(0);
+
+    for bucket in 0..max_points - 2 {
+        let bucket_start = (bucket as f64 * bucket_size) as usize + 1;
+        let bucket_end = usize::min(((bucket + 1) as f64 * bucket_size) as usize + 1, num_points - 1);
+
+        // Average of the next bucket, or the last point if this is the last bucket.
+        let next_start = bucket_end;
+        let next_end = usize::min(((bucket + 2) as f64 * bucket_size) as usize + 1, num_points);
+        let mut avg_x = x[num_points - 1];
+        let mut avg_y = y[num_points - 1];
+        if next_end > next_start {
+            let count = (next_end - next_start) as f64;
+            avg_x = Iterator::sum::<f64>(x[next_start..next_end].iter()) / count;
+            avg_y = Iterator::sum::<f64>(y[next_start..next_end].iter()) / count;
+        }
+
+        // Pick the point in this bucket that forms the largest triangle.
+        let mut best_index = bucket_start;
+        let mut best_area = -1.0;
+        for index in bucket_start..bucket_end {
+            let area = ((x[prev_index] - avg_x) * (y[index] - y[prev_index]) - (x[prev_index] - x[index]) * (avg_y - y[prev_index])).abs();
+            if area > best_area {
+                best_area = area;
+                best_index = index;
+            }
+        }
+
+        result.push(best_index);
+        prev_index = best_index;
+    }
+
+    result.push(num_points - 1);
+    result
+}
+
+/// Distance of the point from the line through the start and end points, treating latitude/longitude as planar
+/// with longitude scaled by the cosine of the latitude. Good enough for ranking points, not for measuring.
+fn perpendicular_distance(lats: &[f64], lons: &[f64], index: usize, start_index: usize, end_index: usize) -> f64 {
+    let lon_scale = lats[start_index].to_radians().cos();
+    let x = lons[index] * lon_scale;
+    let y = lats[index];
+    let x1 = lons[start_index] * lon_scale;
+    let y1 = lats[start_index];
+    let x2 = lons[end_index] * lon_scale;
+    let y2 = lats[end_index];
+
+    let dx = x2 - x1;
+    let dy = y2 - y1;
+    let length = (dx * dx + dy * dy).sqrt();
+    if length == 0.0 {
+        return ((x - x1) * (x - x1) + (y - y1) * (y - y1)).sqrt();
+    }
+    ((dy * x - dx * y + x2 * y1 - y2 * x1) / length).abs()
+}
+
+/// Returns the point between the start and end that is farthest from the line joining them, along with its distance.
+fn farthest_point(lats: &[f64], lons: &[f64], start_index: usize, end_index: usize) -> Option<(usize, f64)> {
+    let mut result = None;
+    let mut max_distance = -1.0;
+
+    for index in start_index + 1..end_index {
+        let distance = perpendicular_distance(lats, lons, index, start_index, end_index);
+        if distance > max_distance {
+            max_distance = distance;
+            result = Some((index, distance));
+        }
+    }
+    result
+}
+
+/// Douglas–Peucker, for tracks. Rather than using a distance tolerance, the segment with the farthest point
+/// is split repeatedly until the maximum number of points has been reached, so the most significant turns are kept.
+pub fn douglas_peucker(lats: &[f64], lons: &[f64], max_points: usize) -> Vec<usize> {
+    let num_points = usize::min(lats.len(), lons.len());
+
+    if max_points == 0 || num_points <= max_points {
+        return (0..num_points).collect();
+    }
+    if max_points < 3 {
+        return (0..max_points).map(|index| index * (num_points - 1)).collect();
+    }
+
+    let mut result = vec![0, num_points - 1];
+    let mut segments = Vec::new(); // Segments that can still be split, as (start_index, end_index, farthest_index, distance)
+
+    if let Some((index, distance)) = farthest_point(lats, lons, 0, num_points - 1) {
+        segments.push((0, num_points - 1, index, distance));
+    }
+
+    while result.len() < max_points && !segments.is_empty() {
+        let mut best_segment = 0;
+        for (segment_index, segment) in segments.iter().enumerate() {
+            if segment.3 > segments[best_segment].3 {
+                best_segment = segment_index;
+            }
+        }
+
+        let (start_index, end_index, split_index, _) = segments.swap_remove(best_segment);
+        result.push(split_index);
+
+        if let Some((index, distance)) = farthest_point(lats, lons, start_index, split_index) {
+            segments.push((start_index, split_index, index, distance));
+        }
+        if let Some((index, distance)) = farthest_point(lats, lons, split_index, end_index) {
+            segments.push((split_index, end_index, index, distance));
+        }
+    }
+
+    result.sort_unstable();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::downsample;
+
+    fn is_increasing(indices: &[usize]) -> bool {
+        indices.windows(2).all(|pair| pair[0] < pair[1])
+    }
+
+    #[test]
+    fn test_lttb_short_series() {
+        let x = vec![0.0, 1.0, 2.0];
+        let y = vec![5.0, 6.0, 7.0];
+
+        // Nothing to do if the series already fits, or if there is no limit.
+        assert_eq!(downsample::lttb(&x, &y, 10), vec![0, 1, 2]);
+        assert_eq!(downsample::lttb(&x, &y, 0), vec![0, 1, 2]);
+        assert_eq!(downsample::lttb(&x, &y, 2), vec![0, 2]);
+    }
+
+    #[test]
+    fn test_lttb_keeps_peaks() {
+        let x: Vec<f64> = (0..1000).map(|index| index as f64).collect();
+        let mut y = vec![100.0; 1000];
+        y[437] = 500.0;
+        y[731] = -200.0;
+
+        let indices = downsample::lttb(&x, &y, 50);
+        assert_eq!(indices.len(), 50);
+        assert_eq!(indices[0], 0);
+        assert_eq!(indices[49], 999);
+        assert!(is_increasing(&indices));
+        assert!(indices.contains(&437));
+        assert!(indices.contains(&731));
+    }
+
+    #[test]
+    fn test_douglas_peucker_keeps_corners() {
+        // North for 100 points, then east for 100 points, then north again.
+        let mut lats = Vec::new();
+        let mut lons = Vec::new();
+        for index in 0..100 {
+            lats.push(45.0 + index as f64 * 0.0001);
+            lons.push(-75.0);
+        }
+        for index in 0..100 {
+            lats.push(45.0099);
+            lons.push(-75.0 + (index + 1) as f64 * 0.0001);
+        }
+        for index in 0..100 {
+            lats.push(45.0099 + (index + 1) as f64 * 0.0001);
+            lons.push(-74.99);
+        }
+
+        assert_eq!(downsample::douglas_peucker(&lats, &lons, 4), vec![0, 99, 199, 299]);
+
+        let indices = downsample::douglas_peucker(&lats, &lons, 20);
+        assert_eq!(indices.len(), 20);
+        assert!(is_increasing(&indices));
+        assert!(indices.contains(&99));
+        assert!(indices.contains(&199));
+    }
+
+    #[test]
+    fn test_douglas_peucker_short_track() {
+        let lats = vec![45.0, 45.001];
+        let lons = vec![-75.0, -75.0];
+        assert_eq!(downsample::douglas_peucker(&lats, &lons, 10), vec![0, 1]);
+        assert_eq!(downsample::douglas_peucker(&lats, &lons, 0), vec![0, 1]);
+    }
+}
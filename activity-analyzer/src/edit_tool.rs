@@ -0,0 +1,239 @@
+// Copyright (c) 2021 Michael J. Simms. All rights reserved.
+
+use crate::analyzer_context::AnalyzerContext;
+use crate::event::Event;
+
+pub struct EditTool {
+}
+
+impl EditTool {
+    pub fn new() -> Self {
+        EditTool{}
+    }
+
+    /// Copies the readings that pass the filter to a new series.
+    fn copy_readings(times: &[u64], readings: &[f64], keep: &dyn Fn(u64) -> bool, mut append: impl FnMut(u64, f64)) {
+        for (ts, value) in times.iter().zip(readings.iter()) {
+            if keep(*ts) {
+                append(*ts, *value);
+            }
+        }
+    }
+
+    /// Builds a new context from the parts of the activity that pass the filter, and analyzes it.
+    /// If a range was removed, the timer is treated as having been stopped for its duration so that
+    /// it doesn't count towards the moving time, and the distance across it isn't counted.
+    fn copy(&self, context: &AnalyzerContext, keep: &dyn Fn(u64) -> bool, removed_range: Option<(u64, u64)>) -> AnalyzerContext {
+        let mut result = AnalyzerContext::new();
+        let loc_data = &context.location_analyzer;
+
+        result.name = context.name.clone();
+        result.location_analyzer.set_activity_type(loc_data.activity_type.clone());
+        result.location_analyzer.is_route = loc_data.is_route;
+        result.swim_analyzer.set_pool_length(context.swim_analyzer.pool_length);
+        result.swim_analyzer.set_pool_length_units(context.swim_analyzer.pool_length_units);
+
+        let mut events: Vec<Event> = context.events.iter().filter(|event| keep(event.timestamp_ms())).cloned().collect();
+        if let Some((start_time_ms, end_time_ms)) = removed_range {
+            events.push(Event::TimerStop{ timestamp_ms: start_time_ms });
+            events.push(Event::TimerStart{ timestamp_ms: end_time_ms });
+        }
+        events.sort_by_key(|event| event.timestamp_ms());
+
+        // Replay the locations (or, for indoor activities, the distances reported by the device) along with the events,
+        // so that pauses fall between the right points.
+        let (indoor_distance_times, indoor_distances) = loc_data.get_indoor_distances();
+        let mut event_iter = events.iter().peekable();
+        for (point_index, ts) in loc_data.times.iter().enumerate() {
+            if !keep(*ts) {
+                continue;
+            }
+            while let Some(event) = event_iter.next_if(|event| event.timestamp_ms() <= *ts) {
+                result.replay_event(event);
+            }
+            result.location_analyzer.append_location(*ts, loc_data.latitude_readings[point_index], loc_data.longitude_readings[point_index], loc_data.get_altitude(point_index));
+            result.location_analyzer.update_speeds();
+        }
+
+        // Distances are totals, so only count what was covered between readings that were both kept.
+        let mut distance = 0.0;
+        let mut last_total_distance: Option<f64> = None;
+        for (ts, total_distance) in indoor_distance_times.iter().zip(indoor_distances.iter()) {
+            if !keep(*ts) {
+                last_total_distance = None;
+                continue;
+            }
+            while let Some(event) = event_iter.next_if(|event| event.timestamp_ms() <= *ts) {
+                result.replay_event(event);
+            }
+            if let Some(last_total_distance) = last_total_distance {
+                distance += f64::max(total_distance - last_total_distance, 0.0);
+            }
+            last_total_distance = Some(*total_distance);
+            result.location_analyzer.append_distance(*ts, distance);
+            result.location_analyzer.update_speeds();
+        }
+        for event in event_iter {
+            result.replay_event(event);
+        }
+
+        for poi in loc_data.points_of_interest.iter() {
+            result.location_analyzer.append_point_of_interest(poi.name.clone(), poi.latitude, poi.longitude);
+        }
+
+        EditTool::copy_readings(&context.hr_analyzer.time_readings, &context.hr_analyzer.readings, keep, |ts, value| result.hr_analyzer.append_sensor_value(ts, value));
+        EditTool::copy_readings(&context.cadence_analyzer.time_readings, &context.cadence_analyzer.readings, keep, |ts, value| result.cadence_analyzer.append_sensor_value(ts, value));
+        EditTool::copy_readings(&context.power_analyzer.time_readings, &context.power_analyzer.readings, keep, |ts, value| result.power_analyzer.append_sensor_value(ts, value));
+        EditTool::copy_readings(&context.temperature_analyzer.time_readings, &context.temperature_analyzer.readings, keep, |ts, value| result.temperature_analyzer.append_sensor_value(ts, value));
+
+        let power_data = &context.power_analyzer;
+        for (index, ts) in power_data.cycling_dynamics_times.iter().enumerate() {
+            if keep(*ts) {
+                result.power_analyzer.append_cycling_dynamics(*ts, power_data.left_right_balance_readings[index], power_data.left_torque_effectiveness_readings[index],
+                    power_data.right_torque_effectiveness_readings[index], power_data.left_pedal_smoothness_readings[index], power_data.right_pedal_smoothness_readings[index]);
+            }
+        }
+
+        let dynamics = &context.running_dynamics_analyzer;
+        let result_dynamics = &mut result.running_dynamics_analyzer;
+        EditTool::copy_readings(&dynamics.ground_contact_time_times, &dynamics.ground_contact_time_readings, keep, |ts, value| result_dynamics.append_ground_contact_time(ts, value));
+        EditTool::copy_readings(&dynamics.vertical_oscillation_times, &dynamics.vertical_oscillation_readings, keep, |ts, value| result_dynamics.append_vertical_oscillation(ts, value));
+        EditTool::copy_readings(&dynamics.vertical_ratio_times, &dynamics.vertical_ratio_readings, keep, |ts, value| result_dynamics.append_vertical_ratio(ts, value));
+        EditTool::copy_readings(&dynamics.step_length_times, &dynamics.step_length_readings, keep, |ts, value| result_dynamics.append_step_length(ts, value));
+        EditTool::copy_readings(&dynamics.ground_contact_time_balance_times, &dynamics.ground_contact_time_balance_readings, keep, |ts, value| result_dynamics.append_ground_contact_time_balance(ts, value));
+
+        for (ts, strokes) in context.swim_analyzer.time_readings.iter().zip(context.swim_analyzer.strokes.iter()) {
+            if keep(*ts) {
+                result.swim_analyzer.append_sensor_value(*ts, *strokes);
+            }
+        }
+
+        result.analyze();
+        result
+    }
+
+    /// Keeps only the part of the activity between the two timestamps (inclusive).
+    pub fn crop(&self, context: &AnalyzerContext, start_time_ms: u64, end_time_ms: u64) -> AnalyzerContext {
+        self.copy(context, &|ts| ts >= start_time_ms && ts <= end_time_ms, None)
+    }
+
+    /// Removes the part of the activity from the start time up to (but not including) the end time, such as when the watch was left running in the car.
+    pub fn remove_range(&self, context: &AnalyzerContext, start_time_ms: u64, end_time_ms: u64) -> AnalyzerContext {
+        self.copy(context, &|ts| ts < start_time_ms || ts >= end_time_ms, Some((start_time_ms, end_time_ms)))
+    }
+
+    /// Splits the activity in two. The second activity starts with the first reading at or after the split time.
+    pub fn split(&self, context: &AnalyzerContext, split_time_ms: u64) -> (AnalyzerContext, AnalyzerContext) {
+        (self.copy(context, &|ts| ts < split_time_ms, None), self.copy(context, &|ts| ts >= split_time_ms, None))
+    }
+}
+
+impl Default for EditTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analyzer_context::AnalyzerContext;
+    use crate::edit_tool::EditTool;
+    use crate::event::Event;
+
+    const START_TIME_MS: u64 = 1600000000000;
+
+    /// A one minute ride with a location and a heart rate reading every second.
+    fn make_outdoor_context() -> AnalyzerContext {
+        let mut context = AnalyzerContext::new();
+
+        for i in 0..60 {
+            let time_ms = START_TIME_MS + i * 1000;
+            context.location_analyzer.append_location(time_ms, 45.0 + i as f64 * 0.0001, -75.0, None);
+            context.location_analyzer.update_speeds();
+            context.hr_analyzer.append_sensor_value(time_ms, 120.0 + i as f64);
+        }
+        context.analyze();
+        context
+    }
+
+    /// A one minute indoor ride, with the trainer reporting ten meters every second.
+    fn make_indoor_context() -> AnalyzerContext {
+        let mut context = AnalyzerContext::new();
+
+        for i in 0..60 {
+            let time_ms = START_TIME_MS + i * 1000;
+            context.location_analyzer.append_distance(time_ms, i as f64 * 10.0);
+            context.location_analyzer.update_speeds();
+            context.power_analyzer.append_sensor_value(time_ms, 200.0);
+        }
+        context.analyze();
+        context
+    }
+
+    #[test]
+    fn test_crop() {
+        let context = make_outdoor_context();
+        let cropped = EditTool::new().crop(&context, START_TIME_MS + 10000, START_TIME_MS + 20000);
+
+        // Both ends are kept.
+        let loc_data = &cropped.location_analyzer;
+        assert_eq!(loc_data.times.len(), 11);
+        assert_eq!(loc_data.times.first(), Some(&(START_TIME_MS + 10000)));
+        assert_eq!(loc_data.times.last(), Some(&(START_TIME_MS + 20000)));
+        assert_eq!(cropped.hr_analyzer.time_readings.len(), 11);
+        assert!(cropped.events.is_empty());
+    }
+
+    #[test]
+    fn test_remove_range() {
+        let context = make_outdoor_context();
+        let edited = EditTool::new().remove_range(&context, START_TIME_MS + 10000, START_TIME_MS + 20000);
+
+        // The start is removed, the end is kept.
+        let loc_data = &edited.location_analyzer;
+        assert_eq!(loc_data.times.len(), 50);
+        assert!(!loc_data.times.contains(&(START_TIME_MS + 10000)));
+        assert!(loc_data.times.contains(&(START_TIME_MS + 20000)));
+        assert!(!edited.hr_analyzer.time_readings.contains(&(START_TIME_MS + 19000)));
+
+        // The gap is a pause, so it doesn't count towards the moving time or the distance.
+        assert_eq!(edited.events.len(), 2);
+        assert!(matches!(edited.events[0], Event::TimerStop{ timestamp_ms } if timestamp_ms == START_TIME_MS + 10000));
+        assert!(matches!(edited.events[1], Event::TimerStart{ timestamp_ms } if timestamp_ms == START_TIME_MS + 20000));
+        assert_eq!(loc_data.pause_intervals.len(), 1);
+        assert_eq!(loc_data.get_paused_ms_between(START_TIME_MS, START_TIME_MS + 59000), 10000);
+        assert!(loc_data.total_distance < context.location_analyzer.total_distance * 0.9);
+    }
+
+    #[test]
+    fn test_split() {
+        let context = make_outdoor_context();
+        let (first, second) = EditTool::new().split(&context, START_TIME_MS + 30000);
+
+        // The split time starts the second activity.
+        assert_eq!(first.location_analyzer.times.len(), 30);
+        assert_eq!(first.location_analyzer.times.last(), Some(&(START_TIME_MS + 29000)));
+        assert_eq!(second.location_analyzer.times.len(), 30);
+        assert_eq!(second.location_analyzer.times.first(), Some(&(START_TIME_MS + 30000)));
+        assert_eq!(second.hr_analyzer.time_readings.first(), Some(&(START_TIME_MS + 30000)));
+        assert_eq!(second.start_time_ms(), START_TIME_MS + 30000);
+    }
+
+    #[test]
+    fn test_indoor_distances() {
+        let context = make_indoor_context();
+        let edit_tool = EditTool::new();
+
+        // The cropped ride starts from zero.
+        let cropped = edit_tool.crop(&context, START_TIME_MS + 10000, START_TIME_MS + 20000);
+        assert_eq!(cropped.location_analyzer.get_distance_at(START_TIME_MS + 20000), 100.0);
+
+        // The distance across the removed range isn't counted.
+        let edited = edit_tool.remove_range(&context, START_TIME_MS + 10000, START_TIME_MS + 20000);
+        assert_eq!(edited.location_analyzer.get_distance_at(START_TIME_MS + 59000), 480.0);
+
+        let (first, second) = edit_tool.split(&context, START_TIME_MS + 30000);
+        assert_eq!(first.location_analyzer.get_distance_at(START_TIME_MS + 29000), 290.0);
+        assert_eq!(second.location_analyzer.get_distance_at(START_TIME_MS + 59000), 290.0);
+    }
+}
@@ -2,22 +2,112 @@
 
 use serde::Serialize;
 
-#[derive(Clone, Copy, Serialize)]
-pub struct Event {
-    pub timestamp_ms: u64, // Timestamp (in milliseconds) at which the event occurred.
-    pub event_type: u8, // Event type, from the file.
-    pub event_data: u8, // Event-type dependent data.
+/// Something that happened during the activity, as read from the activity file.
+/// Serialized with a "type" field that identifies the variant, followed by the variant's fields.
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    TimerStart {
+        timestamp_ms: u64, // Timestamp (in milliseconds) at which the event occurred.
+    },
+    TimerStop {
+        timestamp_ms: u64,
+    },
+    Lap {
+        timestamp_ms: u64, // End of the lap
+    },
+    GearChange {
+        timestamp_ms: u64,
+        front_gear_num: u8,
+        front_gear_teeth: u8,
+        rear_gear_num: u8,
+        rear_gear_teeth: u8,
+    },
+    RadarThreat {
+        timestamp_ms: u64,
+        threat_level: u8,
+        threat_count: u8,
+        approach_speed: Option<f64>, // Meters/second
+    },
+    BatteryLow {
+        timestamp_ms: u64,
+    },
+    WorkoutStep {
+        timestamp_ms: u64,
+        step_index: u32,
+    },
+    Marker {
+        timestamp_ms: u64,
+        name: String,
+    },
 }
 
 impl Event {
-    /// Creates a new [`Event`].
-    pub fn new() -> Self {
-        Event{ timestamp_ms: 0, event_type: 0, event_data: 0 }
+    /// Returns the time (in milliseconds) at which the event occurred.
+    pub fn timestamp_ms(&self) -> u64 {
+        match self {
+            Event::TimerStart { timestamp_ms } => *timestamp_ms,
+            Event::TimerStop { timestamp_ms } => *timestamp_ms,
+            Event::Lap { timestamp_ms } => *timestamp_ms,
+            Event::GearChange { timestamp_ms, .. } => *timestamp_ms,
+            Event::RadarThreat { timestamp_ms, .. } => *timestamp_ms,
+            Event::BatteryLow { timestamp_ms } => *timestamp_ms,
+            Event::WorkoutStep { timestamp_ms, .. } => *timestamp_ms,
+            Event::Marker { timestamp_ms, .. } => *timestamp_ms,
+        }
+    }
+
+    /// Moves the event to the given time (in milliseconds), such as when correcting for a device's clock.
+    pub fn set_timestamp_ms(&mut self, new_timestamp_ms: u64) {
+        match self {
+            Event::TimerStart { timestamp_ms } => *timestamp_ms = new_timestamp_ms,
+            Event::TimerStop { timestamp_ms } => *timestamp_ms = new_timestamp_ms,
+            Event::Lap { timestamp_ms } => *timestamp_ms = new_timestamp_ms,
+            Event::GearChange { timestamp_ms, .. } => *timestamp_ms = new_timestamp_ms,
+            Event::RadarThreat { timestamp_ms, .. } => *timestamp_ms = new_timestamp_ms,
+            Event::BatteryLow { timestamp_ms } => *timestamp_ms = new_timestamp_ms,
+            Event::WorkoutStep { timestamp_ms, .. } => *timestamp_ms = new_timestamp_ms,
+            Event::Marker { timestamp_ms, .. } => *timestamp_ms = new_timestamp_ms,
+        }
     }
 }
 
-impl Default for Event {
-    fn default() -> Self {
-        Self::new()
+#[cfg(test)]
+mod tests {
+    use crate::event::Event;
+    use serde_json::json;
+
+    const START_TIME_MS: u64 = 1600000000000;
+
+    #[test]
+    fn test_serialize() {
+        let cases = [
+            (Event::TimerStart{ timestamp_ms: START_TIME_MS }, json!({ "type": "timer_start", "timestamp_ms": START_TIME_MS })),
+            (Event::TimerStop{ timestamp_ms: START_TIME_MS }, json!({ "type": "timer_stop", "timestamp_ms": START_TIME_MS })),
+            (Event::Lap{ timestamp_ms: START_TIME_MS }, json!({ "type": "lap", "timestamp_ms": START_TIME_MS })),
+            (Event::GearChange{ timestamp_ms: START_TIME_MS, front_gear_num: 2, front_gear_teeth: 50, rear_gear_num: 7, rear_gear_teeth: 17 },
+                json!({ "type": "gear_change", "timestamp_ms": START_TIME_MS, "front_gear_num": 2, "front_gear_teeth": 50, "rear_gear_num": 7, "rear_gear_teeth": 17 })),
+            (Event::RadarThreat{ timestamp_ms: START_TIME_MS, threat_level: 2, threat_count: 1, approach_speed: Some(12.5) },
+                json!({ "type": "radar_threat", "timestamp_ms": START_TIME_MS, "threat_level": 2, "threat_count": 1, "approach_speed": 12.5 })),
+            (Event::RadarThreat{ timestamp_ms: START_TIME_MS, threat_level: 1, threat_count: 0, approach_speed: None },
+                json!({ "type": "radar_threat", "timestamp_ms": START_TIME_MS, "threat_level": 1, "threat_count": 0, "approach_speed": null })),
+            (Event::BatteryLow{ timestamp_ms: START_TIME_MS }, json!({ "type": "battery_low", "timestamp_ms": START_TIME_MS })),
+            (Event::WorkoutStep{ timestamp_ms: START_TIME_MS, step_index: 3 }, json!({ "type": "workout_step", "timestamp_ms": START_TIME_MS, "step_index": 3 })),
+            (Event::Marker{ timestamp_ms: START_TIME_MS, name: "Summit".to_string() }, json!({ "type": "marker", "timestamp_ms": START_TIME_MS, "name": "Summit" })),
+        ];
+
+        for (event, expected) in cases.iter() {
+            assert_eq!(serde_json::to_value(event).unwrap(), *expected);
+        }
+    }
+
+    #[test]
+    fn test_set_timestamp() {
+        let mut event = Event::GearChange{ timestamp_ms: START_TIME_MS, front_gear_num: 2, front_gear_teeth: 50, rear_gear_num: 7, rear_gear_teeth: 17 };
+        assert_eq!(event.timestamp_ms(), START_TIME_MS);
+
+        event.set_timestamp_ms(START_TIME_MS + 5000);
+        assert_eq!(event.timestamp_ms(), START_TIME_MS + 5000);
+        assert_eq!(serde_json::to_value(&event).unwrap()["rear_gear_teeth"], 17);
     }
 }
@@ -2,247 +2,758 @@
 
 use crate::analyzer_context::AnalyzerContext;
 use crate::gpx_writer::GpxWriter;
-use crate::tcx_writer::TcxWriter;
+use crate::tcx_writer::{LapExtension, TcxWriter};
+use crate::fit_writer::{FitRecord, FitSummary, FitWriter};
+use crate::csv_writer::CsvWriter;
+use crate::kml_writer::KmlWriter;
+use crate::sample_table::{Column, SampleTable};
+use crate::utils::format_iso_timestamp;
+
+const MAX_TRACK_GAP_MS: u64 = 60000; // Locations further apart than this are treated as a break in the track
+
+/// How timestamps are written in formats that give us a choice, such as CSV.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFormat {
+    Epoch, // Milliseconds since the epoch
+    Iso8601,
+}
+
+/// The columns that can be included in a CSV export, in the order they are written.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CsvField {
+    Latitude,
+    Longitude,
+    Altitude,
+    Distance,
+    Speed,
+    Gradient,
+    HeartRate,
+    Cadence,
+    Power,
+    Temperature,
+    LeftRightBalance,
+    LeftTorqueEffectiveness,
+    RightTorqueEffectiveness,
+    LeftPedalSmoothness,
+    RightPedalSmoothness,
+}
+
+pub const ALL_CSV_FIELDS: [CsvField; 15] = [ CsvField::Latitude, CsvField::Longitude, CsvField::Altitude, CsvField::Distance, CsvField::Speed, CsvField::Gradient,
+    CsvField::HeartRate, CsvField::Cadence, CsvField::Power, CsvField::Temperature, CsvField::LeftRightBalance, CsvField::LeftTorqueEffectiveness,
+    CsvField::RightTorqueEffectiveness, CsvField::LeftPedalSmoothness, CsvField::RightPedalSmoothness ];
+
+impl CsvField {
+    /// The name used to select the field, e.g. in the list passed to set_export_options.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CsvField::Latitude => "latitude",
+            CsvField::Longitude => "longitude",
+            CsvField::Altitude => "altitude",
+            CsvField::Distance => "distance",
+            CsvField::Speed => "speed",
+            CsvField::Gradient => "gradient",
+            CsvField::HeartRate => "heart_rate",
+            CsvField::Cadence => "cadence",
+            CsvField::Power => "power",
+            CsvField::Temperature => "temperature",
+            CsvField::LeftRightBalance => "left_right_balance",
+            CsvField::LeftTorqueEffectiveness => "left_torque_effectiveness",
+            CsvField::RightTorqueEffectiveness => "right_torque_effectiveness",
+            CsvField::LeftPedalSmoothness => "left_pedal_smoothness",
+            CsvField::RightPedalSmoothness => "right_pedal_smoothness",
+        }
+    }
+
+    /// Returns the field with the given name, or None if there isn't one.
+    pub fn from_name(name: &str) -> Option<CsvField> {
+        ALL_CSV_FIELDS.iter().find(|field| field.name() == name).copied()
+    }
+
+    /// The column header, including the units.
+    fn header(&self) -> &'static str {
+        match self {
+            CsvField::Latitude => "Latitude",
+            CsvField::Longitude => "Longitude",
+            CsvField::Altitude => "Altitude (m)",
+            CsvField::Distance => "Distance (m)",
+            CsvField::Speed => "Speed (m/s)",
+            CsvField::Gradient => "Gradient (%)",
+            CsvField::HeartRate => "Heart Rate (bpm)",
+            CsvField::Cadence => "Cadence (rpm)",
+            CsvField::Power => "Power (W)",
+            CsvField::Temperature => "Temperature (C)",
+            CsvField::LeftRightBalance => "Left/Right Balance (% Right)",
+            CsvField::LeftTorqueEffectiveness => "Left Torque Effectiveness (%)",
+            CsvField::RightTorqueEffectiveness => "Right Torque Effectiveness (%)",
+            CsvField::LeftPedalSmoothness => "Left Pedal Smoothness (%)",
+            CsvField::RightPedalSmoothness => "Right Pedal Smoothness (%)",
+        }
+    }
+
+    /// The sample table column holding the field, or None if it has to be computed.
+    fn column(&self) -> Option<Column> {
+        match self {
+            CsvField::Latitude => Some(Column::Latitude),
+            CsvField::Longitude => Some(Column::Longitude),
+            CsvField::Altitude => Some(Column::Altitude),
+            CsvField::Distance => Some(Column::Distance),
+            CsvField::Speed => Some(Column::Speed),
+            CsvField::Gradient => None,
+            CsvField::HeartRate => Some(Column::HeartRate),
+            CsvField::Cadence => Some(Column::Cadence),
+            CsvField::Power => Some(Column::Power),
+            CsvField::Temperature => Some(Column::Temperature),
+            CsvField::LeftRightBalance => Some(Column::LeftRightBalance),
+            CsvField::LeftTorqueEffectiveness => Some(Column::LeftTorqueEffectiveness),
+            CsvField::RightTorqueEffectiveness => Some(Column::RightTorqueEffectiveness),
+            CsvField::LeftPedalSmoothness => Some(Column::LeftPedalSmoothness),
+            CsvField::RightPedalSmoothness => Some(Column::RightPedalSmoothness),
+        }
+    }
+}
+
+/// Settings that control the exported file.
+#[derive(Clone)]
+pub struct ExportOptions {
+    pub timestamp_format: TimestampFormat,
+    pub csv_fields: Vec<CsvField>, // Columns to include in CSV exports, empty means all of them
+    pub clean_track: bool, // If true, GPS outliers are dropped and the elevation is smoothed in GPX exports
+}
+
+impl ExportOptions {
+    /// Creates a new [`ExportOptions`] with the default settings.
+    pub const fn new() -> Self {
+        ExportOptions{ timestamp_format: TimestampFormat::Epoch, csv_fields: Vec::new(), clean_track: false }
+    }
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 pub struct Exporter {
+    options: ExportOptions,
 }
 
 impl Exporter {
     /// Creates a new [`Exporter`].
     pub fn new() -> Self {
-        Exporter{}
+        Exporter{ options: ExportOptions::new() }
+    }
+
+    /// Creates a new [`Exporter`] with the given settings.
+    pub fn with_options(options: ExportOptions) -> Self {
+        Exporter{ options }
+    }
+
+    /// Returns true if the timestamp falls within the split. Zero offsets mean the split is open ended.
+    fn use_data_point(ts: u64, start_time_ms: u64, split_start_offset_ms: u64, split_end_offset_ms: u64) -> bool {
+        if split_start_offset_ms == 0 && split_end_offset_ms == 0 {
+            return true;
+        }
+        if split_end_offset_ms == 0 {
+            return ts >= start_time_ms + split_start_offset_ms;
+        }
+        ts >= start_time_ms + split_start_offset_ms && ts < start_time_ms + split_end_offset_ms
+    }
+
+    /// The activity starts with the first location, or with the first reading of any kind for indoor activities.
+    fn activity_start_time(context: &AnalyzerContext, table: &SampleTable) -> u64 {
+        let start_time_ms = context.location_analyzer.start_time_ms;
+
+        if start_time_ms == 0 && !table.is_empty() {
+            return table.times[0];
+        }
+        start_time_ms
+    }
+
+    /// Returns the initial bearing (in degrees clockwise from north) from the first location to the second.
+    fn compute_course(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+        let lat1 = lat1.to_radians();
+        let lat2 = lat2.to_radians();
+        let delta_lon = (lon2 - lon1).to_radians();
+
+        let y = delta_lon.sin() * lat2.cos();
+        let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * delta_lon.cos();
+        (y.atan2(x).to_degrees() + 360.0) % 360.0
     }
 
+    /// Writes the sensor readings of the GPX track point at the given time.
+    fn write_gpx_extensions(writer: &mut GpxWriter, temperature: Option<f64>, heart_rate: Option<f64>, cadence: Option<f64>, speed: Option<f64>, course: Option<f64>, power: Option<f64>) {
+        let has_tpx = temperature.is_some() || heart_rate.is_some() || cadence.is_some() || speed.is_some() || course.is_some();
+
+        if has_tpx || power.is_some() {
+            writer.start_extensions();
+
+            // The elements of the extension have to be written in the order required by the schema.
+            if has_tpx {
+                writer.start_track_point_extensions();
+                if let Some(temperature) = temperature {
+                    writer.store_temperature(temperature);
+                }
+                if let Some(heart_rate) = heart_rate {
+                    writer.store_heart_rate_bpm(heart_rate as u8);
+                }
+                if let Some(cadence) = cadence {
+                    writer.store_cadence_rpm(cadence as u8);
+                }
+                if let Some(speed) = speed {
+                    writer.store_speed(speed);
+                }
+                if let Some(course) = course {
+                    writer.store_course(course);
+                }
+                writer.end_track_point_extensions();
+            }
+
+            // Garmin's TrackPointExtension doesn't have power, so it goes alongside it, which is where most apps look for it.
+            if let Some(power) = power {
+                writer.store_power_in_watts(power as u32);
+            }
+            writer.end_extensions();
+        }
+    }
+
+    /// GPX requires a position for every track point, so activities without location data, such as indoor rides,
+    /// can't be written as GPX. An empty string is returned for those, and they should be exported as TCX or FIT instead.
     fn export_gpx(&self, context: &AnalyzerContext, split_start_offset_ms: u64, split_end_offset_ms: u64) -> String {
         let mut writer = GpxWriter::new();
         let loc_data = &context.location_analyzer;
+        let table = context.sample_table();
+
+        if loc_data.times.is_empty() {
+            return String::new();
+        }
 
-        let num_hr_readings = context.hr_analyzer.time_readings.len();
-        let num_cad_readings = context.cadence_analyzer.time_readings.len();
-        let num_power_readings = context.power_analyzer.time_readings.len();
-        let mut hr_index = 0;
-        let mut cad_index = 0;
-        let mut power_index = 0;
+        // Optionally clean up the track, the same way it would be before being displayed.
+        let outliers = if self.options.clean_track { loc_data.find_location_outliers() } else { vec![false; loc_data.times.len()] };
+        let altitudes = if self.options.clean_track { loc_data.get_smoothed_altitude_readings() } else { loc_data.get_altitude_readings().cloned() };
+
+        let point_indices: Vec<usize> = (0..loc_data.times.len())
+            .filter(|point_index| !outliers[*point_index])
+            .filter(|point_index| Exporter::use_data_point(loc_data.times[*point_index], loc_data.start_time_ms, split_start_offset_ms, split_end_offset_ms))
+            .collect();
+
+        let mut bounds = None;
+        if !point_indices.is_empty() {
+            let lats = point_indices.iter().map(|point_index| loc_data.latitude_readings[*point_index]);
+            let lons = point_indices.iter().map(|point_index| loc_data.longitude_readings[*point_index]);
+            let (min_lat, max_lat) = lats.fold((f64::MAX, f64::MIN), |(min, max), lat| (f64::min(min, lat), f64::max(max, lat)));
+            let (min_lon, max_lon) = lons.fold((f64::MAX, f64::MIN), |(min, max), lon| (f64::min(min, lon), f64::max(max, lon)));
+            bounds = Some((min_lat, min_lon, max_lat, max_lon));
+        }
 
         writer.open();
-        writer.write_metadata(loc_data.start_time_ms);
+        writer.write_metadata(loc_data.start_time_ms, bounds);
         writer.start_track();
         writer.write_name(&context.name);
         writer.write_type(&loc_data.activity_type);
         writer.start_track_segment();
 
-        let num_points = loc_data.latitude_readings.len();
-        for point_index in 0..num_points - 1 {
+        let mut prev_index: Option<usize> = None;
+        for point_index in point_indices {
             let ts = loc_data.times[point_index];
-            let use_data_point;
+            let lat = loc_data.latitude_readings[point_index];
+            let lon = loc_data.longitude_readings[point_index];
 
-            if split_start_offset_ms == 0 && split_end_offset_ms == 0 {
-                use_data_point = true;
-            }
-            else if split_end_offset_ms == 0 {
-                use_data_point = ts >= loc_data.start_time_ms + split_start_offset_ms;
-            }
-            else {
-                use_data_point = ts >= loc_data.start_time_ms + split_start_offset_ms && ts < loc_data.start_time_ms + split_end_offset_ms;
-            }
+            writer.start_track_point(lat, lon, altitudes.as_ref().map(|altitudes| altitudes[point_index]), ts);
 
-            if use_data_point {
+            // Only the readings taken at the same time as the location, rather than stretching readings across gaps.
+            let row = table.times.binary_search(&ts).ok();
+            let value = |column: Column| row.and_then(|row| table.get(column, row));
+            let course = prev_index.map(|prev_index| Exporter::compute_course(loc_data.latitude_readings[prev_index], loc_data.longitude_readings[prev_index], lat, lon));
 
-                writer.start_track_point(loc_data.latitude_readings[point_index], loc_data.longitude_readings[point_index], loc_data.altitude_graph[point_index], ts);
+            Exporter::write_gpx_extensions(&mut writer, value(Column::Temperature), value(Column::HeartRate), value(Column::Cadence), value(Column::Speed), course,
+                value(Column::Power));
 
-                while hr_index < num_hr_readings && context.hr_analyzer.time_readings[hr_index] < ts {
-                    hr_index += 1;
-                }
-                while cad_index < num_cad_readings && context.cadence_analyzer.time_readings[cad_index] < ts {
-                    cad_index += 1;
-                }
-                while power_index < num_power_readings && context.power_analyzer.time_readings[power_index] < ts {
-                    power_index += 1;
-                }
+            writer.end_track_point();
+            prev_index = Some(point_index);
+        }
 
-                let mut has_extensions = false;
+        writer.end_track_segment();
+        writer.end_track();
+        writer.close()
+    }
 
-                if num_hr_readings > 0 && hr_index < num_hr_readings {
-                    writer.start_extensions();
-                    writer.start_track_point_extensions();
-                    writer.store_heart_rate_bpm(context.hr_analyzer.readings[hr_index] as u8);
-                    has_extensions = true;
-                }
-                if num_cad_readings > 0 && cad_index < num_cad_readings {
-                    if !has_extensions {
-                        writer.start_extensions();
-                        writer.start_track_point_extensions();
-                        has_extensions = true;
-                    }
-                    writer.store_cadence_rpm(context.cadence_analyzer.readings[cad_index] as u8);
-                }
-                if num_power_readings > 0 && power_index < num_power_readings {
-                    if !has_extensions {
-                        writer.start_extensions();
-                        writer.start_track_point_extensions();
-                        has_extensions = true;
-                    }
-                    writer.store_power_in_watts(context.power_analyzer.readings[power_index] as u32);
-                }
+    /// TCX only knows about running and biking, everything else is "Other".
+    fn tcx_sport(activity_type: &str) -> &'static str {
+        let activity_type = activity_type.to_lowercase();
 
-                if has_extensions {
-                    writer.end_track_point_extensions();
-                    writer.end_extensions();
-                }
+        if activity_type.contains("run") {
+            return "Running";
+        }
+        if activity_type.contains("cycl") || activity_type.contains("bik") {
+            return "Biking";
+        }
+        "Other"
+    }
 
-                writer.end_track_point();
-            }
+    /// Returns the average and maximum of the column's readings in the time range (end exclusive).
+    fn column_stats(table: &SampleTable, column: Column, start_time_ms: u64, end_time_ms: u64) -> (Option<f64>, Option<f64>) {
+        let values: Vec<f64> = table.reading_rows(column).iter()
+            .filter(|row| table.times[**row] >= start_time_ms && table.times[**row] < end_time_ms)
+            .filter_map(|row| table.get(column, *row))
+            .collect();
+
+        if values.is_empty() {
+            return (None, None);
         }
+        let avg = values.iter().sum::<f64>() / values.len() as f64;
+        let max = values.iter().copied().fold(f64::MIN, f64::max);
+        (Some(avg), Some(max))
+    }
 
-        writer.end_track_segment();
-        writer.end_track();
-        writer.close()
+    /// Returns the start and (exclusive) end of each lap. The lap button marks the end of a lap, and whatever follows the last press is one more lap.
+    fn lap_bounds(context: &AnalyzerContext, table: &SampleTable) -> Vec<(u64, u64)> {
+        let mut bounds = Vec::new();
+
+        if table.is_empty() {
+            return bounds;
+        }
+
+        let mut lap_start_ms = table.times[0];
+        let end_ms = table.times[table.len() - 1] + 1;
+
+        for lap_time in context.location_analyzer.lap_times.iter() {
+            if *lap_time > lap_start_ms && *lap_time < end_ms {
+                bounds.push((lap_start_ms, *lap_time));
+                lap_start_ms = *lap_time;
+            }
+        }
+        bounds.push((lap_start_ms, end_ms));
+        bounds
     }
 
+    /// TCX (with Garmin's ActivityExtension) has nowhere to put temperature or cycling dynamics, so they are left out.
+    /// Export FIT to keep them.
     fn export_tcx(&self, context: &AnalyzerContext, split_start_offset_ms: u64, split_end_offset_ms: u64) -> String {
         let loc_data = &context.location_analyzer;
+        let table = context.sample_table();
         let mut writer = TcxWriter::new();
 
-        let lap_num = 1;
-        let num_hr_readings = context.hr_analyzer.time_readings.len();
-        let num_cad_readings = context.cadence_analyzer.time_readings.len();
-        let num_power_readings = context.power_analyzer.time_readings.len();
-        let mut hr_index = 0;
-        let mut cad_index = 0;
-        let mut power_index = 0;
+        let sport = Exporter::tcx_sport(&loc_data.activity_type);
+        let is_running = sport == "Running";
+        let has_distance = table.has_column(Column::Distance);
+
+        // The part of the activity being exported.
+        let activity_start_ms = Exporter::activity_start_time(context, &table);
+        let split_start_ms = activity_start_ms + split_start_offset_ms;
+        let split_end_ms = if split_end_offset_ms == 0 { u64::MAX } else { activity_start_ms + split_end_offset_ms };
 
         writer.open();
         writer.start_activities();
-        writer.start_activity(&loc_data.activity_type);
-        writer.write_id(loc_data.start_time_ms);
-
-        writer.start_lap(loc_data.get_lap_start_time(lap_num));
-        writer.store_lap_seconds(loc_data.get_lap_seconds(lap_num));
-        writer.store_lap_distance(loc_data.get_lap_distance(lap_num));
-        writer.store_lap_calories(loc_data.get_lap_calories(lap_num) as u16);
+        writer.start_activity(sport);
+        writer.write_id(split_start_ms);
 
-        writer.start_track();
+        for (lap_index, (lap_start_ms, lap_end_ms)) in Exporter::lap_bounds(context, &table).iter().enumerate() {
 
-        let num_locs = loc_data.latitude_readings.len();
-        for loc_index in 0..num_locs - 1 {
-            let ts = loc_data.times[loc_index];
-            let use_data_point;
+            // Only export the part of the lap that is within the split.
+            let start_ms = u64::max(*lap_start_ms, split_start_ms);
+            let end_ms = u64::min(*lap_end_ms, split_end_ms);
+            let rows: Vec<usize> = (0..table.len()).filter(|row| table.times[*row] >= start_ms && table.times[*row] < end_ms).collect();
+            if rows.is_empty() {
+                continue;
+            }
 
-            if split_start_offset_ms == 0 && split_end_offset_ms == 0 {
-                use_data_point = true;
+            let seconds = (end_ms - start_ms).saturating_sub(loc_data.get_paused_ms_between(start_ms, end_ms)) as f64 / 1000.0;
+            let distance = loc_data.get_distance_at(end_ms - 1) - loc_data.get_distance_at(start_ms);
+            let (_, max_speed) = Exporter::column_stats(&table, Column::Speed, start_ms, end_ms);
+            let (avg_hr, max_hr) = Exporter::column_stats(&table, Column::HeartRate, start_ms, end_ms);
+            let (avg_cadence, max_cadence) = Exporter::column_stats(&table, Column::Cadence, start_ms, end_ms);
+            let (avg_power, max_power) = Exporter::column_stats(&table, Column::Power, start_ms, end_ms);
+
+            // Lap summary, in the order required by the schema.
+            writer.start_lap(start_ms);
+            writer.store_lap_seconds(seconds);
+            writer.store_lap_distance(distance);
+            if let Some(max_speed) = max_speed {
+                writer.store_lap_maximum_speed(max_speed);
             }
-            else if split_end_offset_ms == 0 {
-                use_data_point = ts >= loc_data.start_time_ms + split_start_offset_ms;
+            writer.store_lap_calories(loc_data.get_lap_calories(lap_index + 1) as u16);
+            if let (Some(avg_hr), Some(max_hr)) = (avg_hr, max_hr) {
+                writer.store_lap_average_heart_rate_bpm(avg_hr.round() as u8);
+                writer.store_lap_maximum_heart_rate_bpm(max_hr.round() as u8);
             }
-            else {
-                use_data_point = ts >= loc_data.start_time_ms + split_start_offset_ms && ts < loc_data.start_time_ms + split_end_offset_ms;
+            writer.store_lap_intensity("Active");
+            if let Some(avg_cadence) = avg_cadence.filter(|_| !is_running) {
+                writer.store_lap_cadence_rpm(avg_cadence.round() as u8);
             }
+            writer.store_lap_trigger_method("Manual");
 
-            if use_data_point {
+            writer.start_track();
+
+            // One trackpoint for every time at which something was recorded, with only the readings taken at that time.
+            for row in rows {
+                let ts = table.times[row];
 
                 writer.start_trackpoint();
                 writer.store_time(ts);
-                writer.store_position(loc_data.latitude_readings[loc_index], loc_data.longitude_readings[loc_index]);
-                writer.store_altitude_meters(loc_data.altitude_graph[loc_index]);
-                writer.store_distance_meters(0.0);
-
-                while hr_index < num_hr_readings && context.hr_analyzer.time_readings[hr_index] < ts {
-                    hr_index += 1;
+                if let (Some(lat), Some(lon)) = (table.get(Column::Latitude, row), table.get(Column::Longitude, row)) {
+                    writer.store_position(lat, lon);
                 }
-                while cad_index < num_cad_readings && context.cadence_analyzer.time_readings[cad_index] < ts {
-                    cad_index += 1;
+                if let Some(altitude) = table.get(Column::Altitude, row) {
+                    writer.store_altitude_meters(altitude);
                 }
-                while power_index < num_power_readings && context.power_analyzer.time_readings[power_index] < ts {
-                    power_index += 1;
+                if has_distance {
+                    writer.store_distance_meters(loc_data.get_distance_at(ts));
                 }
 
-                if num_hr_readings > 0 && hr_index < num_hr_readings {
-                    writer.store_heart_rate_bpm(context.hr_analyzer.readings[hr_index] as u8);
+                if let Some(heart_rate) = table.get(Column::HeartRate, row) {
+                    writer.store_heart_rate_bpm(heart_rate as u8);
                 }
-                if num_cad_readings > 0 && cad_index < num_cad_readings {
-                    writer.store_cadence_rpm(context.cadence_analyzer.readings[cad_index] as u8);
+
+                // Bike cadence has its own element, run cadence goes in the extension.
+                let cadence = table.get(Column::Cadence, row);
+                let run_cadence = cadence.filter(|_| is_running);
+                if let Some(cadence) = cadence.filter(|_| !is_running) {
+                    writer.store_cadence_rpm(cadence as u8);
                 }
-                if num_power_readings > 0 && power_index < num_power_readings {
+
+                let speed = table.get(Column::Speed, row);
+                let power = table.get(Column::Power, row);
+
+                if speed.is_some() || run_cadence.is_some() || power.is_some() {
+                    writer.start_extensions();
                     writer.start_trackpoint_extensions();
-                    writer.store_power_in_watts(context.power_analyzer.readings[power_index] as u32);
+                    if let Some(speed) = speed {
+                        writer.store_speed(speed);
+                    }
+                    if let Some(run_cadence) = run_cadence {
+                        writer.store_run_cadence(run_cadence as u8);
+                    }
+                    if let Some(power) = power {
+                        writer.store_power_in_watts(power as u32);
+                    }
                     writer.end_trackpoint_extensions();
+                    writer.end_extensions();
                 }
 
                 writer.end_trackpoint();
             }
+            writer.end_track();
+
+            let lap_extension = LapExtension{
+                avg_speed: if seconds > 0.0 { Some(distance / seconds) } else { None },
+                max_bike_cadence: if is_running { None } else { max_cadence },
+                avg_run_cadence: if is_running { avg_cadence } else { None },
+                max_run_cadence: if is_running { max_cadence } else { None },
+                avg_watts: avg_power,
+                max_watts: max_power,
+            };
+            writer.store_lap_extension(&lap_extension);
+            writer.end_lap();
         }
-        writer.end_track();
-        writer.end_lap();
+
         writer.end_activity();
         writer.end_activities();
         writer.close()
     }
 
-    fn export_fit(&self, _context: &AnalyzerContext, _split_start_offset_ms: u64, _split_end_offset_ms: u64) -> String {
-        "".to_string()
+    /// FIT sport and sub sport codes for the activity type.
+    fn fit_sport(context: &AnalyzerContext) -> (u8, u8) {
+        let activity_type = context.location_analyzer.activity_type.to_lowercase();
+
+        if context.location_analyzer.is_open_water_swim() {
+            return (5, 18);
+        }
+        if activity_type.contains("run") {
+            return (1, 0);
+        }
+        if activity_type.contains("cycl") || activity_type.contains("bik") {
+            return (2, 0);
+        }
+        if activity_type.contains("swim") {
+            return (5, 0);
+        }
+        (0, 0)
     }
 
-    fn export_csv(&self, context: &AnalyzerContext, split_start_offset_ms: u64, split_end_offset_ms: u64) -> String {
-        let mut result: String = "".to_string();
+    /// Returns the summary of the given part of the activity (end exclusive).
+    fn fit_summary(context: &AnalyzerContext, table: &SampleTable, start_ms: u64, end_ms: u64) -> FitSummary {
         let loc_data = &context.location_analyzer;
+        let (avg_heart_rate, max_heart_rate) = Exporter::column_stats(table, Column::HeartRate, start_ms, end_ms);
+        let (avg_cadence, max_cadence) = Exporter::column_stats(table, Column::Cadence, start_ms, end_ms);
+        let (avg_power, max_power) = Exporter::column_stats(table, Column::Power, start_ms, end_ms);
+
+        FitSummary{
+            start_time_ms: start_ms,
+            end_time_ms: end_ms,
+            timer_time_ms: (end_ms - start_ms).saturating_sub(loc_data.get_paused_ms_between(start_ms, end_ms)),
+            distance: loc_data.get_distance_at(end_ms - 1) - loc_data.get_distance_at(start_ms),
+            avg_heart_rate,
+            max_heart_rate,
+            avg_cadence,
+            max_cadence,
+            avg_power,
+            max_power,
+        }
+    }
 
-        let num_hr_readings = context.hr_analyzer.time_readings.len();
-        let num_cad_readings = context.cadence_analyzer.time_readings.len();
-        let num_power_readings = context.power_analyzer.time_readings.len();
-        let mut hr_index = 0;
-        let mut cad_index = 0;
-        let mut power_index = 0;
+    fn export_fit(&self, context: &AnalyzerContext, split_start_offset_ms: u64, split_end_offset_ms: u64) -> Vec<u8> {
+        let table = context.sample_table();
+        let start_time_ms = Exporter::activity_start_time(context, &table);
+        let mut writer = FitWriter::new();
 
-        let num_points = loc_data.latitude_readings.len();
-        for point_index in 0..num_points - 1 {
-            let ts = loc_data.times[point_index];
-            let use_data_point;
+        writer.open(start_time_ms);
 
-            if split_start_offset_ms == 0 && split_end_offset_ms == 0 {
-                use_data_point = true;
+        let rows: Vec<usize> = (0..table.len())
+            .filter(|row| Exporter::use_data_point(table.times[*row], start_time_ms, split_start_offset_ms, split_end_offset_ms))
+            .collect();
+        if rows.is_empty() {
+            return writer.close();
+        }
+
+        let first_ms = table.times[rows[0]];
+        let end_ms = table.times[rows[rows.len() - 1]] + 1;
+
+        // The timer starts with the first record, stops and restarts around each pause, and stops after the last record.
+        let mut timer_events = vec![(first_ms, true)];
+        for pause in context.location_analyzer.pause_intervals.iter() {
+            if pause.start_time > first_ms && pause.end_time < end_ms {
+                timer_events.push((pause.start_time, false));
+                timer_events.push((pause.end_time, true));
             }
-            else if split_end_offset_ms == 0 {
-                use_data_point = ts >= loc_data.start_time_ms + split_start_offset_ms;
+        }
+        timer_events.push((end_ms, false));
+
+        let laps: Vec<FitSummary> = Exporter::lap_bounds(context, &table).iter()
+            .map(|(lap_start_ms, lap_end_ms)| (u64::max(*lap_start_ms, first_ms), u64::min(*lap_end_ms, end_ms)))
+            .filter(|(lap_start_ms, lap_end_ms)| lap_start_ms < lap_end_ms)
+            .map(|(lap_start_ms, lap_end_ms)| Exporter::fit_summary(context, &table, lap_start_ms, lap_end_ms))
+            .collect();
+
+        // One record for every time at which something was recorded, with only the readings taken at that time.
+        // Timer events and laps are written in time order along with the records.
+        let mut next_event = 0;
+        let mut next_lap = 0;
+        for row in rows {
+            let ts = table.times[row];
+
+            while next_lap < laps.len() && laps[next_lap].end_time_ms <= ts {
+                writer.write_lap(next_lap as u16, &laps[next_lap]);
+                next_lap += 1;
             }
-            else {
-                use_data_point = ts >= loc_data.start_time_ms + split_start_offset_ms && ts < loc_data.start_time_ms + split_end_offset_ms;
+            while next_event < timer_events.len() && timer_events[next_event].0 <= ts {
+                writer.write_timer_event(timer_events[next_event].0, timer_events[next_event].1);
+                next_event += 1;
             }
 
-            if use_data_point {
+            let record = FitRecord{
+                time_ms: ts,
+                latitude: table.get(Column::Latitude, row),
+                longitude: table.get(Column::Longitude, row),
+                altitude: table.get(Column::Altitude, row),
+                heart_rate: table.get(Column::HeartRate, row),
+                cadence: table.get(Column::Cadence, row),
+                power: table.get(Column::Power, row),
+                temperature: table.get(Column::Temperature, row),
+                left_right_balance: table.get(Column::LeftRightBalance, row),
+                left_torque_effectiveness: table.get(Column::LeftTorqueEffectiveness, row),
+                right_torque_effectiveness: table.get(Column::RightTorqueEffectiveness, row),
+                left_pedal_smoothness: table.get(Column::LeftPedalSmoothness, row),
+                right_pedal_smoothness: table.get(Column::RightPedalSmoothness, row),
+            };
+
+            writer.write_record(&record);
+        }
+        for (time_ms, is_start) in timer_events[next_event..].iter() {
+            writer.write_timer_event(*time_ms, *is_start);
+        }
+        for (lap_index, lap) in laps.iter().enumerate().skip(next_lap) {
+            writer.write_lap(lap_index as u16, lap);
+        }
 
-                let loc_str = format!("{},{},{},{},", ts, loc_data.latitude_readings[point_index], loc_data.longitude_readings[point_index], loc_data.altitude_graph[point_index]);
-                result.push_str(&loc_str);
+        // The session covers the whole export, and its times and distance add up to those of the laps.
+        let mut session = Exporter::fit_summary(context, &table, first_ms, end_ms);
+        session.timer_time_ms = laps.iter().map(|lap| lap.timer_time_ms).sum();
+        session.distance = laps.iter().map(|lap| lap.distance).sum();
 
-                while hr_index < num_hr_readings && context.hr_analyzer.time_readings[hr_index] < ts {
-                    hr_index += 1;
-                }
-                while cad_index < num_cad_readings && context.cadence_analyzer.time_readings[cad_index] < ts {
-                    cad_index += 1;
-                }
-                while power_index < num_power_readings && context.power_analyzer.time_readings[power_index] < ts {
-                    power_index += 1;
+        let (sport, sub_sport) = Exporter::fit_sport(context);
+        writer.write_session(&session, sport, sub_sport, laps.len() as u16);
+        writer.write_activity(end_ms, session.timer_time_ms, 1);
+        writer.close()
+    }
+
+    /// Computes the gradient (in percent) at each row that has a location, from the change in altitude and distance since the previous location.
+    fn compute_gradients(table: &SampleTable) -> Vec<Option<f64>> {
+        let mut gradients = vec![None; table.len()];
+        let mut prev_location: Option<(f64, f64)> = None;
+
+        for row in table.reading_rows(Column::Distance).iter() {
+            if let (Some(altitude), Some(distance)) = (table.get(Column::Altitude, *row), table.get(Column::Distance, *row)) {
+                if let Some((prev_altitude, prev_distance)) = prev_location {
+                    if distance > prev_distance {
+                        gradients[*row] = Some((altitude - prev_altitude) / (distance - prev_distance) * 100.0);
+                    }
                 }
+                prev_location = Some((altitude, distance));
+            }
+        }
+        gradients
+    }
+
+    /// Writes one row for every timestamp at which something was recorded. Every row has the same columns, and cells are left empty where there wasn't a reading.
+    fn export_csv(&self, context: &AnalyzerContext, split_start_offset_ms: u64, split_end_offset_ms: u64) -> String {
+        let table = context.sample_table();
+        let gradients = Exporter::compute_gradients(&table);
+        let mut writer = CsvWriter::new();
+
+        let mut fields = self.options.csv_fields.clone();
+        if fields.is_empty() {
+            fields = ALL_CSV_FIELDS.to_vec();
+        }
+
+        let start_time_ms = Exporter::activity_start_time(context, &table);
+
+        let mut header = vec!["Time".to_string()];
+        header.extend(fields.iter().map(|field| field.header().to_string()));
+        writer.write_row(&header);
+
+        for (row, ts) in table.times.iter().enumerate() {
+            if Exporter::use_data_point(*ts, start_time_ms, split_start_offset_ms, split_end_offset_ms) {
+                let mut cells = Vec::with_capacity(fields.len() + 1);
 
-                if num_hr_readings > 0 && hr_index < num_hr_readings {
-                    let hr_str = format!("{},", context.hr_analyzer.readings[hr_index] as u8);
-                    result.push_str(&hr_str);
+                match self.options.timestamp_format {
+                    TimestampFormat::Epoch => cells.push(ts.to_string()),
+                    TimestampFormat::Iso8601 => cells.push(format_iso_timestamp(*ts)),
                 }
-                if num_cad_readings > 0 && cad_index < num_cad_readings {
-                    let cad_str = format!("{},", context.cadence_analyzer.readings[hr_index] as u8);
-                    result.push_str(&cad_str);
+                for field in fields.iter() {
+                    let value = match field.column() {
+                        Some(column) => table.get(column, row),
+                        None => gradients[row],
+                    };
+                    cells.push(CsvWriter::format_value(value));
                 }
-                if num_power_readings > 0 && power_index < num_power_readings {
-                    let power_str = format!("{},", context.power_analyzer.readings[hr_index] as u8);
-                    result.push_str(&power_str);
+                writer.write_row(&cells);
+            }
+        }
+
+        writer.close()
+    }
+
+    /// Splits the locations within the split range into segments, breaking the track wherever the timer was stopped
+    /// or there is a long gap between locations. Returns the indices of the locations in each segment.
+    fn track_segments(context: &AnalyzerContext, split_start_offset_ms: u64, split_end_offset_ms: u64) -> Vec<Vec<usize>> {
+        let loc_data = &context.location_analyzer;
+        let mut segments: Vec<Vec<usize>> = Vec::new();
+        let mut segment: Vec<usize> = Vec::new();
+        let mut prev_ts = 0;
+
+        for (point_index, ts) in loc_data.times.iter().enumerate() {
+            if !Exporter::use_data_point(*ts, loc_data.start_time_ms, split_start_offset_ms, split_end_offset_ms) {
+                continue;
+            }
+
+            if !segment.is_empty() {
+                let paused = loc_data.pause_intervals.iter().any(|pause| pause.start_time < *ts && pause.end_time > prev_ts);
+                if paused || ts - prev_ts > MAX_TRACK_GAP_MS {
+                    segments.push(std::mem::take(&mut segment));
                 }
             }
+            segment.push(point_index);
+            prev_ts = *ts;
+        }
+        if !segment.is_empty() {
+            segments.push(segment);
+        }
+        segments
+    }
+
+    /// Writes the track as a single GeoJSON feature: a LineString, or a MultiLineString if the track is broken up by pauses or gaps.
+    /// The timestamps of each point are included in the properties, in the same shape as the coordinates.
+    fn export_geojson(&self, context: &AnalyzerContext, split_start_offset_ms: u64, split_end_offset_ms: u64) -> String {
+        let loc_data = &context.location_analyzer;
+        let segments = Exporter::track_segments(context, split_start_offset_ms, split_end_offset_ms);
+
+        if loc_data.times.is_empty() {
+            return String::new();
+        }
+
+        let coordinates: Vec<Vec<Vec<f64>>> = segments.iter().map(|segment| segment.iter().map(|point_index| {
+            let mut coordinate = vec![loc_data.longitude_readings[*point_index], loc_data.latitude_readings[*point_index]];
+            if let Some(altitude) = loc_data.get_altitude(*point_index) {
+                coordinate.push(altitude);
+            }
+            coordinate
+        }).collect()).collect();
+        let times: Vec<Vec<u64>> = segments.iter().map(|segment| segment.iter().map(|point_index| loc_data.times[*point_index]).collect()).collect();
+
+        let first_point = segments.first().and_then(|segment| segment.first());
+        let last_point = segments.last().and_then(|segment| segment.last());
+        let start_time = first_point.map(|point_index| loc_data.times[*point_index]);
+        let end_time = last_point.map(|point_index| loc_data.times[*point_index]);
+        let distance = match (first_point, last_point) {
+            (Some(first), Some(last)) => loc_data.distance_readings[*last] - loc_data.distance_readings[*first],
+            _ => 0.0,
+        };
+
+        let geometry = if coordinates.len() == 1 {
+            serde_json::json!({ "type": "LineString", "coordinates": coordinates[0] })
+        }
+        else {
+            serde_json::json!({ "type": "MultiLineString", "coordinates": coordinates })
+        };
+        let coord_times = if times.len() == 1 { serde_json::json!(times[0]) } else { serde_json::json!(times) };
+
+        let feature = serde_json::json!({
+            "type": "Feature",
+            "geometry": geometry,
+            "properties": {
+                "name": context.name,
+                "activity_type": loc_data.activity_type,
+                "start_time": start_time,
+                "end_time": end_time,
+                "distance": distance,
+                "coordTimes": coord_times,
+            },
+        });
+        let collection = serde_json::json!({
+            "type": "FeatureCollection",
+            "features": [ feature ],
+        });
+
+        collection.to_string()
+    }
+
+    /// Writes the track as a KML gx:MultiTrack, with one gx:Track per segment, and a placemark at the end of each lap.
+    fn export_kml(&self, context: &AnalyzerContext, split_start_offset_ms: u64, split_end_offset_ms: u64) -> String {
+        let loc_data = &context.location_analyzer;
+        let segments = Exporter::track_segments(context, split_start_offset_ms, split_end_offset_ms);
+        let mut writer = KmlWriter::new();
+
+        if loc_data.times.is_empty() {
+            return String::new();
+        }
+
+        writer.open();
+        writer.write_name(&context.name);
+        writer.write_description(&loc_data.activity_type);
+        writer.write_styles();
+
+        writer.start_track_placemark(&context.name);
+        for segment in segments.iter() {
+            let times: Vec<u64> = segment.iter().map(|point_index| loc_data.times[*point_index]).collect();
+            let coordinates: Vec<(f64, f64, Option<f64>)> = segment.iter().map(|point_index|
+                (loc_data.latitude_readings[*point_index], loc_data.longitude_readings[*point_index], loc_data.get_altitude(*point_index))).collect();
+            writer.write_track(&times, &coordinates);
+        }
+        writer.end_track_placemark();
 
-            result.push('\n');
+        for (lap_index, lap_time) in loc_data.lap_times.iter().enumerate() {
+            if Exporter::use_data_point(*lap_time, loc_data.start_time_ms, split_start_offset_ms, split_end_offset_ms) {
+                if let Some(point_index) = loc_data.find_nearest_index(*lap_time) {
+                    writer.write_lap_placemark(&format!("Lap {}", lap_index + 1), loc_data.latitude_readings[point_index], loc_data.longitude_readings[point_index], *lap_time);
+                }
+            }
         }
 
-        result
+        writer.close()
     }
 
+    /// Exports the activity in the given text format: GPX, TCX, CSV, GeoJSON or KML. Returns an empty string for any other
+    /// format, including binary ones such as FIT, which need [`Exporter::export_binary`].
     pub fn export(&self, context: &AnalyzerContext, format: &str, split_start_offset_ms: u64, split_end_offset_ms: u64) -> String {
         let format_lower = format.to_lowercase();
 
@@ -252,13 +763,257 @@ impl Exporter {
         if format_lower == "tcx" {
             return self.export_tcx(context, split_start_offset_ms, split_end_offset_ms);
         }
+        if format_lower == "csv" {
+            return self.export_csv(context, split_start_offset_ms, split_end_offset_ms);
+        }
+        if format_lower == "geojson" {
+            return self.export_geojson(context, split_start_offset_ms, split_end_offset_ms);
+        }
+        if format_lower == "kml" {
+            return self.export_kml(context, split_start_offset_ms, split_end_offset_ms);
+        }
+
+        String::new()
+    }
+
+    /// Same as [`Exporter::export`], but also handles binary formats, such as FIT.
+    pub fn export_binary(&self, context: &AnalyzerContext, format: &str, split_start_offset_ms: u64, split_end_offset_ms: u64) -> Vec<u8> {
+        let format_lower = format.to_lowercase();
+
         if format_lower == "fit" {
             return self.export_fit(context, split_start_offset_ms, split_end_offset_ms);
         }
-        if format_lower == "csv" {
-            return self.export_csv(context, split_start_offset_ms, split_end_offset_ms);
+
+        self.export(context, format, split_start_offset_ms, split_end_offset_ms).into_bytes()
+    }
+}
+
+impl Default for Exporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analyzer_context::AnalyzerContext;
+    use crate::exporter::{CsvField, ExportOptions, Exporter, TimestampFormat};
+    use crate::gpx_route_reader;
+    use crate::kml_reader;
+    use crate::sample_table::{Column, Lookup};
+    use serde_json::Value;
+    use std::io::BufReader;
+
+    const START_TIME_MS: u64 = 1600000000000;
+
+    #[test]
+    fn test_gpx_extensions() {
+        let mut context = AnalyzerContext::new();
+
+        // Heart rate and power for the first half of the ride only.
+        for i in 0..10 {
+            let time_ms = START_TIME_MS + i * 1000;
+            context.location_analyzer.append_location(time_ms, 45.0 + i as f64 * 0.0001, -75.0, Some(100.0));
+            if i < 5 {
+                context.hr_analyzer.append_sensor_value(time_ms, 140.0);
+                context.power_analyzer.append_sensor_value(time_ms, 250.0);
+            }
+        }
+
+        let gpx_str = Exporter::new().export(&context, "gpx", 0, 0);
+        let mut data = BufReader::new(gpx_str.as_bytes());
+        let gpx = gpx_route_reader::read(&mut data).unwrap();
+        let points = &gpx.tracks[0].segments[0].points;
+        assert_eq!(points.len(), 10);
+
+        // Power goes alongside Garmin's extension, not inside it.
+        let extensions = points[0].extensions.as_ref().unwrap();
+        assert_eq!(extensions.power, Some(250.0));
+        assert_eq!(extensions.track_point_extension.as_ref().unwrap().power, None);
+        assert_eq!(extensions.track_point_extension.as_ref().unwrap().heart_rate, Some(140.0));
+
+        // The last readings aren't carried over to the points after them.
+        for point in points[5..].iter() {
+            let extensions = point.extensions.as_ref().unwrap();
+            assert_eq!(extensions.get_power(), None);
+            assert_eq!(extensions.track_point_extension.as_ref().unwrap().heart_rate, None);
+        }
+    }
+
+    #[test]
+    fn test_indoor_export() {
+        let mut context = AnalyzerContext::new();
+
+        // An indoor ride, with the distance from the trainer instead of a track.
+        for i in 0..10 {
+            let time_ms = START_TIME_MS + i * 1000;
+            context.location_analyzer.append_distance(time_ms, i as f64 * 10.0);
+            context.power_analyzer.append_sensor_value(time_ms, 250.0);
+        }
+
+        // GPX, GeoJSON and KML need positions, so there's nothing to write.
+        assert!(Exporter::new().export(&context, "gpx", 0, 0).is_empty());
+        assert!(Exporter::new().export(&context, "geojson", 0, 0).is_empty());
+        assert!(Exporter::new().export(&context, "kml", 0, 0).is_empty());
+
+        // The distances reported by the trainer go into the table and the TCX file.
+        let table = context.sample_table();
+        assert_eq!(table.value_at(Column::Distance, START_TIME_MS + 5000, Lookup::Nearest), Some(50.0));
+
+        let tcx_str = Exporter::new().export(&context, "tcx", 0, 0);
+        assert!(tcx_str.contains("<DistanceMeters>90.0</DistanceMeters>"));
+    }
+
+    #[test]
+    fn test_formats() {
+        let mut context = AnalyzerContext::new();
+        for i in 0..10 {
+            context.location_analyzer.append_location(START_TIME_MS + i * 1000, 45.0 + i as f64 * 0.0001, -75.0, Some(100.0));
         }
 
-        format_lower
+        // FIT is binary, so only export_binary writes it, and unknown formats give nothing rather than the format's name.
+        let exporter = Exporter::new();
+        assert!(exporter.export(&context, "fit", 0, 0).is_empty());
+        assert!(exporter.export(&context, "xyz", 0, 0).is_empty());
+        assert_eq!(&exporter.export_binary(&context, "FIT", 0, 0)[8..12], b".FIT");
+        assert_eq!(exporter.export_binary(&context, "tcx", 0, 0), exporter.export(&context, "tcx", 0, 0).into_bytes());
+    }
+
+    /// A twenty second ride with a location every second, and laps ending after five and fifteen seconds. If paused, the timer
+    /// is stopped for a minute after the first ten seconds.
+    fn make_lap_context(paused: bool) -> AnalyzerContext {
+        let mut context = AnalyzerContext::new();
+
+        for i in 0..20 {
+            let time_ms = START_TIME_MS + i * 1000 + if paused && i >= 10 { 60000 } else { 0 };
+            if paused && i == 10 {
+                context.location_analyzer.stop_timer(time_ms - 60000);
+                context.location_analyzer.start_timer(time_ms);
+            }
+            context.location_analyzer.append_location(time_ms, 45.0 + i as f64 * 0.0001, -75.0, Some(100.0 + i as f64));
+            if i == 5 || i == 15 {
+                context.location_analyzer.append_lap(time_ms);
+            }
+        }
+        context
+    }
+
+    #[test]
+    fn test_geojson() {
+        let context = make_lap_context(false);
+        let geojson: Value = serde_json::from_str(&Exporter::new().export(&context, "geojson", 0, 0)).unwrap();
+        let feature = &geojson["features"][0];
+
+        // An unbroken track is a single line, with a time for each coordinate.
+        assert_eq!(feature["geometry"]["type"], "LineString");
+        let coordinates = feature["geometry"]["coordinates"].as_array().unwrap();
+        assert_eq!(coordinates.len(), 20);
+        assert_eq!(coordinates[3], serde_json::json!([-75.0, 45.0003, 103.0]));
+        let times: Vec<u64> = feature["properties"]["coordTimes"].as_array().unwrap().iter().map(|time| time.as_u64().unwrap()).collect();
+        assert_eq!(times, context.location_analyzer.times);
+
+        // Breaks at the pause, with the times broken up the same way.
+        let context = make_lap_context(true);
+        let geojson: Value = serde_json::from_str(&Exporter::new().export(&context, "geojson", 0, 0)).unwrap();
+        let feature = &geojson["features"][0];
+        assert_eq!(feature["geometry"]["type"], "MultiLineString");
+        let lines = feature["geometry"]["coordinates"].as_array().unwrap();
+        let coord_times = feature["properties"]["coordTimes"].as_array().unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(coord_times.len(), 2);
+        for (line, times) in lines.iter().zip(coord_times.iter()) {
+            assert_eq!(line.as_array().unwrap().len(), 10);
+            assert_eq!(times.as_array().unwrap().len(), 10);
+        }
+        assert_eq!(lines[1][0], serde_json::json!([-75.0, 45.001, 110.0]));
+        assert_eq!(coord_times[1][0], START_TIME_MS + 70000);
+
+        // Only the points within the split.
+        let context = make_lap_context(false);
+        let geojson: Value = serde_json::from_str(&Exporter::new().export(&context, "geojson", 2000, 8000)).unwrap();
+        let feature = &geojson["features"][0];
+        assert_eq!(feature["geometry"]["coordinates"].as_array().unwrap().len(), 6);
+        assert_eq!(feature["properties"]["start_time"], START_TIME_MS + 2000);
+        assert_eq!(feature["properties"]["end_time"], START_TIME_MS + 7000);
+    }
+
+    #[test]
+    fn test_kml() {
+        let context = make_lap_context(true);
+        let kml = kml_reader::read(Exporter::new().export(&context, "kml", 0, 0).as_bytes()).unwrap();
+
+        // One track per segment, with the times lined up with the coordinates.
+        assert_eq!(kml.tracks.len(), 2);
+        let points: Vec<&kml_reader::KmlPoint> = kml.tracks.iter().flat_map(|track| track.points.iter()).collect();
+        assert_eq!(kml.tracks[0].points.len(), 10);
+        assert_eq!(points.len(), 20);
+        for (point_index, point) in points.iter().enumerate() {
+            assert_eq!(point.time_ms, Some(context.location_analyzer.times[point_index]));
+            assert_eq!(point.lat, context.location_analyzer.latitude_readings[point_index]);
+            assert_eq!(point.alt, Some(100.0 + point_index as f64));
+        }
+
+        // A placemark where each lap ended.
+        assert_eq!(kml.placemarks.len(), 2);
+        assert_eq!(kml.placemarks[0].name.as_deref(), Some("Lap 1"));
+        assert_eq!(kml.placemarks[0].lat, context.location_analyzer.latitude_readings[5]);
+        assert_eq!(kml.placemarks[1].name.as_deref(), Some("Lap 2"));
+        assert_eq!(kml.placemarks[1].lat, context.location_analyzer.latitude_readings[15]);
+
+        // Only the points and laps within the split.
+        let context = make_lap_context(false);
+        let kml = kml_reader::read(Exporter::new().export(&context, "kml", 2000, 8000).as_bytes()).unwrap();
+        assert_eq!(kml.tracks.len(), 1);
+        assert_eq!(kml.tracks[0].points.len(), 6);
+        assert_eq!(kml.tracks[0].points[0].time_ms, Some(START_TIME_MS + 2000));
+        assert_eq!(kml.placemarks.len(), 1);
+        assert_eq!(kml.placemarks[0].name.as_deref(), Some("Lap 1"));
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut context = AnalyzerContext::new();
+
+        // Locations and heart rate every second, with cadence and power recorded in between.
+        for i in 0..4 {
+            let time_ms = START_TIME_MS + i * 1000;
+            context.location_analyzer.append_location(time_ms, 45.0 + i as f64 * 0.0001, -75.0, Some(100.0));
+            context.hr_analyzer.append_sensor_value(time_ms, 140.0 + i as f64);
+        }
+        context.cadence_analyzer.append_sensor_value(START_TIME_MS + 1500, 90.0);
+        context.power_analyzer.append_sensor_value(START_TIME_MS + 2500, 250.0);
+
+        let csv_str = Exporter::new().export(&context, "csv", 0, 0);
+        let rows: Vec<Vec<&str>> = csv_str.lines().map(|line| line.split(',').collect()).collect();
+
+        // A header, then a row for each time something was recorded, all with the same number of columns.
+        assert_eq!(rows[0][..4], ["Time", "Latitude", "Longitude", "Altitude (m)"]);
+        assert_eq!(rows[0][15], "Right Pedal Smoothness (%)");
+        assert_eq!(rows.len(), 7);
+        assert!(rows.iter().all(|row| row.len() == 16));
+        assert_eq!(rows[1][0], "1600000000000");
+        assert_eq!(rows[2][0], "1600000001000");
+        assert_eq!(rows[3][0], "1600000001500");
+
+        // Readings stay in the row they were recorded in, rather than being matched up with the heart rate readings by index.
+        let cell = |row: &Vec<&str>, header: &str| row[rows[0].iter().position(|name| *name == header).unwrap()].to_string();
+        assert_eq!(cell(&rows[1], "Heart Rate (bpm)"), "140");
+        assert_eq!(cell(&rows[1], "Cadence (rpm)"), "");
+        assert_eq!(cell(&rows[1], "Power (W)"), "");
+        assert_eq!(cell(&rows[3], "Latitude"), "");
+        assert_eq!(cell(&rows[3], "Heart Rate (bpm)"), "");
+        assert_eq!(cell(&rows[3], "Cadence (rpm)"), "90");
+        assert_eq!(cell(&rows[5], "Power (W)"), "250");
+        assert_eq!(rows.iter().skip(1).filter(|row| !cell(row, "Cadence (rpm)").is_empty()).count(), 1);
+        assert_eq!(rows.iter().skip(1).filter(|row| !cell(row, "Power (W)").is_empty()).count(), 1);
+
+        // Only the selected fields, in the order they're listed, with ISO timestamps.
+        let options = ExportOptions{ timestamp_format: TimestampFormat::Iso8601, csv_fields: vec![CsvField::Power, CsvField::HeartRate], clean_track: false };
+        let csv_str = Exporter::with_options(options).export(&context, "csv", 0, 0);
+        let lines: Vec<&str> = csv_str.lines().collect();
+        assert_eq!(lines[0], "Time,Power (W),Heart Rate (bpm)");
+        assert_eq!(lines[1], "2020-09-13T12:26:40.000Z,,140");
+        assert_eq!(lines[3], "2020-09-13T12:26:41.500Z,,");
+        assert_eq!(lines[5], "2020-09-13T12:26:42.500Z,250,");
     }
 }
@@ -0,0 +1,477 @@
+// Copyright (c) 2021 Michael J. Simms. All rights reserved.
+
+const FIT_HEADER_SIZE: u8 = 14;
+const FIT_PROTOCOL_VERSION: u8 = 0x20; // 2.0
+const FIT_PROFILE_VERSION: u16 = 2132; // 21.32
+const FIT_EPOCH_OFFSET_SECS: u64 = 631065600; // Seconds between the UNIX epoch and the FIT epoch (1989-12-31T00:00:00Z)
+
+const GLOBAL_MSG_NUM_FILE_ID: u16 = 0;
+const GLOBAL_MSG_NUM_SESSION: u16 = 18;
+const GLOBAL_MSG_NUM_LAP: u16 = 19;
+const GLOBAL_MSG_NUM_RECORD: u16 = 20;
+const GLOBAL_MSG_NUM_EVENT: u16 = 21;
+const GLOBAL_MSG_NUM_ACTIVITY: u16 = 34;
+
+const LOCAL_MSG_TYPE_FILE_ID: u8 = 0;
+const LOCAL_MSG_TYPE_RECORD: u8 = 1;
+const LOCAL_MSG_TYPE_EVENT: u8 = 2;
+const LOCAL_MSG_TYPE_LAP: u8 = 3;
+const LOCAL_MSG_TYPE_SESSION: u8 = 4;
+const LOCAL_MSG_TYPE_ACTIVITY: u8 = 5;
+
+const DEFINITION_MSG_FLAG: u8 = 0x40;
+
+const BASE_TYPE_ENUM: u8 = 0x00;
+const BASE_TYPE_SINT8: u8 = 0x01;
+const BASE_TYPE_UINT8: u8 = 0x02;
+const BASE_TYPE_UINT16: u8 = 0x84;
+const BASE_TYPE_SINT32: u8 = 0x85;
+const BASE_TYPE_UINT32: u8 = 0x86;
+
+const FILE_TYPE_ACTIVITY: u8 = 4;
+const EVENT_TIMER: u8 = 0;
+const EVENT_SESSION: u8 = 8;
+const EVENT_LAP: u8 = 9;
+const EVENT_ACTIVITY: u8 = 26;
+const EVENT_TYPE_START: u8 = 0;
+const EVENT_TYPE_STOP: u8 = 1;
+const EVENT_TYPE_STOP_ALL: u8 = 4;
+const ACTIVITY_TYPE_MANUAL: u8 = 0;
+const MANUFACTURER_DEVELOPMENT: u16 = 255;
+
+const CRC_TABLE: [u16; 16] = [
+    0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401,
+    0xA001, 0x6C00, 0x7800, 0xB401, 0x5000, 0x9C01, 0x8801, 0x4400
+];
+
+/// Everything that can be written in a single record message. Missing values are written as the FIT invalid value.
+#[derive(Default)]
+pub struct FitRecord {
+    pub time_ms: u64,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub altitude: Option<f64>,
+    pub heart_rate: Option<f64>,
+    pub cadence: Option<f64>,
+    pub power: Option<f64>,
+    pub temperature: Option<f64>,
+    pub left_right_balance: Option<f64>, // Percentage of the power from the right leg
+    pub left_torque_effectiveness: Option<f64>,
+    pub right_torque_effectiveness: Option<f64>,
+    pub left_pedal_smoothness: Option<f64>,
+    pub right_pedal_smoothness: Option<f64>,
+}
+
+/// The summary of a lap or a session.
+#[derive(Default)]
+pub struct FitSummary {
+    pub start_time_ms: u64,
+    pub end_time_ms: u64,
+    pub timer_time_ms: u64, // Time spent with the timer running
+    pub distance: f64, // Meters
+    pub avg_heart_rate: Option<f64>,
+    pub max_heart_rate: Option<f64>,
+    pub avg_cadence: Option<f64>,
+    pub max_cadence: Option<f64>,
+    pub avg_power: Option<f64>,
+    pub max_power: Option<f64>,
+}
+
+pub struct FitWriter {
+    data: Vec<u8>, // Everything after the file header
+}
+
+impl FitWriter {
+    pub fn new() -> Self {
+        FitWriter{ data: Vec::new() }
+    }
+
+    /// Writes the file ID message, which must be the first message in the file.
+    pub fn open(&mut self, start_time_ms: u64) {
+        self.write_definition(LOCAL_MSG_TYPE_FILE_ID, GLOBAL_MSG_NUM_FILE_ID, &[
+            (0, 1, BASE_TYPE_ENUM), // type
+            (1, 2, BASE_TYPE_UINT16), // manufacturer
+            (2, 2, BASE_TYPE_UINT16), // product
+            (4, 4, BASE_TYPE_UINT32), // time_created
+        ]);
+
+        self.data.push(LOCAL_MSG_TYPE_FILE_ID);
+        self.data.push(FILE_TYPE_ACTIVITY);
+        self.data.extend_from_slice(&MANUFACTURER_DEVELOPMENT.to_le_bytes());
+        self.data.extend_from_slice(&0_u16.to_le_bytes());
+        self.data.extend_from_slice(&FitWriter::to_fit_timestamp(start_time_ms).to_le_bytes());
+
+        self.write_definition(LOCAL_MSG_TYPE_RECORD, GLOBAL_MSG_NUM_RECORD, &[
+            (253, 4, BASE_TYPE_UINT32), // timestamp
+            (0, 4, BASE_TYPE_SINT32), // position_lat
+            (1, 4, BASE_TYPE_SINT32), // position_long
+            (2, 2, BASE_TYPE_UINT16), // altitude
+            (3, 1, BASE_TYPE_UINT8), // heart_rate
+            (4, 1, BASE_TYPE_UINT8), // cadence
+            (7, 2, BASE_TYPE_UINT16), // power
+            (13, 1, BASE_TYPE_SINT8), // temperature
+            (30, 1, BASE_TYPE_UINT8), // left_right_balance
+            (43, 1, BASE_TYPE_UINT8), // left_torque_effectiveness
+            (44, 1, BASE_TYPE_UINT8), // right_torque_effectiveness
+            (45, 1, BASE_TYPE_UINT8), // left_pedal_smoothness
+            (46, 1, BASE_TYPE_UINT8), // right_pedal_smoothness
+        ]);
+
+        self.write_definition(LOCAL_MSG_TYPE_EVENT, GLOBAL_MSG_NUM_EVENT, &[
+            (253, 4, BASE_TYPE_UINT32), // timestamp
+            (0, 1, BASE_TYPE_ENUM), // event
+            (1, 1, BASE_TYPE_ENUM), // event_type
+        ]);
+
+        // Laps and sessions share the summary fields, though the field numbers differ from the heart rate on.
+        self.write_definition(LOCAL_MSG_TYPE_LAP, GLOBAL_MSG_NUM_LAP, &[
+            (253, 4, BASE_TYPE_UINT32), // timestamp
+            (254, 2, BASE_TYPE_UINT16), // message_index
+            (0, 1, BASE_TYPE_ENUM), // event
+            (1, 1, BASE_TYPE_ENUM), // event_type
+            (2, 4, BASE_TYPE_UINT32), // start_time
+            (7, 4, BASE_TYPE_UINT32), // total_elapsed_time
+            (8, 4, BASE_TYPE_UINT32), // total_timer_time
+            (9, 4, BASE_TYPE_UINT32), // total_distance
+            (15, 1, BASE_TYPE_UINT8), // avg_heart_rate
+            (16, 1, BASE_TYPE_UINT8), // max_heart_rate
+            (17, 1, BASE_TYPE_UINT8), // avg_cadence
+            (18, 1, BASE_TYPE_UINT8), // max_cadence
+            (19, 2, BASE_TYPE_UINT16), // avg_power
+            (20, 2, BASE_TYPE_UINT16), // max_power
+        ]);
+
+        self.write_definition(LOCAL_MSG_TYPE_SESSION, GLOBAL_MSG_NUM_SESSION, &[
+            (253, 4, BASE_TYPE_UINT32), // timestamp
+            (254, 2, BASE_TYPE_UINT16), // message_index
+            (0, 1, BASE_TYPE_ENUM), // event
+            (1, 1, BASE_TYPE_ENUM), // event_type
+            (2, 4, BASE_TYPE_UINT32), // start_time
+            (7, 4, BASE_TYPE_UINT32), // total_elapsed_time
+            (8, 4, BASE_TYPE_UINT32), // total_timer_time
+            (9, 4, BASE_TYPE_UINT32), // total_distance
+            (16, 1, BASE_TYPE_UINT8), // avg_heart_rate
+            (17, 1, BASE_TYPE_UINT8), // max_heart_rate
+            (18, 1, BASE_TYPE_UINT8), // avg_cadence
+            (19, 1, BASE_TYPE_UINT8), // max_cadence
+            (20, 2, BASE_TYPE_UINT16), // avg_power
+            (21, 2, BASE_TYPE_UINT16), // max_power
+            (5, 1, BASE_TYPE_ENUM), // sport
+            (6, 1, BASE_TYPE_ENUM), // sub_sport
+            (25, 2, BASE_TYPE_UINT16), // first_lap_index
+            (26, 2, BASE_TYPE_UINT16), // num_laps
+        ]);
+
+        self.write_definition(LOCAL_MSG_TYPE_ACTIVITY, GLOBAL_MSG_NUM_ACTIVITY, &[
+            (253, 4, BASE_TYPE_UINT32), // timestamp
+            (0, 4, BASE_TYPE_UINT32), // total_timer_time
+            (1, 2, BASE_TYPE_UINT16), // num_sessions
+            (2, 1, BASE_TYPE_ENUM), // type
+            (3, 1, BASE_TYPE_ENUM), // event
+            (4, 1, BASE_TYPE_ENUM), // event_type
+        ]);
+    }
+
+    /// Prepends the file header and appends the file CRC.
+    pub fn close(self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(FIT_HEADER_SIZE as usize + self.data.len() + 2);
+
+        result.push(FIT_HEADER_SIZE);
+        result.push(FIT_PROTOCOL_VERSION);
+        result.extend_from_slice(&FIT_PROFILE_VERSION.to_le_bytes());
+        result.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        result.extend_from_slice(b".FIT");
+        let header_crc = FitWriter::compute_crc(&result);
+        result.extend_from_slice(&header_crc.to_le_bytes());
+
+        result.extend_from_slice(&self.data);
+        let file_crc = FitWriter::compute_crc(&result);
+        result.extend_from_slice(&file_crc.to_le_bytes());
+        result
+    }
+
+    pub fn write_record(&mut self, record: &FitRecord) {
+        self.data.push(LOCAL_MSG_TYPE_RECORD);
+        self.data.extend_from_slice(&FitWriter::to_fit_timestamp(record.time_ms).to_le_bytes());
+        self.data.extend_from_slice(&FitWriter::to_semicircles(record.latitude).to_le_bytes());
+        self.data.extend_from_slice(&FitWriter::to_semicircles(record.longitude).to_le_bytes());
+
+        // Apply scaling and offset.
+        let altitude = match record.altitude {
+            Some(altitude) => ((altitude + 500.0) * 5.0).round() as u16,
+            None => 0xFFFF,
+        };
+        self.data.extend_from_slice(&altitude.to_le_bytes());
+
+        self.data.push(FitWriter::to_uint8(record.heart_rate, 1.0));
+        self.data.push(FitWriter::to_uint8(record.cadence, 1.0));
+
+        self.data.extend_from_slice(&FitWriter::to_uint16(record.power).to_le_bytes());
+
+        let temperature = match record.temperature {
+            Some(temperature) => temperature.round() as i8,
+            None => 0x7F,
+        };
+        self.data.extend_from_slice(&temperature.to_le_bytes());
+
+        // The high bit indicates that the balance value refers to the right leg.
+        let balance = match record.left_right_balance {
+            Some(balance) => (balance.round() as u8 & 0x7F) | 0x80,
+            None => 0xFF,
+        };
+        self.data.push(balance);
+
+        // Torque effectiveness and pedal smoothness are stored in half percents.
+        self.data.push(FitWriter::to_uint8(record.left_torque_effectiveness, 2.0));
+        self.data.push(FitWriter::to_uint8(record.right_torque_effectiveness, 2.0));
+        self.data.push(FitWriter::to_uint8(record.left_pedal_smoothness, 2.0));
+        self.data.push(FitWriter::to_uint8(record.right_pedal_smoothness, 2.0));
+    }
+
+    /// Writes a timer start or stop event.
+    pub fn write_timer_event(&mut self, time_ms: u64, is_start: bool) {
+        self.data.push(LOCAL_MSG_TYPE_EVENT);
+        self.data.extend_from_slice(&FitWriter::to_fit_timestamp(time_ms).to_le_bytes());
+        self.data.push(EVENT_TIMER);
+        self.data.push(if is_start { EVENT_TYPE_START } else { EVENT_TYPE_STOP_ALL });
+    }
+
+    /// Writes the summary fields shared by laps and sessions, from the start time on.
+    fn write_summary_fields(&mut self, summary: &FitSummary) {
+        self.data.extend_from_slice(&FitWriter::to_fit_timestamp(summary.start_time_ms).to_le_bytes());
+        self.data.extend_from_slice(&(summary.end_time_ms.saturating_sub(summary.start_time_ms) as u32).to_le_bytes()); // Milliseconds
+        self.data.extend_from_slice(&(summary.timer_time_ms as u32).to_le_bytes()); // Milliseconds
+        self.data.extend_from_slice(&((summary.distance * 100.0).round() as u32).to_le_bytes()); // Centimeters
+        self.data.push(FitWriter::to_uint8(summary.avg_heart_rate, 1.0));
+        self.data.push(FitWriter::to_uint8(summary.max_heart_rate, 1.0));
+        self.data.push(FitWriter::to_uint8(summary.avg_cadence, 1.0));
+        self.data.push(FitWriter::to_uint8(summary.max_cadence, 1.0));
+        self.data.extend_from_slice(&FitWriter::to_uint16(summary.avg_power).to_le_bytes());
+        self.data.extend_from_slice(&FitWriter::to_uint16(summary.max_power).to_le_bytes());
+    }
+
+    /// Writes a lap message, which is written when the lap ends.
+    pub fn write_lap(&mut self, lap_index: u16, lap: &FitSummary) {
+        self.data.push(LOCAL_MSG_TYPE_LAP);
+        self.data.extend_from_slice(&FitWriter::to_fit_timestamp(lap.end_time_ms).to_le_bytes());
+        self.data.extend_from_slice(&lap_index.to_le_bytes());
+        self.data.push(EVENT_LAP);
+        self.data.push(EVENT_TYPE_STOP);
+        self.write_summary_fields(lap);
+    }
+
+    /// Writes a session message, covering all of the laps written before it.
+    pub fn write_session(&mut self, session: &FitSummary, sport: u8, sub_sport: u8, num_laps: u16) {
+        self.data.push(LOCAL_MSG_TYPE_SESSION);
+        self.data.extend_from_slice(&FitWriter::to_fit_timestamp(session.end_time_ms).to_le_bytes());
+        self.data.extend_from_slice(&0_u16.to_le_bytes());
+        self.data.push(EVENT_SESSION);
+        self.data.push(EVENT_TYPE_STOP);
+        self.write_summary_fields(session);
+        self.data.push(sport);
+        self.data.push(sub_sport);
+        self.data.extend_from_slice(&0_u16.to_le_bytes());
+        self.data.extend_from_slice(&num_laps.to_le_bytes());
+    }
+
+    /// Writes the activity message, which is the last message in the file.
+    pub fn write_activity(&mut self, end_time_ms: u64, timer_time_ms: u64, num_sessions: u16) {
+        self.data.push(LOCAL_MSG_TYPE_ACTIVITY);
+        self.data.extend_from_slice(&FitWriter::to_fit_timestamp(end_time_ms).to_le_bytes());
+        self.data.extend_from_slice(&(timer_time_ms as u32).to_le_bytes()); // Milliseconds
+        self.data.extend_from_slice(&num_sessions.to_le_bytes());
+        self.data.push(ACTIVITY_TYPE_MANUAL);
+        self.data.push(EVENT_ACTIVITY);
+        self.data.push(EVENT_TYPE_STOP);
+    }
+
+    /// Writes a definition message. Each field is described by its field number, size (in bytes), and base type.
+    fn write_definition(&mut self, local_msg_type: u8, global_msg_num: u16, fields: &[(u8, u8, u8)]) {
+        self.data.push(DEFINITION_MSG_FLAG | local_msg_type);
+        self.data.push(0); // Reserved
+        self.data.push(0); // Little endian
+        self.data.extend_from_slice(&global_msg_num.to_le_bytes());
+        self.data.push(fields.len() as u8);
+        for (field_num, size, base_type) in fields {
+            self.data.push(*field_num);
+            self.data.push(*size);
+            self.data.push(*base_type);
+        }
+    }
+
+    fn to_fit_timestamp(time_ms: u64) -> u32 {
+        (time_ms / 1000).saturating_sub(FIT_EPOCH_OFFSET_SECS) as u32
+    }
+
+    fn to_semicircles(degrees: Option<f64>) -> i32 {
+        match degrees {
+            Some(degrees) => (degrees * (2147483648.0 / 180.0)).round() as i32,
+            None => 0x7FFFFFFF,
+        }
+    }
+
+    fn to_uint8(value: Option<f64>, scale: f64) -> u8 {
+        match value {
+            Some(value) => {
+                let scaled = (value * scale).round();
+                if (0.0..255.0).contains(&scaled) {
+                    return scaled as u8;
+                }
+                0xFF
+            }
+            None => 0xFF,
+        }
+    }
+
+    fn to_uint16(value: Option<f64>) -> u16 {
+        match value {
+            Some(value) => {
+                let rounded = value.round();
+                if (0.0..65535.0).contains(&rounded) {
+                    return rounded as u16;
+                }
+                0xFFFF
+            }
+            None => 0xFFFF,
+        }
+    }
+
+    fn compute_crc(bytes: &[u8]) -> u16 {
+        let mut crc: u16 = 0;
+
+        for &byte in bytes {
+            // Lower four bits.
+            let mut tmp = CRC_TABLE[(crc & 0xF) as usize];
+            crc = (crc >> 4) & 0x0FFF;
+            crc = crc ^ tmp ^ CRC_TABLE[(byte & 0xF) as usize];
+
+            // Upper four bits.
+            tmp = CRC_TABLE[(crc & 0xF) as usize];
+            crc = (crc >> 4) & 0x0FFF;
+            crc = crc ^ tmp ^ CRC_TABLE[((byte >> 4) & 0xF) as usize];
+        }
+        crc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fit_writer::{FitRecord, FitSummary, FitWriter, FIT_EPOCH_OFFSET_SECS};
+    use std::ffi::c_void;
+    use std::io::BufReader;
+
+    const START_TIME_MS: u64 = 1600000000000;
+
+    /// Everything the decoder passed to the callback.
+    struct Decoded {
+        messages: Vec<(u16, u32, Vec<fit_file::fit_file::FitFieldValue>)>,
+    }
+
+    fn callback(timestamp: u32, global_message_num: u16, _local_msg_type: u8, _message_index: u16, fields: Vec<fit_file::fit_file::FitFieldValue>, context: *mut c_void) {
+        let decoded: &mut Decoded = unsafe { &mut *(context as *mut Decoded) };
+        decoded.messages.push((global_message_num, timestamp, fields));
+    }
+
+    fn decode(bytes: &[u8]) -> Decoded {
+        let mut decoded = Decoded{ messages: Vec::new() };
+        let context_ptr: *mut c_void = &mut decoded as *mut _ as *mut c_void;
+        let mut reader = BufReader::new(bytes);
+
+        fit_file::fit_file::read(&mut reader, callback, context_ptr).expect("The written file should be readable");
+        decoded
+    }
+
+    #[test]
+    fn test_compute_crc() {
+        // FIT uses CRC-16/ARC, whose check value is the CRC of the ASCII digits 1 through 9.
+        assert_eq!(FitWriter::compute_crc(b"123456789"), 0xBB3D);
+        assert_eq!(FitWriter::compute_crc(&[]), 0);
+    }
+
+    #[test]
+    fn test_header() {
+        let mut writer = FitWriter::new();
+        writer.open(START_TIME_MS);
+        let bytes = writer.close();
+
+        assert_eq!(bytes[0], 14);
+        assert_eq!(bytes[1], 0x20);
+        assert_eq!(u16::from_le_bytes([bytes[2], bytes[3]]), 2132);
+        assert_eq!(u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize, bytes.len() - 16);
+        assert_eq!(&bytes[8..12], b".FIT");
+        assert_eq!(u16::from_le_bytes([bytes[12], bytes[13]]), FitWriter::compute_crc(&bytes[..12]));
+
+        // Running the CRC over the data followed by its CRC gives zero.
+        assert_eq!(FitWriter::compute_crc(&bytes), 0);
+    }
+
+    #[test]
+    fn test_record_layout() {
+        let mut writer = FitWriter::new();
+        let record = FitRecord{ time_ms: START_TIME_MS, latitude: Some(45.0), heart_rate: Some(150.0), power: Some(250.0), ..Default::default() };
+        writer.write_record(&record);
+
+        // Local message type, timestamp, latitude, longitude, altitude, heart rate, cadence, power, temperature, then the five cycling dynamics.
+        let data = &writer.data;
+        assert_eq!(data.len(), 1 + 4 + 4 + 4 + 2 + 1 + 1 + 2 + 1 + 5);
+        assert_eq!(data[0], 1);
+        assert_eq!(u32::from_le_bytes([data[1], data[2], data[3], data[4]]) as u64, START_TIME_MS / 1000 - FIT_EPOCH_OFFSET_SECS);
+        assert_eq!(i32::from_le_bytes([data[5], data[6], data[7], data[8]]), 0x20000000);
+        assert_eq!(i32::from_le_bytes([data[9], data[10], data[11], data[12]]), 0x7FFFFFFF);
+        assert_eq!(u16::from_le_bytes([data[13], data[14]]), 0xFFFF);
+        assert_eq!(data[15], 150);
+        assert_eq!(data[16], 0xFF);
+        assert_eq!(u16::from_le_bytes([data[17], data[18]]), 250);
+        assert_eq!(data[19], 0x7F);
+        assert!(data[20..].iter().all(|byte| *byte == 0xFF));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut writer = FitWriter::new();
+        writer.open(START_TIME_MS);
+        writer.write_timer_event(START_TIME_MS, true);
+        for i in 0..10 {
+            let record = FitRecord{ time_ms: START_TIME_MS + i * 1000, latitude: Some(45.0 + i as f64 * 0.0001), longitude: Some(-75.0),
+                altitude: Some(100.0), heart_rate: Some(140.0 + i as f64), ..Default::default() };
+            writer.write_record(&record);
+        }
+        writer.write_timer_event(START_TIME_MS + 10000, false);
+
+        let summary = FitSummary{ start_time_ms: START_TIME_MS, end_time_ms: START_TIME_MS + 10000, timer_time_ms: 10000, distance: 100.0,
+            avg_heart_rate: Some(144.5), max_heart_rate: Some(149.0), ..Default::default() };
+        writer.write_lap(0, &summary);
+        writer.write_session(&summary, 1, 0, 1);
+        writer.write_activity(START_TIME_MS + 10000, 10000, 1);
+
+        let mut messages = decode(&writer.close()).messages;
+        let message_nums: Vec<u16> = messages.iter().map(|(message_num, _, _)| *message_num).collect();
+        let mut expected = vec![fit_file::fit_file::GLOBAL_MSG_NUM_FILE_ID, fit_file::fit_file::GLOBAL_MSG_NUM_EVENT];
+        expected.extend(vec![fit_file::fit_file::GLOBAL_MSG_NUM_RECORD; 10]);
+        expected.extend(vec![fit_file::fit_file::GLOBAL_MSG_NUM_EVENT, fit_file::fit_file::GLOBAL_MSG_NUM_LAP, fit_file::fit_file::GLOBAL_MSG_NUM_SESSION,
+            fit_file::fit_file::GLOBAL_MSG_NUM_ACTIVITY]);
+        assert_eq!(message_nums, expected);
+
+        // Records.
+        // The decoder converts timestamps back to the UNIX epoch.
+        assert_eq!(messages[2].1 as u64, START_TIME_MS / 1000);
+        let record = fit_file::fit_file::FitRecordMsg::new(std::mem::take(&mut messages[2].2));
+        let latitude = fit_file::fit_file::semicircles_to_degrees(record.position_lat.unwrap());
+        assert!((latitude - 45.0).abs() < 0.000001);
+        assert_eq!(record.heart_rate, Some(140));
+
+        // Timer events.
+        let start = fit_file::fit_file::FitEventMsg::new(std::mem::take(&mut messages[1].2));
+        assert_eq!((start.event, start.event_type), (Some(0), Some(0)));
+        let stop = fit_file::fit_file::FitEventMsg::new(std::mem::take(&mut messages[12].2));
+        assert_eq!((stop.event, stop.event_type), (Some(0), Some(4)));
+
+        // Session.
+        let session = fit_file::fit_file::FitSessionMsg::new(std::mem::take(&mut messages[14].2));
+        assert_eq!(session.sport, Some(1));
+        assert_eq!(session.total_timer_time, Some(10000));
+        assert_eq!(session.avg_heart_rate, Some(145));
+        assert_eq!(session.max_heart_rate, Some(149));
+        assert_eq!(session.num_laps, Some(1));
+    }
+}
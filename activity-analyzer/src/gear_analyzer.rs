@@ -0,0 +1,206 @@
+// Copyright (c) 2021 Michael J. Simms. All rights reserved.
+
+use crate::cadence_analyzer::CadenceAnalyzer;
+use std::collections::HashMap;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Serialize)]
+pub struct GearChange {
+    pub timestamp_ms: u64,
+    pub front_gear_num: u8, // Chainring number, starting with 1 for the smallest
+    pub front_gear_teeth: u8,
+    pub rear_gear_num: u8, // Cog number, starting with 1 for the largest
+    pub rear_gear_teeth: u8,
+}
+
+impl GearChange {
+    /// Decodes the data field of a FIT gear change event.
+    /// The data field packs the rear gear number, rear gear teeth, front gear number, and front gear teeth, from the lowest byte to the highest.
+    pub fn from_event_data(date_time_ms: u64, event_data: u32) -> Self {
+        GearChange{
+            timestamp_ms: date_time_ms,
+            front_gear_num: ((event_data >> 16) & 0xFF) as u8,
+            front_gear_teeth: ((event_data >> 24) & 0xFF) as u8,
+            rear_gear_num: (event_data & 0xFF) as u8,
+            rear_gear_teeth: ((event_data >> 8) & 0xFF) as u8,
+        }
+    }
+}
+
+/// Summary of the time spent in a single front/rear gear combination.
+#[derive(Clone, Copy, Serialize)]
+pub struct GearCombination {
+    pub front_gear_num: u8,
+    pub front_gear_teeth: u8,
+    pub rear_gear_num: u8,
+    pub rear_gear_teeth: u8,
+    pub seconds: u64, // Total time spent in this gear
+    pub avg_cadence: f64, // Average cadence while in this gear
+    pub avg_speed: f64, // Average speed (in meters/second) while in this gear
+}
+
+#[derive(Default)]
+struct GearTotals {
+    duration_ms: u64,
+    cadence_sum: f64,
+    cadence_count: usize,
+    speed_sum: f64,
+    speed_count: usize,
+}
+
+pub struct GearAnalyzer {
+    pub gear_changes: Vec<GearChange>,
+    pub front_shifts: u64,
+    pub rear_shifts: u64,
+    pub combinations: Vec<GearCombination>,
+}
+
+impl GearAnalyzer {
+    /// Creates a new [`GearAnalyzer`].
+    pub fn new() -> Self {
+        GearAnalyzer{ gear_changes: Vec::new(), front_shifts: 0, rear_shifts: 0, combinations: Vec::new() }
+    }
+
+    /// Adds a gear change, such as one decoded with [`GearChange::from_event_data`].
+    pub fn append_gear_change(&mut self, change: GearChange) -> GearChange {
+
+        // Count the shifts. The first event only tells us the starting gear.
+        if let Some(last_change) = self.gear_changes.last() {
+            if last_change.front_gear_num != change.front_gear_num {
+                self.front_shifts += 1;
+            }
+            if last_change.rear_gear_num != change.rear_gear_num {
+                self.rear_shifts += 1;
+            }
+        }
+
+        self.gear_changes.push(change);
+        change
+    }
+
+    /// Sums the values that fall within [start_time_ms, end_time_ms). Since both the times and the gear changes are sorted,
+    /// the index is carried from one call to the next.
+    fn sum_range(time_readings: &[u64], readings: &[f64], start_time_ms: u64, end_time_ms: u64, index: &mut usize) -> (f64, usize) {
+        let mut sum = 0.0;
+        let mut count = 0;
+
+        while *index < time_readings.len() && time_readings[*index] < start_time_ms {
+            *index += 1;
+        }
+        while *index < time_readings.len() && time_readings[*index] < end_time_ms {
+            sum += readings[*index];
+            count += 1;
+            *index += 1;
+        }
+        (sum, count)
+    }
+
+    /// Called after all data is loaded. Computes the time, cadence, and speed for each gear combination.
+    pub fn analyze(&mut self, end_time_ms: u64, cadence: &CadenceAnalyzer, speed_times: &[u64], speeds: &[f64]) {
+        let mut totals: HashMap<(u8, u8), GearTotals> = HashMap::new();
+        let mut cadence_index = 0;
+        let mut speed_index = 0;
+
+        for (change_index, change) in self.gear_changes.iter().enumerate() {
+            let next_change_time_ms = match self.gear_changes.get(change_index + 1) {
+                Some(next_change) => next_change.timestamp_ms,
+                None => end_time_ms,
+            };
+            if next_change_time_ms <= change.timestamp_ms {
+                continue;
+            }
+
+            let (cadence_sum, cadence_count) = GearAnalyzer::sum_range(&cadence.time_readings, &cadence.readings, change.timestamp_ms, next_change_time_ms, &mut cadence_index);
+            let (speed_sum, speed_count) = GearAnalyzer::sum_range(speed_times, speeds, change.timestamp_ms, next_change_time_ms, &mut speed_index);
+
+            let gear_totals = totals.entry((change.front_gear_num, change.rear_gear_num)).or_default();
+            gear_totals.duration_ms += next_change_time_ms - change.timestamp_ms;
+            gear_totals.cadence_sum += cadence_sum;
+            gear_totals.cadence_count += cadence_count;
+            gear_totals.speed_sum += speed_sum;
+            gear_totals.speed_count += speed_count;
+        }
+
+        self.combinations.clear();
+        for change in self.gear_changes.iter() {
+            if let Some(gear_totals) = totals.remove(&(change.front_gear_num, change.rear_gear_num)) {
+                let mut combination = GearCombination{ front_gear_num: change.front_gear_num, front_gear_teeth: change.front_gear_teeth, rear_gear_num: change.rear_gear_num,
+                    rear_gear_teeth: change.rear_gear_teeth, seconds: gear_totals.duration_ms / 1000, avg_cadence: 0.0, avg_speed: 0.0 };
+
+                if gear_totals.cadence_count > 0 {
+                    combination.avg_cadence = gear_totals.cadence_sum / gear_totals.cadence_count as f64;
+                }
+                if gear_totals.speed_count > 0 {
+                    combination.avg_speed = gear_totals.speed_sum / gear_totals.speed_count as f64;
+                }
+                self.combinations.push(combination);
+            }
+        }
+        self.combinations.sort_by_key(|combination| (combination.front_gear_num, combination.rear_gear_num));
+    }
+}
+
+impl Default for GearAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cadence_analyzer::CadenceAnalyzer;
+    use crate::gear_analyzer::{GearAnalyzer, GearChange};
+
+    fn gear_change(timestamp_ms: u64, front_gear_num: u8, front_gear_teeth: u8, rear_gear_num: u8, rear_gear_teeth: u8) -> GearChange {
+        GearChange{ timestamp_ms, front_gear_num, front_gear_teeth, rear_gear_num, rear_gear_teeth }
+    }
+
+    #[test]
+    fn test_from_event_data() {
+        let change = GearChange::from_event_data(1000, (50 << 24) | (2 << 16) | (17 << 8) | 5);
+        assert_eq!(change.timestamp_ms, 1000);
+        assert_eq!(change.front_gear_num, 2);
+        assert_eq!(change.front_gear_teeth, 50);
+        assert_eq!(change.rear_gear_num, 5);
+        assert_eq!(change.rear_gear_teeth, 17);
+    }
+
+    #[test]
+    fn test_append_gear_change() {
+        let mut analyzer = GearAnalyzer::new();
+
+        // The first event is the starting gear, so only the changes after it are shifts.
+        analyzer.append_gear_change(gear_change(1000, 2, 50, 5, 17));
+        assert_eq!((analyzer.front_shifts, analyzer.rear_shifts), (0, 0));
+        analyzer.append_gear_change(gear_change(2000, 2, 50, 6, 15));
+        analyzer.append_gear_change(gear_change(3000, 1, 34, 6, 15));
+        analyzer.append_gear_change(gear_change(4000, 1, 34, 5, 17));
+        assert_eq!((analyzer.front_shifts, analyzer.rear_shifts), (1, 2));
+        assert_eq!(analyzer.gear_changes.len(), 4);
+    }
+
+    #[test]
+    fn test_analyze() {
+        let mut analyzer = GearAnalyzer::new();
+        let mut cadence = CadenceAnalyzer::new();
+
+        analyzer.append_gear_change(gear_change(0, 2, 50, 5, 17));
+        analyzer.append_gear_change(gear_change(10000, 2, 50, 6, 15));
+        analyzer.append_gear_change(gear_change(20000, 2, 50, 5, 17));
+        for i in 0..30 {
+            cadence.append_sensor_value(i * 1000, if (10..20).contains(&i) { 90.0 } else { 80.0 });
+        }
+        let speed_times: Vec<u64> = (0..30).map(|i| i * 1000).collect();
+        let speeds = vec![10.0; 30];
+
+        analyzer.analyze(30000, &cadence, &speed_times, &speeds);
+
+        // Time in the same gear is combined, even when it isn't continuous.
+        assert_eq!(analyzer.combinations.len(), 2);
+        assert_eq!((analyzer.combinations[0].rear_gear_num, analyzer.combinations[0].seconds), (5, 20));
+        assert_eq!((analyzer.combinations[1].rear_gear_num, analyzer.combinations[1].seconds), (6, 10));
+        assert_eq!(analyzer.combinations[0].avg_cadence, 80.0);
+        assert_eq!(analyzer.combinations[1].avg_cadence, 90.0);
+        assert_eq!(analyzer.combinations[1].avg_speed, 10.0);
+    }
+}
@@ -7,6 +7,58 @@ use std::io::BufReader;
 extern crate serde;
 extern crate serde_xml_rs;
 
+/// Garmin's TrackPointExtension. Namespace prefixes are dropped by the parser, so gpxtpx:hr is read as hr, etc.
+#[derive(Deserialize, Debug, Default)]
+pub struct TrackPointExtension {
+    #[serde(rename="hr")]
+    pub heart_rate: Option<f64>,
+    #[serde(rename="cad")]
+    pub cadence: Option<f64>,
+    #[serde(rename="atemp")]
+    pub temperature: Option<f64>,
+    #[serde(rename="power")]
+    pub power: Option<f64>, // Not part of Garmin's schema, but some apps put it here
+}
+
+/// Garmin's PowerExtension.
+#[derive(Deserialize, Debug, Default)]
+pub struct PowerExtension {
+    #[serde(rename="Watts")]
+    pub watts: Option<f64>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Extensions {
+    #[serde(rename="TrackPointExtension")]
+    pub track_point_extension: Option<TrackPointExtension>,
+    #[serde(rename="PowerExtension")]
+    pub power_extension: Option<PowerExtension>,
+    #[serde(rename="PowerInWatts")]
+    pub power_in_watts: Option<f64>,
+    #[serde(rename="power")]
+    pub power: Option<f64>, // Alongside the TrackPointExtension, which is where our own exporter puts it
+}
+
+impl Extensions {
+    /// Power can be stored in several places, depending on the app that wrote the file.
+    pub fn get_power(&self) -> Option<f64> {
+        if let Some(tpx) = &self.track_point_extension {
+            if tpx.power.is_some() {
+                return tpx.power;
+            }
+        }
+        if let Some(power_extension) = &self.power_extension {
+            if power_extension.watts.is_some() {
+                return power_extension.watts;
+            }
+        }
+        if self.power_in_watts.is_some() {
+            return self.power_in_watts;
+        }
+        self.power
+    }
+}
+
 #[derive(Deserialize, Debug, Default)]
 pub struct TrackPoint {
     #[serde(rename="lat")]
@@ -14,21 +66,48 @@ pub struct TrackPoint {
     #[serde(rename="lon")]
     pub lon: f64,
     #[serde(rename="ele")]
-    pub ele: f64,
+    pub ele: Option<f64>,
+    #[serde(rename="time")]
+    pub time: Option<String>,
+    #[serde(rename="name")]
+    pub name: Option<String>,
+    #[serde(rename="extensions")]
+    pub extensions: Option<Extensions>,
+}
+
+impl TrackPoint {
+    /// Parses the timestamp, returning the number of milliseconds since the epoch, or None if the point doesn't have a valid timestamp.
+    pub fn get_time_ms(&self) -> Option<u64> {
+        if let Some(time_str) = &self.time {
+            if let Ok(date_time) = chrono::DateTime::parse_from_rfc3339(time_str) {
+                return Some(date_time.timestamp_millis() as u64);
+            }
+        }
+        None
+    }
 }
 
 #[derive(Deserialize, Debug)]
 pub struct TrackSegment {
-    #[serde(rename="trkpt")]
+    #[serde(rename="trkpt", default)]
     pub points: Vec<TrackPoint>,
 }
 
 #[derive(Deserialize, Debug, Default)]
 pub struct Track {
+    #[serde(rename="type")]
+    pub type_: Option<String>,
     #[serde(rename="trkseg")]
     pub segments: Vec<TrackSegment>,
 }
 
+/// A planned route. Route points have the same format as track points, but usually don't have timestamps.
+#[derive(Deserialize, Debug, Default)]
+pub struct Route {
+    #[serde(rename="rtept", default)]
+    pub points: Vec<TrackPoint>,
+}
+
 #[derive(Deserialize, Debug, Default)]
 pub struct Metadata {
 }
@@ -38,10 +117,60 @@ pub struct Metadata {
 pub struct GpxRoute {
     #[serde(rename="metadata")]
     pub metadata: Option<Metadata>,
-    #[serde(rename="trk")]
-    pub tracks: Option<Track>,
+    #[serde(rename="wpt", default)]
+    pub waypoints: Vec<TrackPoint>,
+    #[serde(rename="rte", default)]
+    pub routes: Vec<Route>,
+    #[serde(rename="trk", default)]
+    pub tracks: Vec<Track>,
 }
 
 pub fn read<R: Read>(reader: &mut BufReader<R>) -> Result<GpxRoute, serde_xml_rs::Error> {
     serde_xml_rs::from_reader(reader)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::gpx_route_reader;
+    use std::io::BufReader;
+
+    #[test]
+    fn test_read_track_and_extensions() {
+        let s = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="test" xmlns="http://www.topografix.com/GPX/1/1" xmlns:gpxtpx="http://www.garmin.com/xmlschemas/TrackPointExtension/v2">
+  <wpt lat="45.1" lon="-75.1"><time>2021-01-01T10:00:30Z</time><name>Summit</name></wpt>
+  <trk>
+    <name>Morning Ride</name>
+    <type>Cycling</type>
+    <trkseg>
+      <trkpt lat="45.0" lon="-75.0"><ele>100.0</ele><time>2021-01-01T10:00:00Z</time>
+        <extensions><gpxtpx:TrackPointExtension><gpxtpx:atemp>21.5</gpxtpx:atemp><gpxtpx:hr>140</gpxtpx:hr><gpxtpx:cad>85</gpxtpx:cad></gpxtpx:TrackPointExtension><power>250</power></extensions>
+      </trkpt>
+      <trkpt lat="45.001" lon="-75.0"><time>2021-01-01T10:00:01Z</time></trkpt>
+    </trkseg>
+  </trk>
+</gpx>"#;
+        let mut data = BufReader::new(s.as_bytes());
+        let gpx = gpx_route_reader::read(&mut data).unwrap();
+
+        assert_eq!(gpx.waypoints.len(), 1);
+        assert_eq!(gpx.waypoints[0].name.as_deref(), Some("Summit"));
+        assert_eq!(gpx.waypoints[0].get_time_ms(), Some(1609495230000));
+
+        assert_eq!(gpx.tracks.len(), 1);
+        assert_eq!(gpx.tracks[0].type_.as_deref(), Some("Cycling"));
+
+        let points = &gpx.tracks[0].segments[0].points;
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].get_time_ms(), Some(1609495200000));
+        assert_eq!(points[0].ele, Some(100.0));
+
+        let extensions = points[0].extensions.as_ref().unwrap();
+        let tpx = extensions.track_point_extension.as_ref().unwrap();
+        assert_eq!(tpx.temperature, Some(21.5));
+        assert_eq!(tpx.heart_rate, Some(140.0));
+        assert_eq!(tpx.cadence, Some(85.0));
+        assert_eq!(extensions.get_power(), Some(250.0));
+        assert!(points[1].extensions.is_none());
+    }
+}
@@ -1,10 +1,11 @@
 // Copyright (c) 2021 Michael J. Simms. All rights reserved.
 
-extern crate chrono;
-
-use chrono::*;
+use crate::utils::format_iso_timestamp;
 use xmlwriter::*;
 
+const AUTHOR_NAME: &str = "Activity Analyzer";
+const AUTHOR_LINK: &str = "https://activity-analyzer.app";
+
 pub struct GpxWriter {
     writer: XmlWriter,
 }
@@ -19,9 +20,9 @@ impl GpxWriter {
         self.writer.start_element("gpx");
         self.writer.write_attribute("creator", "activity-analyzer.app");
         self.writer.write_attribute("version", "1.1");
-        self.writer.write_attribute("xsi:schemaLocation", "http://www.topografix.com/GPX/1/1 http://www.topografix.com/GPX/1/1/gpx.xsd http://www.garmin.com/xmlschemas/GpxExtensions/v3 http://www.garmin.com/xmlschemas/GpxExtensionsv3.xsd http://www.garmin.com/xmlschemas/TrackPointExtension/v1 http://www.garmin.com/xmlschemas/TrackPointExtensionv1.xsd");
+        self.writer.write_attribute("xsi:schemaLocation", "http://www.topografix.com/GPX/1/1 http://www.topografix.com/GPX/1/1/gpx.xsd http://www.garmin.com/xmlschemas/GpxExtensions/v3 http://www.garmin.com/xmlschemas/GpxExtensionsv3.xsd http://www.garmin.com/xmlschemas/TrackPointExtension/v2 http://www.garmin.com/xmlschemas/TrackPointExtensionv2.xsd");
         self.writer.write_attribute("xmlns", "http://www.topografix.com/GPX/1/1");
-        self.writer.write_attribute("xmlns:gpxtpx", "http://www.garmin.com/xmlschemas/TrackPointExtension/v1");
+        self.writer.write_attribute("xmlns:gpxtpx", "http://www.garmin.com/xmlschemas/TrackPointExtension/v2");
         self.writer.write_attribute("xmlns:gpxx", "http://www.garmin.com/xmlschemas/GpxExtensions/v3");
         self.writer.write_attribute("xmlns:xsi", "http://www.w3.org/2001/XMLSchema-instance");
     }
@@ -29,13 +30,37 @@ impl GpxWriter {
         self.writer.end_document()
     }
 
-    pub fn write_metadata(&mut self, start_time_ms: u64) {
+    /// Writes the author, link, start time, and (if there are any points) the bounds, in the order required by the schema.
+    /// Bounds are (min_lat, min_lon, max_lat, max_lon).
+    pub fn write_metadata(&mut self, start_time_ms: u64, bounds: Option<(f64, f64, f64, f64)>) {
         self.writer.start_element("metadata");
+
+        self.writer.start_element("author");
+        self.write_name(AUTHOR_NAME);
+        self.writer.start_element("link");
+        self.writer.write_attribute("href", AUTHOR_LINK);
+        self.writer.end_element();
+        self.writer.end_element();
+
+        self.writer.start_element("link");
+        self.writer.write_attribute("href", AUTHOR_LINK);
+        self.writer.end_element();
+
         self.writer.start_element("time");
         self.writer.set_preserve_whitespaces(true);
-        self.writer.write_text(&GpxWriter::format_timestamp(start_time_ms));
+        self.writer.write_text(&format_iso_timestamp(start_time_ms));
         self.writer.end_element();
         self.writer.set_preserve_whitespaces(false);
+
+        if let Some((min_lat, min_lon, max_lat, max_lon)) = bounds {
+            self.writer.start_element("bounds");
+            self.writer.write_attribute("minlat", &min_lat);
+            self.writer.write_attribute("minlon", &min_lon);
+            self.writer.write_attribute("maxlat", &max_lat);
+            self.writer.write_attribute("maxlon", &max_lon);
+            self.writer.end_element();
+        }
+
         self.writer.end_element();
     }
     pub fn write_name(&mut self, name: &str) {
@@ -67,18 +92,20 @@ impl GpxWriter {
         self.writer.end_element();
     }
 
-    pub fn start_track_point(&mut self, lat: f64, lon: f64, alt: f64, time_ms: u64) {
+    pub fn start_track_point(&mut self, lat: f64, lon: f64, alt: Option<f64>, time_ms: u64) {
         self.writer.start_element("trkpt");
         self.writer.write_attribute("lat", &lat);
         self.writer.write_attribute("lon", &lon);
-        self.writer.start_element("ele");
-        self.writer.set_preserve_whitespaces(true);
-        self.writer.write_text_fmt(format_args!("{:?}", &alt));
-        self.writer.end_element();
-        self.writer.set_preserve_whitespaces(false);
+        if let Some(alt) = alt {
+            self.writer.start_element("ele");
+            self.writer.set_preserve_whitespaces(true);
+            self.writer.write_text_fmt(format_args!("{:?}", &alt));
+            self.writer.end_element();
+            self.writer.set_preserve_whitespaces(false);
+        }
         self.writer.start_element("time");
         self.writer.set_preserve_whitespaces(true);
-        self.writer.write_text(&GpxWriter::format_timestamp(time_ms));
+        self.writer.write_text(&format_iso_timestamp(time_ms));
         self.writer.end_element();
         self.writer.set_preserve_whitespaces(false);
     }
@@ -100,6 +127,13 @@ impl GpxWriter {
         self.writer.end_element();
     }
 
+    pub fn store_temperature(&mut self, temperature: f64) {
+        self.writer.start_element("gpxtpx:atemp");
+        self.writer.set_preserve_whitespaces(true);
+        self.writer.write_text_fmt(format_args!("{:.1}", &temperature));
+        self.writer.end_element();
+        self.writer.set_preserve_whitespaces(false);
+    }
     pub fn store_heart_rate_bpm(&mut self, heart_rate_bpm: u8) {
         self.writer.start_element("gpxtpx:hr");
         self.writer.set_preserve_whitespaces(true);
@@ -114,6 +148,20 @@ impl GpxWriter {
         self.writer.end_element();
         self.writer.set_preserve_whitespaces(false);
     }
+    pub fn store_speed(&mut self, speed: f64) {
+        self.writer.start_element("gpxtpx:speed");
+        self.writer.set_preserve_whitespaces(true);
+        self.writer.write_text_fmt(format_args!("{:.2}", &speed));
+        self.writer.end_element();
+        self.writer.set_preserve_whitespaces(false);
+    }
+    pub fn store_course(&mut self, course: f64) {
+        self.writer.start_element("gpxtpx:course");
+        self.writer.set_preserve_whitespaces(true);
+        self.writer.write_text_fmt(format_args!("{:.1}", &course));
+        self.writer.end_element();
+        self.writer.set_preserve_whitespaces(false);
+    }
     pub fn store_power_in_watts(&mut self, power_in_watts: u32) {
         self.writer.start_element("power");
         self.writer.set_preserve_whitespaces(true);
@@ -121,14 +169,4 @@ impl GpxWriter {
         self.writer.end_element();
         self.writer.set_preserve_whitespaces(false);
     }
-
-    fn format_timestamp(t: u64) -> String {
-        let sec  = t / 1000;
-        let ms = t % 1000;
-
-        let datetime = DateTime::<Utc>::from_timestamp(sec as i64, 0).unwrap();
-        let buf1 = datetime.format("%Y-%m-%dT%H:%M:%S");
-        let buf2 = format!("{}.{:03}Z", buf1, ms);
-        buf2
-    }
 }
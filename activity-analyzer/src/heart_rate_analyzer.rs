@@ -1,5 +1,7 @@
 // Copyright (c) 2021 Michael J. Simms. All rights reserved.
 
+const NUM_HR_ZONES: u8 = 5;
+
 pub struct HeartRateAnalyzer {
     pub readings: Vec<f64>, // All the readings
     pub time_readings: Vec<u64>, // All the readings (time)
@@ -21,6 +23,20 @@ impl HeartRateAnalyzer {
         0.0
     }
 
+    /// Returns the training zone (1 to 5) for the given heart rate, using zones that are 10% of the maximum heart rate wide,
+    /// starting at 50%. Returns 0 for anything below zone 1.
+    pub fn compute_zone(heart_rate: f64, max_hr: f64) -> u8 {
+        if max_hr <= 0.0 {
+            return 0;
+        }
+
+        let percent_of_max = heart_rate / max_hr * 100.0;
+        if percent_of_max < 50.0 {
+            return 0;
+        }
+        u8::min(((percent_of_max - 50.0) / 10.0) as u8 + 1, NUM_HR_ZONES)
+    }
+
     /// Adds another reading to the analyzer.
     pub fn append_sensor_value(&mut self, date_time_ms: u64, value: f64) {
 
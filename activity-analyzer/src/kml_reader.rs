@@ -0,0 +1,240 @@
+// Copyright (c) 2021 Michael J. Simms. All rights reserved.
+
+extern crate xml;
+
+use std::io::{Cursor, Read};
+use xml::reader::{EventReader, XmlEvent};
+
+/// A location along a track. Points read from a LineString don't have a time.
+#[derive(Debug, Default)]
+pub struct KmlPoint {
+    pub lat: f64,
+    pub lon: f64,
+    pub alt: Option<f64>, // Meters, None if the track is clamped to the ground
+    pub time_ms: Option<u64>,
+}
+
+/// A LineString or a gx:Track.
+#[derive(Debug, Default)]
+pub struct KmlTrack {
+    pub points: Vec<KmlPoint>,
+}
+
+/// A placemark with a single point, such as a marker on a planned route.
+#[derive(Debug, Default)]
+pub struct KmlPlacemark {
+    pub name: Option<String>,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+#[derive(Debug, Default)]
+pub struct KmlDocument {
+    pub tracks: Vec<KmlTrack>,
+    pub placemarks: Vec<KmlPlacemark>,
+}
+
+/// Parses a coordinate tuple. LineStrings and Points separate the values with commas, gx:Track separates them with spaces.
+/// Either way the order is longitude, latitude, and optionally altitude.
+fn parse_coordinate(tuple: &str, separator: char) -> Option<KmlPoint> {
+    let mut values = tuple.split(separator).filter(|value| !value.is_empty()).map(|value| value.trim().parse::<f64>());
+
+    let lon = values.next()?.ok()?;
+    let lat = values.next()?.ok()?;
+    let alt = values.next().and_then(|value| value.ok());
+    Some(KmlPoint{ lat, lon, alt, time_ms: None })
+}
+
+/// Parses a timestamp, returning the number of milliseconds since the epoch.
+fn parse_time(text: &str) -> Option<u64> {
+    match chrono::DateTime::parse_from_rfc3339(text) {
+        Ok(date_time) => Some(date_time.timestamp_millis() as u64),
+        Err(_e) => None,
+    }
+}
+
+/// Reads the tracks and point placemarks from a KML file. Placemarks can be nested inside any number of folders,
+/// so rather than mapping the whole schema we walk the elements and pick out the ones we're interested in.
+pub fn read<R: Read>(reader: R) -> Result<KmlDocument, String> {
+    let mut document = KmlDocument::default();
+
+    let mut path: Vec<String> = Vec::new(); // Names (without namespace prefixes) of the elements we're currently inside
+    let mut text = String::new();
+    let mut placemark_name: Option<String> = None;
+    let mut clamp_to_ground = false;
+    let mut track_times: Vec<Option<u64>> = Vec::new();
+    let mut track_coords: Vec<KmlPoint> = Vec::new();
+
+    for event in EventReader::new(reader) {
+        match event {
+            Ok(XmlEvent::StartElement { name, .. }) => {
+                match name.local_name.as_str() {
+                    "Placemark" => placemark_name = None,
+                    "LineString" | "Track" => {
+                        clamp_to_ground = false;
+                        track_times.clear();
+                        track_coords.clear();
+                    }
+                    _ => {}
+                }
+                path.push(name.local_name);
+                text.clear();
+            }
+            Ok(XmlEvent::Characters(characters)) | Ok(XmlEvent::CData(characters)) => {
+                text.push_str(&characters);
+            }
+            Ok(XmlEvent::EndElement { .. }) => {
+                let element = path.pop().unwrap_or_default();
+                let parent = path.last().map(|parent| parent.as_str()).unwrap_or_default();
+                let value = text.trim();
+
+                match (element.as_str(), parent) {
+                    ("name", "Placemark") => placemark_name = Some(value.to_string()),
+                    ("altitudeMode", "LineString") | ("altitudeMode", "Track") => clamp_to_ground = value == "clampToGround",
+                    ("coordinates", "LineString") => {
+                        track_coords.extend(value.split_whitespace().filter_map(|tuple| parse_coordinate(tuple, ',')));
+                    }
+                    ("coordinates", "Point") => {
+                        if let Some(point) = parse_coordinate(value, ',') {
+                            document.placemarks.push(KmlPlacemark{ name: placemark_name.clone(), lat: point.lat, lon: point.lon });
+                        }
+                    }
+                    ("when", "Track") => track_times.push(parse_time(value)),
+                    ("coord", "Track") => {
+                        if let Some(point) = parse_coordinate(value, ' ') {
+                            track_coords.push(point);
+                        }
+                    }
+                    ("LineString", _) | ("Track", _) => {
+                        let mut track = KmlTrack{ points: std::mem::take(&mut track_coords) };
+
+                        // A gx:Track lists all the times and then all the coordinates, in the same order.
+                        for (point, time_ms) in track.points.iter_mut().zip(track_times.iter()) {
+                            point.time_ms = *time_ms;
+                        }
+                        if clamp_to_ground {
+                            for point in track.points.iter_mut() {
+                                point.alt = None;
+                            }
+                        }
+                        if !track.points.is_empty() {
+                            document.tracks.push(track);
+                        }
+                    }
+                    _ => {}
+                }
+                text.clear();
+            }
+            Err(e) => {
+                return Err(e.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    Ok(document)
+}
+
+/// Reads a KMZ file, which is a zip archive containing a KML file (usually doc.kml) along with any images it references.
+pub fn read_kmz(data: &[u8]) -> Result<KmlDocument, String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).map_err(|e| e.to_string())?;
+
+    // Prefer doc.kml, but otherwise use the first KML file in the archive.
+    let names: Vec<String> = archive.file_names().map(|name| name.to_string()).collect();
+    let kml_name = names.iter().find(|name| name.eq_ignore_ascii_case("doc.kml"))
+        .or_else(|| names.iter().find(|name| name.to_lowercase().ends_with(".kml")));
+
+    match kml_name {
+        Some(kml_name) => {
+            let mut kml = String::new();
+            archive.by_name(kml_name).map_err(|e| e.to_string())?.read_to_string(&mut kml).map_err(|e| e.to_string())?;
+            read(kml.as_bytes())
+        }
+        None => Err("The KMZ file does not contain a KML file.".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kml_reader;
+    use std::io::{Cursor, Write};
+
+    const KML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">
+  <Document>
+    <Folder>
+      <Placemark>
+        <name>Route</name>
+        <LineString>
+          <altitudeMode>clampToGround</altitudeMode>
+          <coordinates>-75.0,45.0,100 -75.001,45.001,110
+            -75.002,45.002,120</coordinates>
+        </LineString>
+      </Placemark>
+      <Folder>
+        <Placemark>
+          <name>Summit</name>
+          <Point><coordinates>-75.002,45.002,120</coordinates></Point>
+        </Placemark>
+      </Folder>
+    </Folder>
+    <Placemark>
+      <gx:Track>
+        <when>2020-09-13T12:26:40Z</when>
+        <when>2020-09-13T12:26:41Z</when>
+        <gx:coord>-75.0 45.0 100</gx:coord>
+        <gx:coord>-75.0001 45.0001 101</gx:coord>
+      </gx:Track>
+    </Placemark>
+  </Document>
+</kml>"#;
+
+    fn check_document(document: &kml_reader::KmlDocument) {
+        assert_eq!(document.tracks.len(), 2);
+
+        // The LineString is clamped to the ground, so its altitudes are dropped.
+        let route = &document.tracks[0].points;
+        assert_eq!(route.len(), 3);
+        assert_eq!((route[1].lat, route[1].lon), (45.001, -75.001));
+        assert!(route.iter().all(|point| point.alt.is_none() && point.time_ms.is_none()));
+
+        let track = &document.tracks[1].points;
+        assert_eq!(track.len(), 2);
+        assert_eq!((track[1].lat, track[1].lon, track[1].alt), (45.0001, -75.0001, Some(101.0)));
+        assert_eq!(track[0].time_ms, Some(1600000000000));
+        assert_eq!(track[1].time_ms, Some(1600000001000));
+
+        // Only placemarks with a point are placemarks, however deeply they're nested.
+        assert_eq!(document.placemarks.len(), 1);
+        assert_eq!(document.placemarks[0].name.as_deref(), Some("Summit"));
+        assert_eq!((document.placemarks[0].lat, document.placemarks[0].lon), (45.002, -75.002));
+    }
+
+    /// Zips the files into a KMZ.
+    fn make_kmz(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+
+        for (name, contents) in files {
+            writer.start_file(*name, zip::write::FileOptions::default()).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_read() {
+        check_document(&kml_reader::read(KML.as_bytes()).unwrap());
+        assert!(kml_reader::read("<kml><Document>".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_read_kmz() {
+        check_document(&kml_reader::read_kmz(&make_kmz(&[("images/icon.png", "not really a png"), ("doc.kml", KML)])).unwrap());
+
+        // Without a doc.kml, the first KML file is used.
+        check_document(&kml_reader::read_kmz(&make_kmz(&[("readme.txt", "hello"), ("files/Ride.KML", KML)])).unwrap());
+
+        assert!(kml_reader::read_kmz(&make_kmz(&[("readme.txt", "hello")])).is_err());
+        assert!(kml_reader::read_kmz(b"not a zip file").is_err());
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright (c) 2021 Michael J. Simms. All rights reserved.
+
+use crate::utils::format_iso_timestamp;
+use xmlwriter::*;
+
+const TRACK_STYLE_ID: &str = "track";
+const LAP_STYLE_ID: &str = "lap";
+const TRACK_COLOR: &str = "ff0055ff"; // KML colors are aabbggrr, so this is opaque orange
+const TRACK_WIDTH: u8 = 4;
+const LAP_ICON: &str = "http://maps.google.com/mapfiles/kml/shapes/flag.png";
+
+pub struct KmlWriter {
+    writer: XmlWriter,
+}
+
+impl KmlWriter {
+    pub fn new() -> Self {
+        let opt = Options { use_single_quote: true, attributes_indent: Indent::Spaces(2), indent: Indent::Spaces(2) };
+        KmlWriter{ writer: XmlWriter::new(opt) }
+    }
+
+    pub fn open(&mut self) {
+        self.writer.start_element("kml");
+        self.writer.write_attribute("xmlns", "http://www.opengis.net/kml/2.2");
+        self.writer.write_attribute("xmlns:gx", "http://www.google.com/kml/ext/2.2");
+        self.writer.start_element("Document");
+    }
+    pub fn close(self) -> String {
+        self.writer.end_document()
+    }
+
+    /// Writes the styles referenced by the track and the lap placemarks.
+    pub fn write_styles(&mut self) {
+        self.writer.start_element("Style");
+        self.writer.write_attribute("id", TRACK_STYLE_ID);
+        self.writer.start_element("LineStyle");
+        self.write_text_element("color", TRACK_COLOR);
+        self.write_text_element("width", &TRACK_WIDTH.to_string());
+        self.writer.end_element();
+        self.writer.end_element();
+
+        self.writer.start_element("Style");
+        self.writer.write_attribute("id", LAP_STYLE_ID);
+        self.writer.start_element("IconStyle");
+        self.writer.start_element("Icon");
+        self.write_text_element("href", LAP_ICON);
+        self.writer.end_element();
+        self.writer.end_element();
+        self.writer.end_element();
+    }
+
+    pub fn write_name(&mut self, name: &str) {
+        self.write_text_element("name", name);
+    }
+    pub fn write_description(&mut self, description: &str) {
+        self.write_text_element("description", description);
+    }
+
+    /// Starts the placemark that holds the track. Each segment of the track is written with [`KmlWriter::write_track`].
+    pub fn start_track_placemark(&mut self, name: &str) {
+        self.writer.start_element("Placemark");
+        self.write_name(name);
+        self.write_text_element("styleUrl", &format!("#{}", TRACK_STYLE_ID));
+        self.writer.start_element("gx:MultiTrack");
+    }
+    pub fn end_track_placemark(&mut self) {
+        self.writer.end_element();
+        self.writer.end_element();
+    }
+
+    /// Writes one segment of the track. Coordinates are (latitude, longitude, altitude). Altitude is clamped to the ground if there isn't any.
+    pub fn write_track(&mut self, times: &[u64], coordinates: &[(f64, f64, Option<f64>)]) {
+        let has_altitude = coordinates.iter().any(|(_, _, alt)| alt.is_some());
+
+        self.writer.start_element("gx:Track");
+        self.write_text_element("altitudeMode", if has_altitude { "absolute" } else { "clampToGround" });
+
+        // The schema requires all the times to come before all the coordinates.
+        for time_ms in times.iter() {
+            self.write_text_element("when", &format_iso_timestamp(*time_ms));
+        }
+        for (lat, lon, alt) in coordinates.iter() {
+            self.write_text_element("gx:coord", &format!("{} {} {}", lon, lat, alt.unwrap_or(0.0)));
+        }
+        self.writer.end_element();
+    }
+
+    /// Writes a placemark marking the end of a lap.
+    pub fn write_lap_placemark(&mut self, name: &str, lat: f64, lon: f64, time_ms: u64) {
+        self.writer.start_element("Placemark");
+        self.write_name(name);
+        self.write_text_element("styleUrl", &format!("#{}", LAP_STYLE_ID));
+        self.writer.start_element("TimeStamp");
+        self.write_text_element("when", &format_iso_timestamp(time_ms));
+        self.writer.end_element();
+        self.writer.start_element("Point");
+        self.write_text_element("coordinates", &format!("{},{}", lon, lat));
+        self.writer.end_element();
+        self.writer.end_element();
+    }
+
+    fn write_text_element(&mut self, name: &str, text: &str) {
+        self.writer.start_element(name);
+        self.writer.set_preserve_whitespaces(true);
+        self.writer.write_text(text);
+        self.writer.end_element();
+        self.writer.set_preserve_whitespaces(false);
+    }
+}
+
+impl Default for KmlWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -9,22 +9,36 @@ extern crate fit_file;
 mod utils;
 mod analyzer_context;
 mod cadence_analyzer;
+mod csv_reader;
+mod csv_writer;
+mod downsample;
+mod edit_tool;
 mod exporter;
 mod event;
+mod fit_writer;
+mod gear_analyzer;
 mod geojson;
 mod gpx_route_reader;
 mod gpx_writer;
+mod live_session;
 mod location_analyzer;
 mod merge_tool;
 mod power_analyzer;
+mod radar_analyzer;
+mod report_options;
+mod running_dynamics_analyzer;
+mod sample_table;
 mod temperature_analyzer;
 mod heart_rate_analyzer;
+mod kml_reader;
+mod kml_writer;
 mod swim_analyzer;
 mod tcx_writer;
 
 use wasm_bindgen::prelude::*;
 use std::io::BufReader;
 use std::ffi::c_void;
+use std::cell::RefCell;
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global allocator.
 #[cfg(feature = "wee_alloc")]
@@ -50,6 +64,20 @@ static mut CONTEXT_LIST: ContextList = ContextList {
     contexts: Vec::new()
 };
 
+// Settings and sessions that last between calls. Kept per thread, and borrowed for each use, so they don't need unsafe access.
+thread_local! {
+    // The activity currently being recorded, if any.
+    static LIVE_SESSION: RefCell<Option<live_session::LiveSession>> = const { RefCell::new(None) };
+
+    // Controls which time series are included in the report.
+    static REPORT_OPTIONS: RefCell<report_options::ReportOptions> = const { RefCell::new(report_options::ReportOptions::new()) };
+
+    // Controls the timestamp format and the columns of exported files.
+    static EXPORT_OPTIONS: RefCell<exporter::ExportOptions> = const { RefCell::new(exporter::ExportOptions::new()) };
+
+    // Controls how activities are lined up and which one each sensor is taken from when merging.
+    static MERGE_OPTIONS: RefCell<merge_tool::MergeOptions> = const { RefCell::new(merge_tool::MergeOptions::new()) };
+}
 
 #[wasm_bindgen]
 extern {
@@ -61,6 +89,54 @@ pub fn greet() {
     alert("Copyright (c) 2021 Michael J. Simms. All rights reserved.");
 }
 
+#[wasm_bindgen]
+pub fn set_report_options(include_series: bool, max_series_points: u32) {
+    utils::set_panic_hook();
+
+    REPORT_OPTIONS.with_borrow_mut(|options| {
+        options.include_series = include_series;
+        options.max_series_points = max_series_points as usize;
+    });
+}
+
+/// Sets the timestamp format ("epoch" or "iso8601") and the comma-separated list of CSV columns to export.
+/// An empty list exports every column. If clean_track is set, GPS outliers are dropped and the elevation is smoothed.
+#[wasm_bindgen]
+pub fn set_export_options(timestamp_format: &str, csv_fields: &str, clean_track: bool) {
+    utils::set_panic_hook();
+
+    let timestamp_format = match timestamp_format {
+        "epoch" => exporter::TimestampFormat::Epoch,
+        "iso8601" => exporter::TimestampFormat::Iso8601,
+        _ => {
+            alert("Unknown timestamp format.");
+            return;
+        }
+    };
+
+    let mut fields = Vec::new();
+    for name in csv_fields.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+        match exporter::CsvField::from_name(name) {
+            Some(field) => fields.push(field),
+            None => {
+                alert(&format!("Unknown CSV field: {}", name));
+                return;
+            }
+        }
+    }
+
+    EXPORT_OPTIONS.with_borrow_mut(|options| {
+        options.timestamp_format = timestamp_format;
+        options.csv_fields = fields;
+        options.clean_track = clean_track;
+    });
+}
+
+/// Returns an exporter that uses the options set with set_export_options.
+fn make_exporter() -> exporter::Exporter {
+    EXPORT_OPTIONS.with_borrow(|options| exporter::Exporter::with_options(options.clone()))
+}
+
 #[wasm_bindgen]
 pub fn set_world_data(s: &str) {
     utils::set_panic_hook();
@@ -79,7 +155,52 @@ pub fn set_us_data(s: &str) {
     }
 }
 
+/// Returns true if the caller wants the time series in the report. When they don't, the series are never serialized.
+fn include_series() -> bool {
+    REPORT_OPTIONS.with_borrow(|options| options.include_series)
+}
+
+/// Downsamples the time series in the report, if the caller asked us to, and converts it to a string.
+fn finish_report(mut analysis_report: serde_json::Value) -> String {
+    REPORT_OPTIONS.with_borrow(|options| options.apply(&mut analysis_report));
+    analysis_report.to_string()
+}
+
+/// Planned routes don't have any timing data, so the report only describes the course.
+fn make_route_report(context: &analyzer_context::AnalyzerContext) -> String {
+    let mut analysis_report = serde_json::json!({
+        "Activity Type": context.location_analyzer.activity_type,
+        "Route": true,
+        "Total Distance": context.location_analyzer.total_distance,
+        "Total Vertical Distance": context.location_analyzer.get_total_vertical(),
+        "Maximum Altitude": context.location_analyzer.get_max_altitude(),
+        "Climbs": context.location_analyzer.climbs,
+        "Points of Interest": context.location_analyzer.points_of_interest,
+    });
+
+    if include_series() {
+        add_to_report(&mut analysis_report, serde_json::json!({
+            "Distance Readings": context.location_analyzer.distance_readings,
+            "Altitude Readings": context.location_analyzer.get_altitude_readings(),
+            "Gradient Curve": context.location_analyzer.gradient_curve,
+            "Latitude Readings": context.location_analyzer.latitude_readings,
+            "Longitude Readings": context.location_analyzer.longitude_readings,
+        }));
+    }
+    finish_report(analysis_report)
+}
+
+/// Adds the keys in the section to the report. Sections that only apply to some activities are built separately so they can be left out.
+fn add_to_report(report: &mut serde_json::Value, section: serde_json::Value) {
+    if let (serde_json::Value::Object(report_map), serde_json::Value::Object(section_map)) = (report, section) {
+        report_map.extend(section_map);
+    }
+}
+
 fn make_final_report(context: &analyzer_context::AnalyzerContext) -> String {
+    if context.location_analyzer.is_route {
+        return make_route_report(context);
+    }
 
     let mut start_time_ms = context.location_analyzer.start_time_ms;
     let mut last_time_ms = context.location_analyzer.last_time_ms;
@@ -94,25 +215,22 @@ fn make_final_report(context: &analyzer_context::AnalyzerContext) -> String {
         total_distance = context.swim_analyzer.get_total_distance() as f64;
     }
 
-    let analysis_report_str = serde_json::json!({
+    let mut analysis_report = serde_json::json!({
         "Activity Type": context.location_analyzer.activity_type,
         "Start Time (ms)": start_time_ms,
         "End Time (ms)": last_time_ms,
         "Elapsed Time": (last_time_ms - start_time_ms) / 1000,
+        "Timer Time": context.location_analyzer.get_timer_seconds(),
+        "Pause Intervals": context.location_analyzer.pause_intervals,
         "Total Distance": total_distance,
-        "Total Vertical Distance": context.location_analyzer.total_vertical,
+        "Total Vertical Distance": context.location_analyzer.get_total_vertical(),
         "Average Speed": context.location_analyzer.avg_speed,
         "Bests": context.location_analyzer.bests,
         "Mile Splits": context.location_analyzer.mile_splits,
         "KM Splits": context.location_analyzer.km_splits,
-        "Times": context.location_analyzer.times,
-        "Speed Times": context.location_analyzer.speed_times,
-        "Speeds": context.location_analyzer.speed_graph,
-        "Altitude Readings": context.location_analyzer.altitude_graph,
-        "Gradient Curve": context.location_analyzer.gradient_curve,
-        "Latitude Readings": context.location_analyzer.latitude_readings,
-        "Longitude Readings": context.location_analyzer.longitude_readings,
         "Intervals": context.location_analyzer.significant_intervals,
+        "Climbs": context.location_analyzer.climbs,
+        "Points of Interest": context.location_analyzer.points_of_interest,
         "Maximum Power": context.power_analyzer.max_power,
         "Average Power": context.power_analyzer.avg_power,
         "5 Second Power": context.power_analyzer.get_best_power(power_analyzer::BEST_5_SEC_POWER),
@@ -120,77 +238,212 @@ fn make_final_report(context: &analyzer_context::AnalyzerContext) -> String {
         "20 Minute Power": context.power_analyzer.get_best_power(power_analyzer::BEST_20_MIN_POWER),
         "1 Hour Power": context.power_analyzer.get_best_power(power_analyzer::BEST_1_HOUR_POWER),
         "Normalized Power": context.power_analyzer.np,
-        "Power Readings": context.power_analyzer.readings.clone(),
-        "Power Times": context.power_analyzer.time_readings.clone(),
         "Power Intervals": context.power_analyzer.significant_intervals.clone(),
+        "Average Left/Right Balance": power_analyzer::PowerAnalyzer::compute_optional_average(&context.power_analyzer.left_right_balance_readings),
+        "Average Left Torque Effectiveness": power_analyzer::PowerAnalyzer::compute_optional_average(&context.power_analyzer.left_torque_effectiveness_readings),
+        "Average Right Torque Effectiveness": power_analyzer::PowerAnalyzer::compute_optional_average(&context.power_analyzer.right_torque_effectiveness_readings),
+        "Average Left Pedal Smoothness": power_analyzer::PowerAnalyzer::compute_optional_average(&context.power_analyzer.left_pedal_smoothness_readings),
+        "Average Right Pedal Smoothness": power_analyzer::PowerAnalyzer::compute_optional_average(&context.power_analyzer.right_pedal_smoothness_readings),
         "Maximum Cadence": context.cadence_analyzer.max_cadence,
         "Average Cadence": context.cadence_analyzer.compute_average(),
-        "Cadence Readings": context.cadence_analyzer.readings.clone(),
-        "Cadence Times": context.cadence_analyzer.time_readings.clone(),
         "Maximum Heart Rate": context.hr_analyzer.max_hr,
         "Average Heart Rate": context.hr_analyzer.compute_average(),
-        "Heart Rate Readings": context.hr_analyzer.readings.clone(),
-        "Heart Rate Times": context.hr_analyzer.time_readings.clone(),
-        "Temperature Readings": context.temperature_analyzer.readings.clone(),
-        "Temperature Times": context.temperature_analyzer.time_readings.clone(),
-        "Swim Stroke Readings": context.swim_analyzer.strokes.clone(),
-        "Swim Stroke Times": context.swim_analyzer.time_readings.clone(),
+        "Gear Changes": context.gear_analyzer.gear_changes.clone(),
+        "Gear Combinations": context.gear_analyzer.combinations.clone(),
+        "Front Shifts": context.gear_analyzer.front_shifts,
+        "Rear Shifts": context.gear_analyzer.rear_shifts,
+        "Radar Threats": context.radar_analyzer.threats.clone(),
+        "Radar Threat Count": context.radar_analyzer.threat_count,
+        "Radar Threats Per Hour": context.radar_analyzer.threats_per_hour,
         "Events": context.events.clone()
-    }).to_string();
+    });
+
+    // The time series make up most of the report, so only serialize them if they're wanted.
+    if include_series() {
+        add_to_report(&mut analysis_report, serde_json::json!({
+            "Times": context.location_analyzer.times,
+            "Speed Times": context.location_analyzer.speed_times,
+            "Speeds": context.location_analyzer.speed_graph,
+            "Altitude Readings": context.location_analyzer.get_altitude_readings(),
+            "Gradient Curve": context.location_analyzer.gradient_curve,
+            "Latitude Readings": context.location_analyzer.latitude_readings,
+            "Longitude Readings": context.location_analyzer.longitude_readings,
+            "Distance Readings": context.location_analyzer.distance_readings,
+            "Power Readings": context.power_analyzer.readings,
+            "Power Times": context.power_analyzer.time_readings,
+            "Cycling Dynamics Times": context.power_analyzer.cycling_dynamics_times,
+            "Left/Right Balance Readings": context.power_analyzer.left_right_balance_readings,
+            "Left Torque Effectiveness Readings": context.power_analyzer.left_torque_effectiveness_readings,
+            "Right Torque Effectiveness Readings": context.power_analyzer.right_torque_effectiveness_readings,
+            "Left Pedal Smoothness Readings": context.power_analyzer.left_pedal_smoothness_readings,
+            "Right Pedal Smoothness Readings": context.power_analyzer.right_pedal_smoothness_readings,
+            "Cadence Readings": context.cadence_analyzer.readings,
+            "Cadence Times": context.cadence_analyzer.time_readings,
+            "Heart Rate Readings": context.hr_analyzer.readings,
+            "Heart Rate Times": context.hr_analyzer.time_readings,
+            "Temperature Readings": context.temperature_analyzer.readings,
+            "Temperature Times": context.temperature_analyzer.time_readings,
+            "Swim Stroke Readings": context.swim_analyzer.strokes,
+            "Swim Stroke Times": context.swim_analyzer.time_readings,
+        }));
+    }
 
-    analysis_report_str
+    let dynamics = &context.running_dynamics_analyzer;
+    if dynamics.has_data() {
+        add_to_report(&mut analysis_report, serde_json::json!({
+            "Average Ground Contact Time": running_dynamics_analyzer::RunningDynamicsAnalyzer::compute_average(&dynamics.ground_contact_time_readings),
+            "Average Vertical Oscillation": running_dynamics_analyzer::RunningDynamicsAnalyzer::compute_average(&dynamics.vertical_oscillation_readings),
+            "Average Vertical Ratio": running_dynamics_analyzer::RunningDynamicsAnalyzer::compute_average(&dynamics.vertical_ratio_readings),
+            "Average Step Length": running_dynamics_analyzer::RunningDynamicsAnalyzer::compute_average(&dynamics.step_length_readings),
+            "Average Ground Contact Time Balance": running_dynamics_analyzer::RunningDynamicsAnalyzer::compute_average(&dynamics.ground_contact_time_balance_readings),
+            "Running Dynamics KM Splits": dynamics.km_splits.clone(),
+            "Running Dynamics Mile Splits": dynamics.mile_splits.clone()
+        }));
+        if include_series() {
+            add_to_report(&mut analysis_report, serde_json::json!({
+                "Ground Contact Time Readings": dynamics.ground_contact_time_readings,
+                "Ground Contact Time Times": dynamics.ground_contact_time_times,
+                "Vertical Oscillation Readings": dynamics.vertical_oscillation_readings,
+                "Vertical Oscillation Times": dynamics.vertical_oscillation_times,
+                "Vertical Ratio Readings": dynamics.vertical_ratio_readings,
+                "Vertical Ratio Times": dynamics.vertical_ratio_times,
+                "Step Length Readings": dynamics.step_length_readings,
+                "Step Length Times": dynamics.step_length_times,
+                "Ground Contact Time Balance Readings": dynamics.ground_contact_time_balance_readings,
+                "Ground Contact Time Balance Times": dynamics.ground_contact_time_balance_times,
+            }));
+        }
+    }
+    if context.location_analyzer.is_open_water_swim() {
+        add_to_report(&mut analysis_report, serde_json::json!({
+            "Pace per 100m": context.location_analyzer.get_pace_per_100m(),
+            "100m Splits": context.location_analyzer.swim_splits,
+            "Swim Efficiency": context.location_analyzer.swim_efficiency,
+            "Sighting Efficiencies": context.location_analyzer.sighting_efficiencies,
+        }));
+        if include_series() {
+            add_to_report(&mut analysis_report, serde_json::json!({
+                "Smoothed Latitude Readings": context.location_analyzer.smoothed_latitude_readings,
+                "Smoothed Longitude Readings": context.location_analyzer.smoothed_longitude_readings,
+            }));
+        }
+    }
+
+    finish_report(analysis_report)
 }
 
-fn analyze_gpx_route(s: &str) -> String {
-    let mut context = analyzer_context::AnalyzerContext::new();
+/// Adds the sensor readings (heart rate, cadence, temperature, and power) found in a GPX point's extensions to the analyzers.
+fn append_gpx_extensions(context: &mut analyzer_context::AnalyzerContext, time_ms: u64, extensions: &gpx_route_reader::Extensions) {
+    if let Some(tpx) = &extensions.track_point_extension {
+        if let Some(heart_rate) = tpx.heart_rate {
+            context.hr_analyzer.append_sensor_value(time_ms, heart_rate);
+        }
+        if let Some(cadence) = tpx.cadence {
+            context.cadence_analyzer.append_sensor_value(time_ms, cadence);
+        }
+        if let Some(temperature) = tpx.temperature {
+            context.temperature_analyzer.append_sensor_value(time_ms, temperature);
+        }
+    }
+    if let Some(power) = extensions.get_power() {
+        context.power_analyzer.append_sensor_value(time_ms, power);
+    }
+}
+
+/// Reads the file with our own GPX parser, which picks up the sensor readings in the point extensions in the same pass as the track.
+/// Returns false if the file couldn't be parsed, in which case nothing is added to the context.
+fn read_gpx_route(s: &str, context: &mut analyzer_context::AnalyzerContext) -> bool {
     let mut route_data = BufReader::new(s.as_bytes());
-    let route_result = gpx_route_reader::read(&mut route_data);
 
-    match route_result {
-        Err(_e) => {
-            alert("Error parsing the GPX file.");
-        }
+    match gpx_route_reader::read(&mut route_data) {
+        Err(_e) => false,
         Ok(gpx) => {
+            let mut has_time = false;
+
             // Iterate through the tracks.
             for track in gpx.tracks {
 
+                // Get the track name.
+                if let Some(activity_type) = &track.type_ {
+                    context.location_analyzer.set_activity_type(activity_type.to_string())
+                }
+
                 // Iterate through the track segments.
                 for trackseg in track.segments {
-                    let mut fake_time = 0;
 
                     // Iterate through the points.
                     for point in trackseg.points {
-                        context.location_analyzer.append_location(fake_time, point.lat, point.lon, point.ele);
-                        fake_time += 1;
+                        match point.get_time_ms() {
+                            Some(time_ms) => {
+                                context.location_analyzer.append_location(time_ms, point.lat, point.lon, point.ele);
+                                context.location_analyzer.update_speeds();
+                                if let Some(extensions) = &point.extensions {
+                                    append_gpx_extensions(context, time_ms, extensions);
+                                }
+                                has_time = true;
+                            }
+                            None => {
+                                // No timestamp, so just make up a time that is greater than the previous one.
+                                let time_ms = context.location_analyzer.next_synthetic_time_ms();
+                                context.location_analyzer.append_location(time_ms, point.lat, point.lon, point.ele);
+                            }
+                        }
                     }
                 }
             }
 
-            // For calculations that only make sense once all the points have been added.
-            context.location_analyzer.analyze();
+            // Only look at the routes if there wasn't a recorded track.
+            if context.location_analyzer.start_time_ms == 0 {
+                for route in gpx.routes {
+                    for point in route.points {
+                        let time_ms = context.location_analyzer.next_synthetic_time_ms();
+                        context.location_analyzer.append_location(time_ms, point.lat, point.lon, point.ele);
+                    }
+                }
+            }
+
+            // Without any timestamps, the best we can do is describe the route.
+            context.location_analyzer.is_route = context.location_analyzer.start_time_ms != 0 && !has_time;
+
+            // Waypoints are points of interest along the route. Those with a timestamp also mark something that happened during the activity.
+            for waypoint in gpx.waypoints {
+                let timestamp_ms = waypoint.get_time_ms();
+                let name = waypoint.name.unwrap_or_else(|| "Waypoint".to_string());
+
+                context.location_analyzer.append_point_of_interest(name.clone(), waypoint.lat, waypoint.lon);
+                if let Some(timestamp_ms) = timestamp_ms {
+                    context.events.push(event::Event::Marker{ timestamp_ms, name });
+                }
+            }
+            true
         }
     }
-
-    // Copy items to the final report.
-    make_final_report(&context)
 }
 
-#[wasm_bindgen]
-pub fn analyze_gpx(s: &str) -> String {
-    utils::set_panic_hook();
-
-    let analysis_report_str;
-    let mut context = analyzer_context::AnalyzerContext::new();
+/// Reads the file with the GPX crate, for files our own parser can't handle. The crate ignores extensions, so only the track is read.
+/// Returns false if the file couldn't be parsed, in which case nothing is added to the context.
+fn read_gpx(s: &str, context: &mut analyzer_context::AnalyzerContext) -> bool {
     let data = BufReader::new(s.as_bytes());
-    let res = gpx::read(data);
 
-    match res {
-        Err(_e) => {
-            // The GPX parser being used sometimes fails on very simple GPX routes,
-            // so try again by running it through our own simple GPX route parser before totally giving up.
-            return analyze_gpx_route(s);
-        }
+    match gpx::read(data) {
+        Err(_e) => false,
         Ok(gpx) => {
+            // Waypoints are points of interest along the route. Those with a timestamp also mark something that happened during the activity.
+            for waypoint in gpx.waypoints {
+                let point = waypoint.point();
+                let name = waypoint.name.unwrap_or_else(|| "Waypoint".to_string());
+
+                context.location_analyzer.append_point_of_interest(name.clone(), point.y(), point.x());
+                if let Some(waypoint_time) = waypoint.time {
+                    let temp: time::OffsetDateTime = waypoint_time.into();
+                    let timestamp_ms = (temp.unix_timestamp() * 1000) as u64;
+
+                    context.events.push(event::Event::Marker{ timestamp_ms, name });
+                }
+            }
+
+            let mut has_time = false;
+
             // Iterate through the tracks.
             for track in gpx.tracks {
 
@@ -204,31 +457,67 @@ pub fn analyze_gpx(s: &str) -> String {
 
                     // Iterate through the points.
                     for point in trackseg.points {
-                        let mut time = 0;
+                        let lat = point.point().y();
+                        let lon = point.point().x();
+                        let alt = point.elevation;
+
                         match point.time {
                             Some(temp_time) => {
                                 let temp: time::OffsetDateTime = temp_time.into();
-                                time = temp.unix_timestamp();
+                                let time_ms = (temp.unix_timestamp() * 1000) as u64;
+
+                                context.location_analyzer.append_location(time_ms, lat, lon, alt);
+                                context.location_analyzer.update_speeds();
+                                has_time = true;
+                            }
+                            None => {
+                                // No timestamp, so just make up a time that is greater than the previous one.
+                                let time_ms = context.location_analyzer.next_synthetic_time_ms();
+                                context.location_analyzer.append_location(time_ms, lat, lon, alt);
                             }
-                            _ => { time += 1; }, // data is from a route so just make up a time that is greater than the previous one.
                         }
+                    }
+                }
+            }
+
+            // Only look at the routes if there wasn't a recorded track.
+            if context.location_analyzer.start_time_ms == 0 {
+                for route in gpx.routes {
+                    for point in route.points {
                         let lat = point.point().y();
                         let lon = point.point().x();
-                        let alt = point.elevation.unwrap();
+                        let time_ms = context.location_analyzer.next_synthetic_time_ms();
 
-                        context.location_analyzer.append_location((time * 1000) as u64, lat, lon, alt);
-                        context.location_analyzer.update_speeds();
+                        context.location_analyzer.append_location(time_ms, lat, lon, point.elevation);
                     }
                 }
             }
 
-            // For calculations that only make sense once all the points have been added.
-            context.location_analyzer.analyze();
-
-            // Copy items to the final report.
-            analysis_report_str = make_final_report(&context);
+            // Without any timestamps, the best we can do is describe the route.
+            context.location_analyzer.is_route = context.location_analyzer.start_time_ms != 0 && !has_time;
+            true
         }
     }
+}
+
+#[wasm_bindgen]
+pub fn analyze_gpx(s: &str) -> String {
+    utils::set_panic_hook();
+
+    let mut context = analyzer_context::AnalyzerContext::new();
+
+    // Our own parser reads the sensor readings from the extensions along with the track. Fall back to the GPX crate
+    // for anything our parser can't handle.
+    if !read_gpx_route(s, &mut context) && !read_gpx(s, &mut context) {
+        alert("Error parsing the GPX file.");
+        return make_final_report(&context);
+    }
+
+    // For calculations that only make sense once all the points have been added.
+    context.analyze();
+
+    // Copy items to the final report.
+    let analysis_report_str = make_final_report(&context);
 
     // Remember this context in case we need it later.
     unsafe {
@@ -264,6 +553,7 @@ pub fn analyze_tcx(s: &str) -> String {
 
                         // Iterate through the laps.
                         for lap in activity.laps {
+                            let mut lap_end_time = None;
 
                             // Iterate through the tracks.
                             for track in lap.tracks {
@@ -271,6 +561,7 @@ pub fn analyze_tcx(s: &str) -> String {
                                 // Iterate through each point.
                                 for trackpoint in track.trackpoints {
                                     let time = trackpoint.time.timestamp() * 1000 + trackpoint.time.timestamp_subsec_millis() as i64;
+                                    lap_end_time = Some(time as u64);
 
                                     // Get the position, including altitude.
                                     let position = trackpoint.position;
@@ -278,15 +569,8 @@ pub fn analyze_tcx(s: &str) -> String {
                                         None => {
                                         }
                                         Some(position) => {
-                                            let altitude = trackpoint.altitude_meters;
-                                            match altitude {
-                                                None => {
-                                                }
-                                                Some(altitude) => {
-                                                    context.location_analyzer.append_location(time as u64, position.latitude, position.longitude, altitude);
-                                                    context.location_analyzer.update_speeds();
-                                                }
-                                            }
+                                            context.location_analyzer.append_location(time as u64, position.latitude, position.longitude, trackpoint.altitude_meters);
+                                            context.location_analyzer.update_speeds();
                                         }
                                     }
 
@@ -337,14 +621,19 @@ pub fn analyze_tcx(s: &str) -> String {
                                     }
                                 }
                             }
+
+                            // Mark the end of the lap.
+                            if let Some(lap_end_time) = lap_end_time {
+                                context.location_analyzer.append_lap(lap_end_time);
+                                context.events.push(event::Event::Lap{ timestamp_ms: lap_end_time });
+                            }
                         }
                     }
                 }
             }
 
             // For calculations that only make sense once all the points have been added.
-            context.location_analyzer.analyze();
-            context.power_analyzer.analyze();
+            context.analyze();
         }
     }
 
@@ -375,6 +664,13 @@ fn callback(timestamp: u32, global_message_num: u16, _local_msg_type: u8, _messa
             callback_context.location_analyzer.set_activity_type(sport_names.get(&sport_id).unwrap().to_string());
         }
 
+        // Open water swims need their own analysis (sub sport 18 == open water).
+        if let Some(sub_sport_id) = msg.sub_sport {
+            if sub_sport_id == 18 {
+                callback_context.location_analyzer.set_activity_type(location_analyzer::TYPE_OPEN_WATER_SWIMMING_KEY.to_string());
+            }
+        }
+
         if let Some(pool_length) = msg.pool_length {
             callback_context.swim_analyzer.set_pool_length(pool_length);
         }
@@ -383,9 +679,14 @@ fn callback(timestamp: u32, global_message_num: u16, _local_msg_type: u8, _messa
         let msg = fit_file::fit_file::FitRecordMsg::new(fields);
         let timestamp_ms = timestamp as u64 * 1000;
 
+        // Ignore anything recorded while the timer was stopped.
+        if !callback_context.location_analyzer.is_timer_running() {
+            return;
+        }
+
         let mut latitude = 0.0;
         let mut longitude = 0.0;
-        let mut altitude = 0.0;
+        let mut altitude = None;
         let mut valid_location = false;
 
         if let Some(lat_semicircles) = msg.position_lat {
@@ -405,13 +706,13 @@ fn callback(timestamp: u32, global_message_num: u16, _local_msg_type: u8, _messa
             }
         }
 
-        // Some devices don't have altitude data, so just zero it out in that case.
+        // Some devices don't have altitude data, so leave it unset in that case.
         if let Some(res) = msg.altitude {
     
             // Make sure we have a valid reading.
             if res != 0xFFFF {
                 // Apply scaling and offset.
-                altitude = (res as f64 / 5.0) - 500.0;
+                altitude = Some((res as f64 / 5.0) - 500.0);
             }
         }
 
@@ -421,7 +722,7 @@ fn callback(timestamp: u32, global_message_num: u16, _local_msg_type: u8, _messa
             // Make sure we have a valid reading.
             if res != 0xFFFF {
                 // Apply scaling and offset.
-                altitude = (res as f64 / 5.0) - 500.0;
+                altitude = Some((res as f64 / 5.0) - 500.0);
             }
         }
 
@@ -441,6 +742,55 @@ fn callback(timestamp: u32, global_message_num: u16, _local_msg_type: u8, _messa
             }
         }
 
+        // Cycling dynamics, from power meters that support it.
+        let mut left_right_balance = None;
+        let mut left_torque_effectiveness = None;
+        let mut right_torque_effectiveness = None;
+        let mut left_pedal_smoothness = None;
+        let mut right_pedal_smoothness = None;
+
+        if let Some(balance) = msg.left_right_balance {
+
+            // Make sure we have a valid reading. The high bit is set when the value is the contribution from the right leg,
+            // otherwise we don't know which leg it refers to.
+            if balance != 0xFF && balance & 0x80 != 0 {
+                left_right_balance = Some((balance & 0x7F) as f64);
+            }
+        }
+        if let Some(effectiveness) = msg.left_torque_effectiveness {
+
+            // Make sure we have a valid reading.
+            if effectiveness != 0xFF {
+                // Apply scaling (stored in half percents).
+                left_torque_effectiveness = Some(effectiveness as f64 / 2.0);
+            }
+        }
+        if let Some(effectiveness) = msg.right_torque_effectiveness {
+
+            // Make sure we have a valid reading.
+            if effectiveness != 0xFF {
+                // Apply scaling (stored in half percents).
+                right_torque_effectiveness = Some(effectiveness as f64 / 2.0);
+            }
+        }
+        if let Some(smoothness) = msg.left_pedal_smoothness {
+
+            // Make sure we have a valid reading.
+            if smoothness != 0xFF {
+                // Apply scaling (stored in half percents).
+                left_pedal_smoothness = Some(smoothness as f64 / 2.0);
+            }
+        }
+        if let Some(smoothness) = msg.right_pedal_smoothness {
+
+            // Make sure we have a valid reading.
+            if smoothness != 0xFF {
+                // Apply scaling (stored in half percents).
+                right_pedal_smoothness = Some(smoothness as f64 / 2.0);
+            }
+        }
+        callback_context.power_analyzer.append_cycling_dynamics(timestamp_ms, left_right_balance, left_torque_effectiveness, right_torque_effectiveness, left_pedal_smoothness, right_pedal_smoothness);
+
         if let Some(temp) = msg.temperature {
 
             // Make sure we have a valid reading.
@@ -449,23 +799,66 @@ fn callback(timestamp: u32, global_message_num: u16, _local_msg_type: u8, _messa
             }
         }
 
+        callback_context.running_dynamics_analyzer.append_fit_record(timestamp_ms, &msg);
+
         if valid_location {
             callback_context.location_analyzer.append_location(timestamp_ms, latitude, longitude, altitude);
             callback_context.location_analyzer.update_speeds();
         }
     }
+    else if global_message_num == fit_file::fit_file::GLOBAL_MSG_NUM_LAP {
+        // The lap message is written when the lap ends.
+        let timestamp_ms = timestamp as u64 * 1000;
+
+        callback_context.location_analyzer.append_lap(timestamp_ms);
+        callback_context.events.push(event::Event::Lap{ timestamp_ms });
+    }
     else if global_message_num == fit_file::fit_file::GLOBAL_MSG_NUM_EVENT {
         let msg = fit_file::fit_file::FitEventMsg::new(fields);
         let timestamp_ms = timestamp as u64 * 1000;
 
         if let Some(event_num) = msg.event {
-            // Front and rear gear change (42 == rear gear change, 43 == front gear change).
-            if event_num == 42 || event_num == 43 {
-                let event = event::Event{ timestamp_ms, event_type: event_num, event_data: 0 };
-                callback_context.events.push(event);
+            // Timer start and stop (event type 0 == start, 1, 4, 8, and 9 are the various types of stop).
+            if event_num == 0 {
+                if let Some(event_type) = msg.event_type {
+                    if event_type == 0 {
+                        callback_context.location_analyzer.start_timer(timestamp_ms);
+                        callback_context.events.push(event::Event::TimerStart{ timestamp_ms });
+                    }
+                    else if event_type == 1 || event_type == 4 || event_type == 8 || event_type == 9 {
+                        callback_context.location_analyzer.stop_timer(timestamp_ms);
+                        callback_context.events.push(event::Event::TimerStop{ timestamp_ms });
+                    }
+                }
+            }
+            // Workout step.
+            else if event_num == 4 {
+                let step_index = msg.data.unwrap_or(0);
+                callback_context.events.push(event::Event::WorkoutStep{ timestamp_ms, step_index });
+            }
+            // Course point.
+            else if event_num == 10 {
+                callback_context.events.push(event::Event::Marker{ timestamp_ms, name: "Course Point".to_string() });
+            }
+            // Battery low.
+            else if event_num == 22 {
+                callback_context.events.push(event::Event::BatteryLow{ timestamp_ms });
+            }
+            // User marker, such as a point of interest saved during the activity.
+            else if event_num == 32 {
+                callback_context.events.push(event::Event::Marker{ timestamp_ms, name: "Marker".to_string() });
+            }
+            // Front and rear gear change (42 == front gear change, 43 == rear gear change).
+            else if event_num == 42 || event_num == 43 {
+                let change = callback_context.gear_analyzer.append_gear_change(gear_analyzer::GearChange::from_event_data(timestamp_ms, msg.data.unwrap_or(0)));
+                callback_context.events.push(event::Event::GearChange{ timestamp_ms, front_gear_num: change.front_gear_num, front_gear_teeth: change.front_gear_teeth,
+                    rear_gear_num: change.rear_gear_num, rear_gear_teeth: change.rear_gear_teeth });
             }
             // Radar threat alert.
             else if event_num == 75 {
+                let threat = callback_context.radar_analyzer.append_threat(radar_analyzer::RadarThreat::from_event_data(timestamp_ms, msg.data.unwrap_or(0)));
+                callback_context.events.push(event::Event::RadarThreat{ timestamp_ms, threat_level: threat.threat_level, threat_count: threat.threat_count,
+                    approach_speed: threat.approach_speed });
             }
         }
     }
@@ -500,8 +893,7 @@ pub fn analyze_fit(s: &[u8]) -> String {
         }
         Ok(_res) => {
             // For calculations that only make sense once all the points have been added.
-            context.location_analyzer.analyze();
-            context.power_analyzer.analyze();
+            context.analyze();
         }
     }
 
@@ -520,6 +912,167 @@ pub fn analyze_fit(s: &[u8]) -> String {
     analysis_report_str
 }
 
+/// Times in the file that are relative to the start of the activity are added to start_time_ms (milliseconds since the epoch).
+/// Pass zero if the start time isn't known, in which case files with relative times are rejected.
+#[wasm_bindgen]
+pub fn analyze_csv(s: &str, start_time_ms: f64) -> String {
+    utils::set_panic_hook();
+
+    let mut context = analyzer_context::AnalyzerContext::new();
+    let mut error = false;
+
+    match csv_reader::read(s, start_time_ms as u64) {
+        Err(e) => {
+            alert(&e);
+            error = true;
+        }
+        Ok(activity) => {
+            for row in activity.rows {
+                let time_ms = row.time_ms;
+
+                // Use the location if there is one. Otherwise, the distance column (if there is one) is the best we can do,
+                // such as for a treadmill or an indoor trainer.
+                if activity.has_location {
+                    if let (Some(latitude), Some(longitude)) = (row.latitude, row.longitude) {
+                        context.location_analyzer.append_location(time_ms, latitude, longitude, row.altitude);
+                        context.location_analyzer.update_speeds();
+                    }
+                }
+                else if let Some(distance) = row.distance {
+                    context.location_analyzer.append_distance(time_ms, distance);
+                    context.location_analyzer.update_speeds();
+                }
+
+                if let Some(heart_rate) = row.heart_rate {
+                    context.hr_analyzer.append_sensor_value(time_ms, heart_rate);
+                }
+                if let Some(cadence) = row.cadence {
+                    context.cadence_analyzer.append_sensor_value(time_ms, cadence);
+                }
+                if let Some(power) = row.power {
+                    context.power_analyzer.append_sensor_value(time_ms, power);
+                }
+                if let Some(temperature) = row.temperature {
+                    context.temperature_analyzer.append_sensor_value(time_ms, temperature);
+                }
+            }
+
+            // For calculations that only make sense once all the points have been added.
+            context.analyze();
+        }
+    }
+
+    let mut analysis_report_str = "".to_string();
+
+    if !error {
+        // Copy items to the final report.
+        analysis_report_str = make_final_report(&context);
+
+        // Remember this context in case we need it later.
+        unsafe {
+            CONTEXT_LIST.contexts.push(context);
+        }
+    }
+
+    analysis_report_str
+}
+
+/// Analyzes the tracks read from a KML or KMZ file, in the same way as a GPX file.
+fn analyze_kml_document(document: kml_reader::KmlDocument) -> String {
+    let mut context = analyzer_context::AnalyzerContext::new();
+
+    // Recorded activities are stored as gx:Track, with timestamps. Planned routes are stored as a LineString, without them.
+    // If there are any timestamps then ignore the untimed tracks, they're probably routes drawn alongside the activity.
+    let has_time = document.tracks.iter().any(|track| track.points.iter().any(|point| point.time_ms.is_some()));
+
+    for track in document.tracks {
+        for point in track.points {
+            match point.time_ms {
+                Some(time_ms) => {
+                    // Skip anything out of order, the distance and speed calculations require the points to be in time order.
+                    if time_ms < context.location_analyzer.last_time_ms {
+                        continue;
+                    }
+                    context.location_analyzer.append_location(time_ms, point.lat, point.lon, point.alt);
+                    context.location_analyzer.update_speeds();
+                }
+                None => {
+                    if !has_time {
+                        // No timestamp, so just make up a time that is greater than the previous one.
+                        let time_ms = context.location_analyzer.next_synthetic_time_ms();
+                        context.location_analyzer.append_location(time_ms, point.lat, point.lon, point.alt);
+                    }
+                }
+            }
+        }
+    }
+
+    // Without any timestamps, the best we can do is describe the route.
+    context.location_analyzer.is_route = context.location_analyzer.start_time_ms != 0 && !has_time;
+
+    // Placemarks with a single point are points of interest along the route.
+    for placemark in document.placemarks {
+        let name = placemark.name.unwrap_or_else(|| "Placemark".to_string());
+        context.location_analyzer.append_point_of_interest(name, placemark.lat, placemark.lon);
+    }
+
+    // For calculations that only make sense once all the points have been added.
+    context.analyze();
+
+    // Copy items to the final report.
+    let analysis_report_str = make_final_report(&context);
+
+    // Remember this context in case we need it later.
+    unsafe {
+        CONTEXT_LIST.contexts.push(context);
+    }
+
+    analysis_report_str
+}
+
+#[wasm_bindgen]
+pub fn analyze_kml(s: &str) -> String {
+    utils::set_panic_hook();
+
+    match kml_reader::read(s.as_bytes()) {
+        Ok(document) => analyze_kml_document(document),
+        Err(_e) => {
+            alert("Error parsing the KML file.");
+            "".to_string()
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub fn analyze_kmz(s: &[u8]) -> String {
+    utils::set_panic_hook();
+
+    match kml_reader::read_kmz(s) {
+        Ok(document) => analyze_kml_document(document),
+        Err(_e) => {
+            alert("Error parsing the KMZ file.");
+            "".to_string()
+        }
+    }
+}
+
+/// GPX, GeoJSON and KML require a position for every point, so activities without location data can't be exported in those formats.
+/// Tells the user which formats to use instead, and returns false, if that's what was asked for.
+fn check_export_format(context: &analyzer_context::AnalyzerContext, format: &str) -> bool {
+    let format_name = match format.to_lowercase().as_str() {
+        "gpx" => "GPX",
+        "geojson" => "GeoJSON",
+        "kml" => "KML",
+        _ => return true,
+    };
+
+    if context.location_analyzer.times.is_empty() {
+        alert(&format!("Activities without location data, such as indoor rides, can't be exported as {}. Export them as TCX or FIT instead.", format_name));
+        return false;
+    }
+    true
+}
+
 #[wasm_bindgen]
 pub fn export_data(format: &str, split_start: u32, split_end: u32) -> String {
     utils::set_panic_hook();
@@ -528,7 +1081,10 @@ pub fn export_data(format: &str, split_start: u32, split_end: u32) -> String {
 
     unsafe {
         if !CONTEXT_LIST.contexts.is_empty() {
-            let exporter = exporter::Exporter::new();
+            if !check_export_format(CONTEXT_LIST.contexts.last().unwrap(), format) {
+                return exported_data;
+            }
+            let exporter = make_exporter();
             exported_data = exporter.export(CONTEXT_LIST.contexts.last().unwrap(), format, (split_start as u64) * 1000, (split_end as u64) * 1000);
         }
         else {
@@ -540,32 +1096,388 @@ pub fn export_data(format: &str, split_start: u32, split_end: u32) -> String {
 }
 
 #[wasm_bindgen]
-pub fn merge(format: &str) -> String {
+pub fn export_binary_data(format: &str, split_start: u32, split_end: u32) -> Vec<u8> {
     utils::set_panic_hook();
 
-    let mut merged_data = String::new();
+    let mut exported_data = Vec::new();
 
     unsafe {
-        if  CONTEXT_LIST.contexts.len() > 1 {
-            let merge_tool = merge_tool::MergeTool::new();
-            let exporter = exporter::Exporter::new();
-            let merged_context = merge_tool.merge(&CONTEXT_LIST.contexts[0], &CONTEXT_LIST.contexts[1]);
-
-            merged_data = exporter.export(&merged_context, format, 0, 0);
+        if !CONTEXT_LIST.contexts.is_empty() {
+            if !check_export_format(CONTEXT_LIST.contexts.last().unwrap(), format) {
+                return exported_data;
+            }
+            let exporter = make_exporter();
+            exported_data = exporter.export_binary(CONTEXT_LIST.contexts.last().unwrap(), format, (split_start as u64) * 1000, (split_end as u64) * 1000);
         }
         else {
+            alert("Nothing to export.");
+        }
+    }
+
+    exported_data
+}
+
+/// Sets the options used by [`merge`] and [`merge_activities`]. The priorities are "average", "first" or "second", and say which
+/// of the activities each sensor's readings are taken from. Gaps in the preferred activity's readings are filled from the others.
+#[wasm_bindgen]
+pub fn set_merge_options(detect_offset: bool, location: &str, heart_rate: &str, cadence: &str, power: &str, temperature: &str) {
+    utils::set_panic_hook();
+
+    let mut priorities = Vec::new();
+    for name in [location, heart_rate, cadence, power, temperature] {
+        match merge_tool::SensorPriority::from_name(name) {
+            Some(priority) => priorities.push(priority),
+            None => {
+                alert(&format!("Unknown sensor priority: {}", name));
+                return;
+            }
+        }
+    }
+
+    MERGE_OPTIONS.with_borrow_mut(|options| {
+        options.detect_offset = detect_offset;
+        options.location = priorities[0];
+        options.heart_rate = priorities[1];
+        options.cadence = priorities[2];
+        options.power = priorities[3];
+        options.temperature = priorities[4];
+    });
+}
+
+/// Merges the stored activities with the given indices, in that order. Returns None, after telling the user why,
+/// if they can't be merged or the result can't be exported in the given format.
+fn merge_contexts(indices: &[usize], format: &str) -> Option<analyzer_context::AnalyzerContext> {
+    unsafe {
+        let mut contexts = Vec::new();
+        for index in indices {
+            match CONTEXT_LIST.contexts.get(*index) {
+                Some(context) => contexts.push(context),
+                None => {
+                    alert(&format!("There is no activity {}.", index));
+                    return None;
+                }
+            }
+        }
+
+        let merge_tool = MERGE_OPTIONS.with_borrow(|options| merge_tool::MergeTool::with_options(options.clone()));
+        match merge_tool.merge_all(&contexts) {
+            Some(merged_context) => {
+                if !check_export_format(&merged_context, format) {
+                    return None;
+                }
+                Some(merged_context)
+            }
+            None => {
+                alert("Nothing to merge.");
+                None
+            }
+        }
+    }
+}
+
+/// Merges the first two stored activities.
+fn merge_first_two(format: &str) -> Option<analyzer_context::AnalyzerContext> {
+    unsafe {
+        if CONTEXT_LIST.contexts.len() < 2 {
             alert("Nothing to merge.");
+            return None;
+        }
+    }
+    merge_contexts(&[0, 1], format)
+}
+
+/// Parses a comma separated list of activity indices. Returns None, after telling the user why, if any of them are invalid or repeated.
+fn parse_indices(indices: &str) -> Option<Vec<usize>> {
+    let mut parsed_indices = Vec::new();
+    for index in indices.split(',').map(|index| index.trim()).filter(|index| !index.is_empty()) {
+        match index.parse::<usize>() {
+            Ok(index) if parsed_indices.contains(&index) => {
+                alert(&format!("Activity {} is listed more than once.", index));
+                return None;
+            }
+            Ok(index) => parsed_indices.push(index),
+            Err(_e) => {
+                alert(&format!("Invalid activity index: {}", index));
+                return None;
+            }
+        }
+    }
+    Some(parsed_indices)
+}
+
+/// Exports the merged activity, if there is one.
+fn export_merged(merged_context: Option<analyzer_context::AnalyzerContext>, format: &str) -> String {
+    match merged_context {
+        Some(merged_context) => {
+            let exporter = make_exporter();
+            exporter.export(&merged_context, format, 0, 0)
+        }
+        None => String::new(),
+    }
+}
+
+/// Same as [`export_merged`], but also handles binary formats, such as FIT.
+fn export_merged_binary(merged_context: Option<analyzer_context::AnalyzerContext>, format: &str) -> Vec<u8> {
+    match merged_context {
+        Some(merged_context) => {
+            let exporter = make_exporter();
+            exporter.export_binary(&merged_context, format, 0, 0)
+        }
+        None => Vec::new(),
+    }
+}
+
+/// Merges the first two stored activities and exports the result. Use merge_activities to choose which activities to merge.
+#[wasm_bindgen]
+pub fn merge(format: &str) -> String {
+    utils::set_panic_hook();
+
+    export_merged(merge_first_two(format), format)
+}
+
+/// Same as [`merge`], but also handles binary formats, such as FIT.
+#[wasm_bindgen]
+pub fn merge_binary(format: &str) -> Vec<u8> {
+    utils::set_panic_hook();
+
+    export_merged_binary(merge_first_two(format), format)
+}
+
+/// Merges the stored activities with the given indices (comma separated, in order of preference) and exports the result.
+#[wasm_bindgen]
+pub fn merge_activities(indices: &str, format: &str) -> String {
+    utils::set_panic_hook();
+
+    export_merged(parse_indices(indices).and_then(|indices| merge_contexts(&indices, format)), format)
+}
+
+/// Same as [`merge_activities`], but also handles binary formats, such as FIT.
+#[wasm_bindgen]
+pub fn merge_activities_binary(indices: &str, format: &str) -> Vec<u8> {
+    utils::set_panic_hook();
+
+    export_merged_binary(parse_indices(indices).and_then(|indices| merge_contexts(&indices, format)), format)
+}
+
+/// Returns the number of stored activities. Each analyzed or edited activity is stored, in order, so it can be merged later.
+#[wasm_bindgen]
+pub fn num_activities() -> usize {
+    unsafe { CONTEXT_LIST.contexts.len() }
+}
+
+/// Removes the stored activity with the given index. Later activities move down one place.
+#[wasm_bindgen]
+pub fn remove_activity(index: usize) {
+    utils::set_panic_hook();
+
+    unsafe {
+        if index < CONTEXT_LIST.contexts.len() {
+            CONTEXT_LIST.contexts.remove(index);
+        }
+        else {
+            alert(&format!("There is no activity {}.", index));
         }
     }
+}
 
-    merged_data
+/// Removes all of the stored activities.
+#[wasm_bindgen]
+pub fn clear_activities() {
+    unsafe {
+        CONTEXT_LIST.contexts.clear();
+    }
+}
+
+/// Keeps only the part of the stored activity with the given index between the two offsets (in seconds from the start).
+/// The result is analyzed and stored, so it can be exported, and its report is returned.
+#[wasm_bindgen]
+pub fn crop_activity(index: usize, start_offset: u32, end_offset: u32) -> String {
+    utils::set_panic_hook();
+
+    let mut analysis_report_str = "".to_string();
+
+    unsafe {
+        match CONTEXT_LIST.contexts.get(index) {
+            Some(context) => {
+                let start_time_ms = context.start_time_ms();
+                let edit_tool = edit_tool::EditTool::new();
+                let cropped_context = edit_tool.crop(context, start_time_ms + (start_offset as u64) * 1000, start_time_ms + (end_offset as u64) * 1000);
+
+                analysis_report_str = make_final_report(&cropped_context);
+                CONTEXT_LIST.contexts.push(cropped_context);
+            }
+            None => {
+                alert(&format!("There is no activity {}.", index));
+            }
+        }
+    }
+
+    analysis_report_str
+}
+
+/// Removes the part of the stored activity with the given index between the two offsets (in seconds from the start),
+/// such as when the watch was left running in the car. The result is analyzed and stored, and its report is returned.
+#[wasm_bindgen]
+pub fn remove_activity_range(index: usize, start_offset: u32, end_offset: u32) -> String {
+    utils::set_panic_hook();
+
+    let mut analysis_report_str = "".to_string();
+
+    unsafe {
+        match CONTEXT_LIST.contexts.get(index) {
+            Some(context) => {
+                let start_time_ms = context.start_time_ms();
+                let edit_tool = edit_tool::EditTool::new();
+                let edited_context = edit_tool.remove_range(context, start_time_ms + (start_offset as u64) * 1000, start_time_ms + (end_offset as u64) * 1000);
+
+                analysis_report_str = make_final_report(&edited_context);
+                CONTEXT_LIST.contexts.push(edited_context);
+            }
+            None => {
+                alert(&format!("There is no activity {}.", index));
+            }
+        }
+    }
+
+    analysis_report_str
+}
+
+/// Splits the stored activity with the given index in two at the offset (in seconds from the start). Both halves are
+/// analyzed and stored, the second one last, and their reports are returned as a JSON array.
+#[wasm_bindgen]
+pub fn split_activity(index: usize, split_offset: u32) -> String {
+    utils::set_panic_hook();
+
+    let mut analysis_report_str = "".to_string();
+
+    unsafe {
+        match CONTEXT_LIST.contexts.get(index) {
+            Some(context) => {
+                let split_time_ms = context.start_time_ms() + (split_offset as u64) * 1000;
+                let edit_tool = edit_tool::EditTool::new();
+                let (first_context, second_context) = edit_tool.split(context, split_time_ms);
+
+                analysis_report_str = format!("[{},{}]", make_final_report(&first_context), make_final_report(&second_context));
+                CONTEXT_LIST.contexts.push(first_context);
+                CONTEXT_LIST.contexts.push(second_context);
+            }
+            None => {
+                alert(&format!("There is no activity {}.", index));
+            }
+        }
+    }
+
+    analysis_report_str
+}
+
+#[wasm_bindgen]
+pub fn live_start(activity_type: &str, max_hr: f64) {
+    utils::set_panic_hook();
+
+    LIVE_SESSION.with_borrow_mut(|session| *session = Some(live_session::LiveSession::new(activity_type, max_hr)));
+}
+
+// The live_append_* functions are called for every reading, so they quietly ignore readings that arrive without a session.
+
+#[wasm_bindgen]
+pub fn live_append_location(timestamp_ms: f64, latitude: f64, longitude: f64, altitude: Option<f64>) {
+    LIVE_SESSION.with_borrow_mut(|session| {
+        if let Some(session) = session {
+            session.append_location(timestamp_ms as u64, latitude, longitude, altitude);
+        }
+    });
+}
+
+#[wasm_bindgen]
+pub fn live_append_heart_rate(timestamp_ms: f64, value: f64) {
+    LIVE_SESSION.with_borrow_mut(|session| {
+        if let Some(session) = session {
+            session.append_heart_rate(timestamp_ms as u64, value);
+        }
+    });
+}
+
+#[wasm_bindgen]
+pub fn live_append_cadence(timestamp_ms: f64, value: f64) {
+    LIVE_SESSION.with_borrow_mut(|session| {
+        if let Some(session) = session {
+            session.append_cadence(timestamp_ms as u64, value);
+        }
+    });
+}
+
+#[wasm_bindgen]
+pub fn live_append_power(timestamp_ms: f64, value: f64) {
+    LIVE_SESSION.with_borrow_mut(|session| {
+        if let Some(session) = session {
+            session.append_power(timestamp_ms as u64, value);
+        }
+    });
+}
+
+#[wasm_bindgen]
+pub fn live_lap(timestamp_ms: f64) {
+    LIVE_SESSION.with_borrow_mut(|session| {
+        if let Some(session) = session {
+            session.append_lap(timestamp_ms as u64);
+        }
+    });
+}
+
+#[wasm_bindgen]
+pub fn live_snapshot() -> String {
+    utils::set_panic_hook();
+
+    LIVE_SESSION.with_borrow(|session| {
+        match session {
+            Some(session) => serde_json::json!(session.snapshot()).to_string(),
+            None => {
+                alert("A live session has not been started.");
+                "".to_string()
+            }
+        }
+    })
+}
+
+#[wasm_bindgen]
+pub fn live_finish() -> String {
+    utils::set_panic_hook();
+
+    let mut analysis_report_str = "".to_string();
+
+    match LIVE_SESSION.with_borrow_mut(|session| session.take()) {
+        Some(session) => {
+            let context = session.finish();
+            analysis_report_str = make_final_report(&context);
+
+            // Remember this context so it can be exported or merged, same as a loaded file.
+            unsafe {
+                CONTEXT_LIST.contexts.push(context);
+            }
+        }
+        None => {
+            alert("A live session has not been started.");
+        }
+    }
+
+    analysis_report_str
 }
 
 #[cfg(test)]
 mod tests {
     use std::io::Read;
     use std::fs::File;
-    use crate::analyze_tcx;
+    use serde_json::Value;
+    use std::sync::{Mutex, MutexGuard};
+    use crate::{analyze_gpx, analyze_tcx, make_final_report, read_gpx_route, set_report_options};
+    use crate::analyzer_context::AnalyzerContext;
+
+    // The analyze functions store each activity in CONTEXT_LIST, so tests that call them take turns.
+    static CONTEXT_LIST_LOCK: Mutex<()> = Mutex::new(());
+
+    fn lock_context_list() -> MutexGuard<'static, ()> {
+        CONTEXT_LIST_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
 
     /// Downloads a remote file to the local file path.
     fn download_test_file(local_file_name: &str, remote_file_name: &str) {
@@ -593,8 +1505,133 @@ mod tests {
         analyze_tcx(&content)
     }
 
+    /// A ten minute ride with a location and a heart rate reading every second.
+    fn make_context() -> AnalyzerContext {
+        let mut context = AnalyzerContext::new();
+        for index in 0..600 {
+            let time_ms = 1600000000000 + index * 1000;
+            context.location_analyzer.append_location(time_ms, 40.0 + index as f64 * 0.0001, -75.0, Some(100.0));
+            context.location_analyzer.update_speeds();
+            context.hr_analyzer.append_sensor_value(time_ms, 120.0 + (index % 10) as f64);
+        }
+        context.analyze();
+        context
+    }
+
+    #[test]
+    fn test_report_options() {
+        let context = make_context();
+        let full_report: Value = serde_json::from_str(&make_final_report(&context)).unwrap();
+        assert_eq!(full_report["Times"].as_array().unwrap().len(), 600);
+        assert_eq!(full_report["Heart Rate Readings"].as_array().unwrap().len(), 600);
+
+        // Thinned out series.
+        set_report_options(true, 100);
+        let report: Value = serde_json::from_str(&make_final_report(&context)).unwrap();
+        assert!(report["Times"].as_array().unwrap().len() <= 100);
+        assert_eq!(report["Heart Rate Readings"].as_array().unwrap().len(), 100);
+        assert_eq!(report["Total Distance"], full_report["Total Distance"]);
+
+        // No series at all, but the same summary.
+        set_report_options(false, 0);
+        let report: Value = serde_json::from_str(&make_final_report(&context)).unwrap();
+        assert!(report.get("Times").is_none());
+        assert!(report.get("Heart Rate Readings").is_none());
+        assert!(report.get("Distance Readings").is_none());
+        assert_eq!(report["Average Heart Rate"], full_report["Average Heart Rate"]);
+        assert_eq!(report["KM Splits"], full_report["KM Splits"]);
+
+        set_report_options(true, 0);
+    }
+
+    #[test]
+    fn test_gpx_subsecond_times() {
+        let gpx = r#"<gpx version="1.1" creator="test"><trk><trkseg>
+            <trkpt lat="40.0" lon="-75.0"><time>2020-09-13T12:26:40.250Z</time><extensions><power>200</power></extensions></trkpt>
+            <trkpt lat="40.0001" lon="-75.0"><time>2020-09-13T12:26:40.750Z</time><extensions><power>210</power></extensions></trkpt>
+            <trkpt lat="40.0002" lon="-75.0"><time>2020-09-13T12:26:41.250Z</time><extensions><power>220</power></extensions></trkpt>
+            </trkseg></trk></gpx>"#;
+
+        let mut context = AnalyzerContext::new();
+        assert!(read_gpx_route(gpx, &mut context));
+        assert_eq!(context.location_analyzer.times, vec![1600000000250, 1600000000750, 1600000001250]);
+        assert_eq!(context.power_analyzer.time_readings, context.location_analyzer.times);
+    }
+
+    #[test]
+    fn test_analyze_gpx_route() {
+        let _lock = lock_context_list();
+
+        // A planned route, with no timestamps, that climbs 50 meters over the first half kilometer and then descends.
+        let altitudes = [100.0, 110.0, 120.0, 130.0, 140.0, 150.0, 145.0, 140.0, 135.0, 130.0, 120.0];
+        let points: Vec<String> = altitudes.iter().enumerate()
+            .map(|(index, altitude)| format!(r#"<rtept lat="{}" lon="-75.0"><ele>{}</ele></rtept>"#, 40.0 + index as f64 * 0.001, altitude))
+            .collect();
+        let gpx = format!(r#"<gpx version="1.1" creator="test"><wpt lat="40.005" lon="-75.0001"><name>Summit</name></wpt><rte><name>Hill</name>{}</rte></gpx>"#,
+            points.concat());
+
+        let report: Value = serde_json::from_str(&analyze_gpx(&gpx)).unwrap();
+        assert_eq!(report["Route"], true);
+        assert!(report.get("Timer Time").is_none());
+
+        let total_distance = report["Total Distance"].as_f64().unwrap();
+        assert!((total_distance - 1112.0).abs() < 5.0);
+        assert_eq!(report["Maximum Altitude"], 150.0);
+        assert_eq!(report["Total Vertical Distance"], 50.0);
+
+        let climbs = report["Climbs"].as_array().unwrap();
+        assert_eq!(climbs.len(), 1);
+        assert_eq!(climbs[0]["start_altitude"], 100.0);
+        assert_eq!(climbs[0]["end_altitude"], 150.0);
+        assert!((climbs[0]["end_distance"].as_f64().unwrap() - total_distance / 2.0).abs() < 1.0);
+
+        // The waypoint is placed at the nearest point along the route.
+        let points_of_interest = report["Points of Interest"].as_array().unwrap();
+        assert_eq!(points_of_interest.len(), 1);
+        assert_eq!(points_of_interest[0]["name"], "Summit");
+        assert_eq!(points_of_interest[0]["distance"], climbs[0]["end_distance"]);
+    }
+
+    #[test]
+    fn test_analyze_gpx_without_elevation_or_time() {
+        let _lock = lock_context_list();
+
+        // Neither elevation nor time, so all we can do is describe the route, without anything that needs an altitude.
+        let points: Vec<String> = (0..10).map(|index| format!(r#"<trkpt lat="{}" lon="-75.0"></trkpt>"#, 40.0 + index as f64 * 0.001)).collect();
+        let gpx = format!(r#"<gpx version="1.1" creator="test"><trk><trkseg>{}</trkseg></trk></gpx>"#, points.concat());
+
+        let report: Value = serde_json::from_str(&analyze_gpx(&gpx)).unwrap();
+        assert_eq!(report["Route"], true);
+        assert!(report["Total Distance"].as_f64().unwrap() > 1000.0);
+        assert!(report["Maximum Altitude"].is_null());
+        assert!(report["Total Vertical Distance"].is_null());
+        assert!(report["Altitude Readings"].is_null());
+        assert!(report["Climbs"].as_array().unwrap().is_empty());
+
+        // Untimed points are given made up times that keep increasing.
+        let mut context = AnalyzerContext::new();
+        assert!(read_gpx_route(&gpx, &mut context));
+        assert_eq!(context.location_analyzer.times.len(), 10);
+        assert!(context.location_analyzer.times[0] > 0);
+        assert!(context.location_analyzer.times.windows(2).all(|pair| pair[1] > pair[0]));
+
+        // Timestamps, but no elevation, gives an activity report without the altitude metrics. A point missing
+        // its timestamp is placed after the one before it.
+        let gpx = r#"<gpx version="1.1" creator="test"><trk><trkseg>
+            <trkpt lat="40.0" lon="-75.0"><time>2020-09-13T12:26:40Z</time></trkpt>
+            <trkpt lat="40.0001" lon="-75.0"><time>2020-09-13T12:26:41Z</time></trkpt>
+            <trkpt lat="40.0002" lon="-75.0"></trkpt>
+            </trkseg></trk></gpx>"#;
+        let report: Value = serde_json::from_str(&analyze_gpx(gpx)).unwrap();
+        assert!(report.get("Route").is_none());
+        assert!(report["Total Vertical Distance"].is_null());
+        assert!(report["Altitude Readings"].is_null());
+        assert_eq!(report["Times"], serde_json::json!([1600000000000u64, 1600000001000u64, 1600000002000u64]));
+    }
+
     #[test]
     fn file1_test() {
+        let _lock = lock_context_list();
         let local_file_name = "tests/20180810_zwift_innsbruckring_x2.tcx";
         let remote_file_name = "https://github.com/msimms/TestFilesForFitnessApps/raw/master/tcx/20180810_zwift_innsbruckring_x2.tcx";
         let result = test_file(local_file_name, remote_file_name);
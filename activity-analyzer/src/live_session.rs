@@ -0,0 +1,182 @@
+// Copyright (c) 2021 Michael J. Simms. All rights reserved.
+
+use crate::analyzer_context::AnalyzerContext;
+use crate::heart_rate_analyzer::HeartRateAnalyzer;
+use crate::event::Event;
+use serde::Serialize;
+
+const SHORT_POWER_SECONDS: u64 = 3; // Window for the short power average, smooths out the pedal stroke
+const LONG_POWER_SECONDS: u64 = 30; // Window for the long power average
+
+/// The current state of a live activity. Cheap to compute, so it can be requested after every reading.
+#[derive(Serialize)]
+pub struct LiveSnapshot {
+    pub timestamp_ms: u64, // Time of the most recent reading
+    pub timer_seconds: u64, // Elapsed time, not counting time spent with the timer stopped
+    pub total_distance: f64, // Meters
+    pub current_speed: f64, // Meters/second
+    pub avg_speed: f64, // Meters/second
+    pub lap: usize, // Current lap number, starting with 1
+    pub lap_seconds: u64,
+    pub lap_distance: f64, // Meters
+    pub heart_rate: Option<f64>, // Most recent reading
+    pub heart_rate_zone: Option<u8>, // Zone of the most recent reading, 0 if below zone 1
+    pub cadence: Option<f64>, // Most recent reading
+    pub power_3_sec: Option<f64>, // Average power (in watts) over the last three seconds
+    pub power_30_sec: Option<f64>, // Average power (in watts) over the last thirty seconds
+}
+
+/// Wraps an [`AnalyzerContext`] that is fed one reading at a time, as they are recorded.
+pub struct LiveSession {
+    pub context: AnalyzerContext,
+    pub max_hr: f64, // Used to compute the heart rate zone
+    last_time_ms: u64, // Time of the most recent reading, from any source
+    lap_start_time_ms: u64,
+    lap_start_distance: f64,
+}
+
+impl LiveSession {
+    /// Creates a new [`LiveSession`].
+    pub fn new(activity_type: &str, max_hr: f64) -> Self {
+        let mut context = AnalyzerContext::new();
+        context.location_analyzer.set_activity_type(activity_type.to_string());
+
+        LiveSession{ context, max_hr, last_time_ms: 0, lap_start_time_ms: 0, lap_start_distance: 0.0 }
+    }
+
+    /// Notes the time of the latest reading.
+    fn update_time(&mut self, date_time_ms: u64) {
+        if self.lap_start_time_ms == 0 {
+            self.lap_start_time_ms = date_time_ms;
+        }
+        if date_time_ms > self.last_time_ms {
+            self.last_time_ms = date_time_ms;
+        }
+    }
+
+    pub fn append_location(&mut self, date_time_ms: u64, latitude: f64, longitude: f64, altitude: Option<f64>) {
+        self.update_time(date_time_ms);
+        self.context.location_analyzer.append_location(date_time_ms, latitude, longitude, altitude);
+        self.context.location_analyzer.update_speeds();
+    }
+
+    pub fn append_heart_rate(&mut self, date_time_ms: u64, value: f64) {
+        self.update_time(date_time_ms);
+        self.context.hr_analyzer.append_sensor_value(date_time_ms, value);
+    }
+
+    pub fn append_cadence(&mut self, date_time_ms: u64, value: f64) {
+        self.update_time(date_time_ms);
+        self.context.cadence_analyzer.append_sensor_value(date_time_ms, value);
+    }
+
+    pub fn append_power(&mut self, date_time_ms: u64, value: f64) {
+        self.update_time(date_time_ms);
+        self.context.power_analyzer.append_sensor_value(date_time_ms, value);
+    }
+
+    /// Ends the current lap and starts the next one.
+    pub fn append_lap(&mut self, date_time_ms: u64) {
+        self.update_time(date_time_ms);
+        self.context.location_analyzer.append_lap(date_time_ms);
+        self.context.events.push(Event::Lap{ timestamp_ms: date_time_ms });
+        self.lap_start_time_ms = date_time_ms;
+        self.lap_start_distance = self.context.location_analyzer.total_distance;
+    }
+
+    /// Returns the current state of the activity.
+    pub fn snapshot(&self) -> LiveSnapshot {
+        let location_analyzer = &self.context.location_analyzer;
+        let heart_rate = self.context.hr_analyzer.readings.last().copied();
+        let heart_rate_zone = heart_rate.map(|heart_rate| HeartRateAnalyzer::compute_zone(heart_rate, self.max_hr));
+
+        LiveSnapshot{
+            timestamp_ms: self.last_time_ms,
+            timer_seconds: location_analyzer.get_timer_seconds(),
+            total_distance: location_analyzer.total_distance,
+            current_speed: location_analyzer.current_speed,
+            avg_speed: location_analyzer.avg_speed,
+            lap: location_analyzer.lap_times.len() + 1,
+            lap_seconds: (self.last_time_ms - self.lap_start_time_ms) / 1000,
+            lap_distance: location_analyzer.total_distance - self.lap_start_distance,
+            heart_rate,
+            heart_rate_zone,
+            cadence: self.context.cadence_analyzer.readings.last().copied(),
+            power_3_sec: self.context.power_analyzer.compute_trailing_average(self.last_time_ms, SHORT_POWER_SECONDS),
+            power_30_sec: self.context.power_analyzer.compute_trailing_average(self.last_time_ms, LONG_POWER_SECONDS),
+        }
+    }
+
+    /// Ends the session, running the calculations that need all the data, and returns the context.
+    pub fn finish(mut self) -> AnalyzerContext {
+        if self.last_time_ms > 0 {
+            self.append_lap(self.last_time_ms);
+        }
+        self.context.analyze();
+        self.context
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::live_session::LiveSession;
+
+    const START_TIME_MS: u64 = 1600000000000;
+
+    /// Records riding north at about 10 m/s over the given seconds, with a reading from every sensor each second.
+    fn ride(session: &mut LiveSession, start_second: u64, end_second: u64) {
+        for second in start_second..end_second {
+            let time_ms = START_TIME_MS + second * 1000;
+
+            session.append_location(time_ms, 45.0 + second as f64 * 0.00009, -75.0, Some(100.0));
+            session.append_heart_rate(time_ms, 150.0);
+            session.append_cadence(time_ms, 90.0);
+            session.append_power(time_ms, if second % 2 == 0 { 200.0 } else { 300.0 });
+        }
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut session = LiveSession::new("Cycling", 200.0);
+
+        // Nothing recorded yet.
+        let snapshot = session.snapshot();
+        assert_eq!(snapshot.timestamp_ms, 0);
+        assert_eq!(snapshot.lap, 1);
+        assert!(snapshot.heart_rate.is_none());
+        assert!(snapshot.power_3_sec.is_none());
+
+        ride(&mut session, 0, 61);
+        let snapshot = session.snapshot();
+        assert_eq!(snapshot.timestamp_ms, START_TIME_MS + 60000);
+        assert_eq!(snapshot.timer_seconds, 60);
+        assert!((snapshot.total_distance - 600.0).abs() < 10.0);
+        assert_eq!(snapshot.heart_rate, Some(150.0));
+        assert_eq!(snapshot.heart_rate_zone, Some(3)); // 75% of max
+        assert_eq!(snapshot.cadence, Some(90.0));
+
+        // Power alternates between 200 and 300, so the averages depend on how many of each are in the window.
+        assert_eq!(snapshot.power_3_sec, Some(700.0 / 3.0));
+        assert_eq!(snapshot.power_30_sec, Some(250.0));
+    }
+
+    #[test]
+    fn test_laps() {
+        let mut session = LiveSession::new("Cycling", 200.0);
+
+        ride(&mut session, 0, 31);
+        session.append_lap(START_TIME_MS + 30000);
+        let distance_at_lap = session.snapshot().total_distance;
+
+        ride(&mut session, 31, 51);
+        let snapshot = session.snapshot();
+        assert_eq!(snapshot.lap, 2);
+        assert_eq!(snapshot.lap_seconds, 20);
+        assert!((snapshot.lap_distance - (snapshot.total_distance - distance_at_lap)).abs() < 0.000001);
+        assert!((snapshot.lap_distance - 200.0).abs() < 5.0);
+
+        // Finishing the session ends the last lap.
+        let context = session.finish();
+        assert_eq!(context.location_analyzer.lap_times, vec![START_TIME_MS + 30000, START_TIME_MS + 50000]);
+    }
+}
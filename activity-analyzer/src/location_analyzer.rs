@@ -23,6 +23,21 @@ pub const BEST_CENTURY: &str = "Best Century";
 const TYPE_UNSPECIFIED_ACTIVITY_KEY: &str = "Unknown";
 const TYPE_RUNNING_KEY: &str = "Running";
 const TYPE_CYCLING_KEY: &str = "Cycling";
+pub const TYPE_OPEN_WATER_SWIMMING_KEY: &str = "Open Water Swimming";
+
+const OPEN_WATER_SMOOTHING_WINDOW: usize = 9; // Number of GPS points averaged together when smoothing an open water swim track
+const OPEN_WATER_SIGHTING_METERS: f64 = 50.0; // Distance (in meters) between the sighting points of an open water swim
+const METERS_PER_SWIM_SPLIT: f64 = 100.0;
+
+const SYNTHETIC_TIME_STEP_MS: u64 = 1000; // Spacing of the made up timestamps given to points that don't have one
+
+const MAX_PLAUSIBLE_SPEED: f64 = 50.0; // Meters/second, a location that implies anything faster than this is a GPS glitch
+const MAX_CONSECUTIVE_OUTLIERS: usize = 5; // After this many, assume the earlier location was the glitch and accept the new one
+const ALTITUDE_SMOOTHING_WINDOW: usize = 5; // Number of altitude readings averaged together when smoothing the elevation profile
+
+const MIN_CLIMB_GAIN_METERS: f64 = 20.0; // Minimum altitude gain (in meters) for a section to be considered a climb
+const MIN_CLIMB_GRADIENT: f64 = 3.0; // Minimum average gradient (percent) for a section to be considered a climb
+const CLIMB_END_DESCENT_METERS: f64 = 10.0; // A descent (in meters) of this much from the high point ends the climb
 
 #[derive(Clone, Copy, Serialize)]
 pub struct IntervalDescription {
@@ -44,14 +59,42 @@ impl Default for IntervalDescription {
     }
 }
 
+/// A period of time during which the timer was stopped.
+#[derive(Clone, Copy, Serialize)]
+pub struct PauseDescription {
+    pub start_time: u64,
+    pub end_time: u64,
+}
+
+/// A sustained climb, located by its distance along the track.
+#[derive(Clone, Copy, Serialize)]
+pub struct ClimbDescription {
+    pub start_distance: f64, // Distance (in meters) from the start of the track
+    pub end_distance: f64, // Distance (in meters) from the start of the track
+    pub start_altitude: f64,
+    pub end_altitude: f64,
+    pub avg_gradient: f64, // Percent
+}
+
+/// A named location, such as a GPX waypoint.
+#[derive(Clone, Serialize)]
+pub struct PointOfInterest {
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub distance: Option<f64>, // Distance (in meters) along the track to the closest point, if there is a track
+}
+
 struct DistanceNode {
     date_time_ms: u64,
+    moving_time_ms: u64, // Time since the start of the activity, not counting time spent with the timer stopped
     total_distance: f64, // Distance traveled (in meters)
 }
 
 pub struct LocationAnalyzer {
     pub start_time_ms: u64, // First timestamp
     pub last_time_ms: u64, // Most recent timestamp
+    last_moving_time_ms: u64, // Most recent timestamp, relative to the start and not counting time spent with the timer stopped
     last_lat: f64, // Most recent latitude reading
     last_lon: f64, // Most recent longitude reading
     last_alt: f64, // Most recent altitude reading
@@ -67,6 +110,7 @@ pub struct LocationAnalyzer {
     pub latitude_readings: Vec<f64>,
     pub longitude_readings: Vec<f64>,
     pub altitude_graph: Vec<f64>, // Holds all altitude readings
+    pub distance_readings: Vec<f64>, // Holds the distance (in meters) traveled at each point
     pub gradient_curve: Vec<f64>, // Holds the gradient calculations
     pub gap_graph: Vec<u64>, // Holds the grade adjusted pace calculations
 
@@ -79,10 +123,27 @@ pub struct LocationAnalyzer {
 
     pub bests: HashMap<String, u64>,
     pub max_altitude: f64,
+    pub has_altitude: bool, // False if none of the points had an altitude reading, in which case the altitude metrics are meaningless
 
     pub activity_type: String,
 
     pub significant_intervals: Vec<IntervalDescription>,
+    pub climbs: Vec<ClimbDescription>,
+    pub points_of_interest: Vec<PointOfInterest>,
+
+    pub is_route: bool, // True if the data is a planned route, in which case the times are made up and there's no timing data to analyze
+
+    pub pause_intervals: Vec<PauseDescription>, // Periods during which the timer was stopped
+    paused_ms: u64, // Total time spent with the timer stopped, not counting the current pause
+    pause_start_ms: Option<u64>, // Start of the current pause, if the timer is stopped
+    skip_next_distance: bool, // Set when the timer restarts, so that distance covered while stopped is not counted
+
+    pub smoothed_latitude_readings: Vec<f64>, // Open water swims only, the track after the GPS zig-zag has been smoothed out
+    pub smoothed_longitude_readings: Vec<f64>, // Open water swims only, the track after the GPS zig-zag has been smoothed out
+    pub swim_splits: Vec<u64>, // Open water swims only, seconds taken to swim each 100 meters, not counting time spent with the timer stopped
+    pub sighting_efficiencies: Vec<f64>, // Open water swims only, straight line distance divided by distance swum, for each pair of sighting points
+    pub swim_efficiency: f64, // Open water swims only, straight line distance divided by distance swum, for the whole swim
+
     pub geo_analyzer: super::geojson::GeoJson,
 
     speed_window_size: u64,
@@ -91,28 +152,204 @@ pub struct LocationAnalyzer {
 
 impl LocationAnalyzer {
     pub fn new() -> Self {
-        LocationAnalyzer{ start_time_ms: 0, last_time_ms: 0, last_lat: 0.0, last_lon: 0.0, last_alt: 0.0, distance_buf: Vec::new(), speed_times: Vec::new(),
+        LocationAnalyzer{ start_time_ms: 0, last_time_ms: 0, last_moving_time_ms: 0, last_lat: 0.0, last_lon: 0.0, last_alt: 0.0, distance_buf: Vec::new(), speed_times: Vec::new(),
             speed_graph: Vec::new(), total_distance: 0.0, total_vertical: 0.0, times: Vec::new(), lap_times: Vec::new(), latitude_readings: Vec::new(), longitude_readings: Vec::new(),
-            altitude_graph: Vec::new(), gradient_curve: Vec::new(), gap_graph: Vec::new(), mile_splits: Vec::new(), km_splits: Vec::new(), avg_speed: 0.0, current_speed: 0.0,
-            speed_variance: 0.0, bests: HashMap::new(), max_altitude: 0.0, activity_type: TYPE_UNSPECIFIED_ACTIVITY_KEY.to_string(), significant_intervals: Vec::new(),
+            altitude_graph: Vec::new(), distance_readings: Vec::new(), gradient_curve: Vec::new(), gap_graph: Vec::new(), mile_splits: Vec::new(), km_splits: Vec::new(), avg_speed: 0.0, current_speed: 0.0,
+            speed_variance: 0.0, bests: HashMap::new(), max_altitude: 0.0, has_altitude: false, activity_type: TYPE_UNSPECIFIED_ACTIVITY_KEY.to_string(), significant_intervals: Vec::new(),
+            climbs: Vec::new(), points_of_interest: Vec::new(), is_route: false, pause_intervals: Vec::new(), paused_ms: 0, pause_start_ms: None, skip_next_distance: false,
+            smoothed_latitude_readings: Vec::new(), smoothed_longitude_readings: Vec::new(), swim_splits: Vec::new(), sighting_efficiencies: Vec::new(), swim_efficiency: 0.0,
             geo_analyzer: super::geojson::GeoJson::new(), speed_window_size: 1, last_speed_buf_update_time: 0 }
     }
 
-    /// Accessor methods for lap metadata.
-    pub fn get_lap_start_time(&self, lap_num: u8) -> u64 {
-        if lap_num == 1 {
+    /// Returns the number of laps. The lap button marks the end of a lap, and whatever follows the last press is one more lap.
+    pub fn get_num_laps(&self) -> usize {
+        if self.start_time_ms == 0 {
+            return 0;
+        }
+        self.lap_times.iter().filter(|lap_time| **lap_time > self.start_time_ms && **lap_time < self.last_time_ms).count() + 1
+    }
+
+    /// Accessor methods for lap metadata. Laps are numbered from one.
+    pub fn get_lap_start_time(&self, lap_num: usize) -> u64 {
+        if lap_num <= 1 {
             return self.start_time_ms;
         }
-        0
+        self.get_lap_end_time(lap_num - 1)
+    }
+    pub fn get_lap_end_time(&self, lap_num: usize) -> u64 {
+        let lap_ends: Vec<u64> = self.lap_times.iter().copied().filter(|lap_time| *lap_time > self.start_time_ms && *lap_time < self.last_time_ms).collect();
+        match lap_ends.get(lap_num.saturating_sub(1)) {
+            Some(lap_end) => *lap_end,
+            None => self.last_time_ms,
+        }
     }
-    pub fn get_lap_seconds(&self, _lap_num: u8) -> u64 {
-        0
+    pub fn get_lap_seconds(&self, lap_num: usize) -> f64 {
+        let start_time_ms = self.get_lap_start_time(lap_num);
+        let end_time_ms = self.get_lap_end_time(lap_num);
+        (end_time_ms - start_time_ms).saturating_sub(self.get_paused_ms_between(start_time_ms, end_time_ms)) as f64 / 1000.0
     }
-    pub fn get_lap_calories(&self, _lap_num: u8) -> f64 {
+    pub fn get_lap_calories(&self, _lap_num: usize) -> f64 {
         0.0
     }
-    pub fn get_lap_distance(&self, _lap_num: u8) -> f64 {
-        0.0
+    pub fn get_lap_distance(&self, lap_num: usize) -> f64 {
+        self.get_distance_at(self.get_lap_end_time(lap_num)) - self.get_distance_at(self.get_lap_start_time(lap_num))
+    }
+
+    /// Returns the time (in milliseconds) between the two timestamps during which the timer was stopped.
+    pub fn get_paused_ms_between(&self, start_time_ms: u64, end_time_ms: u64) -> u64 {
+        self.pause_intervals.iter().map(|pause| {
+            let overlap_start = u64::max(pause.start_time, start_time_ms);
+            let overlap_end = u64::min(pause.end_time, end_time_ms);
+            overlap_end.saturating_sub(overlap_start)
+        }).sum()
+    }
+
+    /// Returns the times and total distances (in meters) reported by the device, for activities without location data
+    /// (see append_distance), starting with zero at the start time. Empty for activities with a track, whose distances
+    /// are in distance_readings.
+    pub fn get_indoor_distances(&self) -> (Vec<u64>, Vec<f64>) {
+        if !self.times.is_empty() || self.distance_buf.is_empty() {
+            return (Vec::new(), Vec::new());
+        }
+        std::iter::once((self.start_time_ms, 0.0)).chain(self.distance_buf.iter().map(|node| (node.date_time_ms, node.total_distance))).unzip()
+    }
+
+    /// Returns the distance (in meters) traveled as of the given timestamp, from the last location (or, without a track,
+    /// the last distance reported by the device) at or before it.
+    pub fn get_distance_at(&self, date_time_ms: u64) -> f64 {
+        if self.times.is_empty() {
+            let index = self.distance_buf.partition_point(|node| node.date_time_ms <= date_time_ms);
+            if index == 0 {
+                return 0.0;
+            }
+            return self.distance_buf[index - 1].total_distance;
+        }
+
+        let index = self.times.partition_point(|time| *time <= date_time_ms);
+        if index == 0 {
+            return 0.0;
+        }
+        self.distance_readings[index - 1]
+    }
+
+    /// Notes the end of a lap.
+    pub fn append_lap(&mut self, date_time_ms: u64) {
+        self.lap_times.push(date_time_ms);
+    }
+
+    /// Notes that the timer was stopped. The caller is expected to discard anything recorded until the timer restarts.
+    pub fn stop_timer(&mut self, date_time_ms: u64) {
+        if self.pause_start_ms.is_none() {
+            self.pause_start_ms = Some(date_time_ms);
+        }
+    }
+
+    /// Notes that the timer was restarted, closing out the current pause.
+    pub fn start_timer(&mut self, date_time_ms: u64) {
+        if let Some(pause_start_ms) = self.pause_start_ms {
+            if date_time_ms > pause_start_ms {
+                self.pause_intervals.push(PauseDescription{ start_time: pause_start_ms, end_time: date_time_ms });
+
+                // Pauses before the first location reading don't affect anything.
+                if self.start_time_ms != 0 && pause_start_ms >= self.start_time_ms {
+                    self.paused_ms += date_time_ms - pause_start_ms;
+                }
+            }
+            self.pause_start_ms = None;
+            self.skip_next_distance = true;
+        }
+    }
+
+    /// Returns false if the timer is currently stopped.
+    pub fn is_timer_running(&self) -> bool {
+        self.pause_start_ms.is_none()
+    }
+
+    /// Returns the elapsed time (in seconds), not counting time spent with the timer stopped.
+    pub fn get_timer_seconds(&self) -> u64 {
+        (self.last_time_ms - self.start_time_ms).saturating_sub(self.paused_ms) / 1000
+    }
+
+    /// Converts a time (in seconds) relative to the start of the activity, not counting time spent with the timer stopped,
+    /// back to a timestamp (in milliseconds). Used to find the time at which each split ended.
+    pub fn moving_seconds_to_timestamp(&self, moving_seconds: u64) -> u64 {
+        let mut timestamp_ms = self.start_time_ms + moving_seconds * 1000;
+
+        for pause in self.pause_intervals.iter() {
+            if pause.start_time <= timestamp_ms && pause.start_time >= self.start_time_ms {
+                timestamp_ms += pause.end_time - pause.start_time;
+            }
+        }
+        timestamp_ms
+    }
+
+    /// Returns a made up timestamp for a point that doesn't have one, a fixed step after the previous point.
+    /// Since zero means "not set", the first made up timestamp is one step after zero.
+    pub fn next_synthetic_time_ms(&self) -> u64 {
+        self.last_time_ms + SYNTHETIC_TIME_STEP_MS
+    }
+
+    /// Returns the altitude of the specified point, or None if the activity doesn't have altitude data.
+    pub fn get_altitude(&self, point_index: usize) -> Option<f64> {
+        if self.has_altitude {
+            return Some(self.altitude_graph[point_index]);
+        }
+        None
+    }
+
+    /// Returns the altitude readings, or None if the activity doesn't have altitude data.
+    pub fn get_altitude_readings(&self) -> Option<&Vec<f64>> {
+        if self.has_altitude {
+            return Some(&self.altitude_graph);
+        }
+        None
+    }
+
+    /// Returns the altitude readings with the barometric/GPS noise smoothed out, or None if the activity doesn't have altitude data.
+    pub fn get_smoothed_altitude_readings(&self) -> Option<Vec<f64>> {
+        self.get_altitude_readings().map(|altitude_readings| LocationAnalyzer::moving_average(altitude_readings, ALTITUDE_SMOOTHING_WINDOW))
+    }
+
+    /// Flags the locations that would require an implausible speed to reach from the previous good location.
+    /// The result has one entry per location, true for each outlier.
+    pub fn find_location_outliers(&self) -> Vec<bool> {
+        let num_points = self.latitude_readings.len();
+        let mut outliers = vec![false; num_points];
+        let mut prev_index: Option<usize> = None;
+        let mut num_consecutive = 0;
+
+        for (point_index, outlier) in outliers.iter_mut().enumerate() {
+            if let Some(prev_index) = prev_index {
+                let meters = distance::haversine_distance(self.latitude_readings[point_index], self.longitude_readings[point_index], 0.0,
+                    self.latitude_readings[prev_index], self.longitude_readings[prev_index], 0.0);
+                let seconds = (self.times[point_index] - self.times[prev_index]) as f64 / 1000.0;
+
+                if seconds > 0.0 && meters / seconds > MAX_PLAUSIBLE_SPEED && num_consecutive < MAX_CONSECUTIVE_OUTLIERS {
+                    *outlier = true;
+                    num_consecutive += 1;
+                    continue;
+                }
+            }
+            prev_index = Some(point_index);
+            num_consecutive = 0;
+        }
+        outliers
+    }
+
+    /// Returns the total ascent (in meters), or None if the activity doesn't have altitude data.
+    pub fn get_total_vertical(&self) -> Option<f64> {
+        if self.has_altitude {
+            return Some(self.total_vertical);
+        }
+        None
+    }
+
+    /// Returns the highest altitude (in meters), or None if the activity doesn't have altitude data.
+    pub fn get_max_altitude(&self) -> Option<f64> {
+        if self.has_altitude {
+            return Some(self.max_altitude);
+        }
+        None
     }
 
     /// Accessor for setting the activity type.
@@ -129,6 +366,42 @@ impl LocationAnalyzer {
         }
     }
 
+    /// Returns true if the activity is an open water swim, in which case the track needs special handling.
+    pub fn is_open_water_swim(&self) -> bool {
+        self.activity_type == TYPE_OPEN_WATER_SWIMMING_KEY
+    }
+
+    /// Returns the average pace (in seconds per 100 meters), for swims.
+    pub fn get_pace_per_100m(&self) -> f64 {
+        if self.avg_speed > 0.0 {
+            return METERS_PER_SWIM_SPLIT / self.avg_speed;
+        }
+        0.0
+    }
+
+    /// Returns the index of the location reading closest in time to the given timestamp, or None if there are no readings.
+    pub fn find_nearest_index(&self, date_time_ms: u64) -> Option<usize> {
+        if self.times.is_empty() {
+            return None;
+        }
+
+        match self.times.binary_search(&date_time_ms) {
+            Ok(index) => Some(index),
+            Err(index) => {
+                if index == 0 {
+                    return Some(0);
+                }
+                if index >= self.times.len() {
+                    return Some(self.times.len() - 1);
+                }
+                if date_time_ms - self.times[index - 1] <= self.times[index] - date_time_ms {
+                    return Some(index - 1);
+                }
+                Some(index)
+            }
+        }
+    }
+
     /// Computes the average speed of the workout. Called by 'append_location'.
     fn update_average_speed(&mut self, elapsed_seconds: u64) {
         if elapsed_seconds > 0 {
@@ -325,9 +598,223 @@ impl LocationAnalyzer {
         }
     }
 
+    /// Centered moving average. The result is the same length as the input. Near the ends the window shrinks evenly on
+    /// both sides, so that the ends aren't pulled towards the middle, which would shorten a smoothed track.
+    fn moving_average(values: &[f64], window_size: usize) -> Vec<f64> {
+        let num_values = values.len();
+        let half_window = window_size / 2;
+        let mut result = Vec::with_capacity(num_values);
+
+        for i in 0..num_values {
+            let half_window = usize::min(half_window, usize::min(i, num_values - 1 - i));
+            let start_index = i - half_window;
+            let end_index = i + half_window + 1;
+            let sum: f64 = Iterator::sum(values[start_index..end_index].iter());
+            result.push(sum / (end_index - start_index) as f64);
+        }
+        result
+    }
+
+    /// Returns true if the timer was restarted after the first time and no later than the second. Distance between points
+    /// on either side of a restart wasn't covered with the timer running, so it isn't counted.
+    fn timer_restarted_between(&self, start_time_ms: u64, end_time_ms: u64) -> bool {
+        self.pause_intervals.iter().any(|pause| pause.end_time > start_time_ms && pause.end_time <= end_time_ms)
+    }
+
+    /// Recomputes the distance readings, splits, speeds and bests from the distance covered since the previous point,
+    /// for each point, replacing those that were computed from the raw track as the points were added.
+    fn rebuild_distances(&mut self, meters_per_point: &[f64]) {
+        self.distance_buf.clear();
+        self.distance_readings.clear();
+        self.speed_times.clear();
+        self.speed_graph.clear();
+        self.km_splits.clear();
+        self.mile_splits.clear();
+        self.bests.clear();
+        self.total_distance = 0.0;
+        self.current_speed = 0.0;
+        self.last_moving_time_ms = 0;
+        self.last_speed_buf_update_time = 0;
+
+        for (point_index, meters_traveled) in meters_per_point.iter().enumerate() {
+            let date_time_ms = self.times[point_index];
+            let moving_time_ms = (date_time_ms - self.start_time_ms).saturating_sub(self.get_paused_ms_between(self.start_time_ms, date_time_ms));
+            let elapsed_seconds = moving_time_ms / 1000;
+
+            self.total_distance += meters_traveled;
+            self.distance_readings.push(self.total_distance);
+            if point_index == 0 {
+                continue;
+            }
+
+            self.distance_buf.push(DistanceNode{ date_time_ms, moving_time_ms, total_distance: self.total_distance });
+            self.last_moving_time_ms = moving_time_ms;
+            self.update_average_speed(elapsed_seconds);
+            self.do_km_split_check(elapsed_seconds);
+            self.do_mile_split_check(elapsed_seconds);
+            self.update_speeds();
+        }
+    }
+
+    /// The GPS loses its signal every time the watch goes underwater, so an open water swim track zig-zags
+    /// and greatly overestimates the distance swum. Smooth the track and recompute the distance, splits,
+    /// speeds and bests from the smoothed version. Each stretch between pauses is smoothed separately, so that
+    /// wherever the swimmer went while the timer was stopped doesn't bleed into the track on either side.
+    fn analyze_open_water_swim(&mut self) {
+        let num_points = self.latitude_readings.len();
+        if num_points < 2 {
+            return;
+        }
+
+        let mut stretch_starts: Vec<usize> = (1..num_points).filter(|point_index| self.timer_restarted_between(self.times[point_index - 1], self.times[*point_index])).collect();
+        stretch_starts.insert(0, 0);
+        stretch_starts.push(num_points);
+
+        self.smoothed_latitude_readings.clear();
+        self.smoothed_longitude_readings.clear();
+        for stretch in stretch_starts.windows(2) {
+            self.smoothed_latitude_readings.extend(LocationAnalyzer::moving_average(&self.latitude_readings[stretch[0]..stretch[1]], OPEN_WATER_SMOOTHING_WINDOW));
+            self.smoothed_longitude_readings.extend(LocationAnalyzer::moving_average(&self.longitude_readings[stretch[0]..stretch[1]], OPEN_WATER_SMOOTHING_WINDOW));
+        }
+
+        let lats = &self.smoothed_latitude_readings;
+        let lons = &self.smoothed_longitude_readings;
+        let mut meters_per_point = vec![0.0; num_points];
+        for stretch in stretch_starts.windows(2) {
+            for point_index in stretch[0] + 1..stretch[1] {
+                meters_per_point[point_index] = distance::haversine_distance(lats[point_index], lons[point_index], 0.0, lats[point_index - 1], lons[point_index - 1], 0.0);
+            }
+        }
+
+        let mut swim_distance = 0.0;
+        let mut next_split_distance = METERS_PER_SWIM_SPLIT;
+        let mut last_split_time_ms = self.start_time_ms;
+        let mut total_straight_line_distance = 0.0;
+        let mut total_sighted_distance = 0.0;
+
+        for stretch in stretch_starts.windows(2) {
+            let mut sighting_index = stretch[0];
+            let mut distance_since_sighting = 0.0;
+
+            for point_index in stretch[0] + 1..stretch[1] {
+                let meters_swum = meters_per_point[point_index];
+                swim_distance += meters_swum;
+                distance_since_sighting += meters_swum;
+
+                // Did we finish another 100 meters?
+                while swim_distance >= next_split_distance {
+                    let split_time_ms = self.times[point_index];
+                    let split_ms = (split_time_ms - last_split_time_ms).saturating_sub(self.get_paused_ms_between(last_split_time_ms, split_time_ms));
+                    self.swim_splits.push(split_ms / 1000);
+                    last_split_time_ms = split_time_ms;
+                    next_split_distance += METERS_PER_SWIM_SPLIT;
+                }
+
+                // Compare the distance swum since the last sighting point with the straight line distance between them.
+                if distance_since_sighting >= OPEN_WATER_SIGHTING_METERS || (point_index == stretch[1] - 1 && distance_since_sighting > 0.0) {
+                    let straight_line_distance = distance::haversine_distance(lats[point_index], lons[point_index], 0.0, lats[sighting_index], lons[sighting_index], 0.0);
+
+                    self.sighting_efficiencies.push(straight_line_distance / distance_since_sighting);
+                    total_straight_line_distance += straight_line_distance;
+                    total_sighted_distance += distance_since_sighting;
+                    sighting_index = point_index;
+                    distance_since_sighting = 0.0;
+                }
+            }
+        }
+
+        if total_sighted_distance > 0.0 {
+            self.swim_efficiency = total_straight_line_distance / total_sighted_distance;
+        }
+
+        // Replace the distance calculations that were made from the raw track.
+        self.rebuild_distances(&meters_per_point);
+        self.update_average_speed(self.get_timer_seconds());
+    }
+
+    /// Adds a named location, such as a waypoint. Its distance along the track is computed once all the points have been loaded.
+    pub fn append_point_of_interest(&mut self, name: String, latitude: f64, longitude: f64) {
+        self.points_of_interest.push(PointOfInterest{ name, latitude, longitude, distance: None });
+    }
+
+    /// Finds the distance along the track of each point of interest, using the closest point on the track.
+    fn locate_points_of_interest(&mut self) {
+        for poi in self.points_of_interest.iter_mut() {
+            let mut closest_meters = f64::MAX;
+
+            for (point_index, distance_traveled) in self.distance_readings.iter().enumerate() {
+                let meters = distance::haversine_distance(poi.latitude, poi.longitude, 0.0, self.latitude_readings[point_index], self.longitude_readings[point_index], 0.0);
+
+                if meters < closest_meters {
+                    closest_meters = meters;
+                    poi.distance = Some(*distance_traveled);
+                }
+            }
+        }
+    }
+
+    /// Saves the section between the two points as a climb, if it is long and steep enough.
+    fn add_climb(&mut self, start_index: usize, end_index: usize) {
+        let start_altitude = self.altitude_graph[start_index];
+        let end_altitude = self.altitude_graph[end_index];
+        let start_distance = self.distance_readings[start_index];
+        let end_distance = self.distance_readings[end_index];
+        let gain = end_altitude - start_altitude;
+        let length = end_distance - start_distance;
+
+        if gain >= MIN_CLIMB_GAIN_METERS && length > 0.0 {
+            let avg_gradient = (gain / length) * 100.0;
+
+            if avg_gradient >= MIN_CLIMB_GRADIENT {
+                self.climbs.push(ClimbDescription{ start_distance, end_distance, start_altitude, end_altitude, avg_gradient });
+            }
+        }
+    }
+
+    /// Looks for sustained climbs in the altitude profile. A climb runs from a low point to the following high point,
+    /// and ends once the track has descended far enough from the high point.
+    fn search_for_climbs(&mut self) {
+        self.climbs.clear();
+
+        let num_points = self.altitude_graph.len();
+        if num_points < 2 {
+            return;
+        }
+
+        let mut low_index = 0;
+        let mut high_index = 0;
+
+        for point_index in 1..num_points {
+            let altitude = self.altitude_graph[point_index];
+
+            if self.altitude_graph[high_index] - altitude >= CLIMB_END_DESCENT_METERS {
+                self.add_climb(low_index, high_index);
+                low_index = point_index;
+                high_index = point_index;
+            }
+            else if altitude < self.altitude_graph[low_index] {
+                low_index = point_index;
+                high_index = point_index;
+            }
+            else if altitude > self.altitude_graph[high_index] {
+                high_index = point_index;
+            }
+        }
+        self.add_climb(low_index, high_index);
+    }
+
     /// Called after all data is loaded.
     pub fn analyze(&mut self) {
-        self.search_for_intervals();
+        if self.is_open_water_swim() {
+            self.analyze_open_water_swim();
+        }
+        if self.has_altitude {
+            self.search_for_climbs();
+        }
+        self.locate_points_of_interest();
+        if !self.is_route {
+            self.search_for_intervals();
+        }
     }
 
     /// Computes the average speed over the last mile. Called by 'append_location'.
@@ -340,9 +827,9 @@ impl LocationAnalyzer {
         let time_distance_iter = self.distance_buf.iter().rev();
         for time_distance_node in time_distance_iter {
 
-            // Convert time from ms to seconds - seconds from this point to the end of the activity.
+            // Convert time from ms to seconds - seconds from this point to the end of the activity, not counting time spent with the timer stopped.
             let current_time_ms = time_distance_node.date_time_ms;
-            let total_seconds = (self.last_time_ms - current_time_ms) / 1000;
+            let total_seconds = (self.last_moving_time_ms - time_distance_node.moving_time_ms) / 1000;
             if total_seconds == 0 {
                 continue;
             }
@@ -444,25 +931,49 @@ impl LocationAnalyzer {
         }
     }
 
-    pub fn append_location(&mut self, date_time_ms: u64, latitude: f64, longitude: f64, altitude: f64) {
+    pub fn append_location(&mut self, date_time_ms: u64, latitude: f64, longitude: f64, altitude_reading: Option<f64>) {
 
-        // Not much we can do with the first location other than note the start time.
+        // Not every point (or every file) has altitude. Carry the last known altitude forward so the altitude graph
+        // stays in step with the other readings, but only compute altitude changes between actual readings.
+        let altitude = altitude_reading.unwrap_or(self.last_alt);
+        let altitude_change_valid = altitude_reading.is_some() && self.has_altitude;
+        if altitude_reading.is_some() {
+            self.has_altitude = true;
+        }
+
+        // Not much we can do with the first location other than note the start time, but keep it as part of
+        // the track so that anything rebuilt from the track (merges, edits, exports) doesn't lose it.
         if self.start_time_ms == 0 {
             self.start_time_ms = date_time_ms;
+            self.times.push(date_time_ms);
+            self.latitude_readings.push(latitude);
+            self.longitude_readings.push(longitude);
+            self.altitude_graph.push(altitude);
+            self.distance_readings.push(0.0);
+
+            if altitude_reading.is_some() && altitude > self.max_altitude {
+                self.max_altitude = altitude;
+            }
         }
 
         // Update the total distance calculation.
         else if self.last_time_ms != 0 {
 
-            // How far since the last point?
-            let meters_traveled = distance::haversine_distance(latitude, longitude, altitude, self.last_lat, self.last_lon, self.last_alt);
+            // How far since the last point? Don't count anything covered while the timer was stopped.
+            let mut meters_traveled = distance::haversine_distance(latitude, longitude, altitude, self.last_lat, self.last_lon, self.last_alt);
+            if self.skip_next_distance {
+                meters_traveled = 0.0;
+                self.skip_next_distance = false;
+            }
 
-            // How long has it been?
-            let elapsed_seconds = (date_time_ms - self.start_time_ms) / 1000;
+            // How long has it been, not counting time spent with the timer stopped?
+            let moving_time_ms = (date_time_ms - self.start_time_ms).saturating_sub(self.paused_ms);
+            let elapsed_seconds = moving_time_ms / 1000;
 
             // Compute the grade adjusted pace.
             let num_alts = self.altitude_graph.len();
-            if num_alts > 0 {
+            // Nothing to compute when we haven't moved, e.g., the first point after the timer was restarted.
+            if num_alts > 0 && altitude_change_valid && meters_traveled > 0.0 {
                 let prev_alt = self.altitude_graph[num_alts - 1];
                 let gradient = (altitude - prev_alt) / meters_traveled;
 
@@ -471,17 +982,19 @@ impl LocationAnalyzer {
 
             // Update totals and averages.
             let new_distance = self.total_distance + meters_traveled;
-            let distance_node = DistanceNode{ date_time_ms: date_time_ms, total_distance: new_distance };
+            let distance_node = DistanceNode{ date_time_ms: date_time_ms, moving_time_ms, total_distance: new_distance };
             self.distance_buf.push(distance_node);
             self.total_distance = new_distance;
             let vertical = altitude - self.last_alt;
-            if vertical > 0.0 {
+            if vertical > 0.0 && altitude_change_valid {
                 self.total_vertical += vertical;
             }
+            self.last_moving_time_ms = moving_time_ms;
             self.times.push(date_time_ms);
             self.latitude_readings.push(latitude);
             self.longitude_readings.push(longitude);
             self.altitude_graph.push(altitude);
+            self.distance_readings.push(new_distance);
             self.update_average_speed(elapsed_seconds);
 
             // Update the split calculations.
@@ -489,7 +1002,7 @@ impl LocationAnalyzer {
             self.do_mile_split_check(elapsed_seconds);
 
             // Update max altitude.
-            if altitude > self.max_altitude {
+            if altitude_reading.is_some() && altitude > self.max_altitude {
                 self.max_altitude = altitude;
             }
         }
@@ -499,6 +1012,37 @@ impl LocationAnalyzer {
         self.last_lon = longitude;
         self.last_alt = altitude;
     }
+
+    /// For activities without location data, such as those recorded on a treadmill or an indoor trainer, where the
+    /// device only reports the total distance (in meters) traveled so far. Updates the distance and speed calculations,
+    /// but not the track.
+    pub fn append_distance(&mut self, date_time_ms: u64, total_distance: f64) {
+
+        // Not much we can do with the first reading other than note the start time.
+        if self.start_time_ms == 0 {
+            self.start_time_ms = date_time_ms;
+        }
+        else if self.last_time_ms != 0 {
+
+            // How long has it been, not counting time spent with the timer stopped?
+            let moving_time_ms = (date_time_ms - self.start_time_ms).saturating_sub(self.paused_ms);
+            let elapsed_seconds = moving_time_ms / 1000;
+
+            // Distance should never go backwards, but some devices reset it.
+            let new_distance = f64::max(total_distance, self.total_distance);
+            let distance_node = DistanceNode{ date_time_ms, moving_time_ms, total_distance: new_distance };
+            self.distance_buf.push(distance_node);
+            self.total_distance = new_distance;
+            self.last_moving_time_ms = moving_time_ms;
+            self.update_average_speed(elapsed_seconds);
+
+            // Update the split calculations.
+            self.do_km_split_check(elapsed_seconds);
+            self.do_mile_split_check(elapsed_seconds);
+        }
+
+        self.last_time_ms = date_time_ms;
+    }
 }
 
 impl Default for LocationAnalyzer {
@@ -506,3 +1050,148 @@ impl Default for LocationAnalyzer {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::location_analyzer::{LocationAnalyzer, BEST_1K, TYPE_OPEN_WATER_SWIMMING_KEY, TYPE_RUNNING_KEY};
+
+    const START_TIME_MS: u64 = 1600000000000;
+    const DEGREES_PER_SECOND: f64 = 0.00001; // About 1.1 meters/second heading north
+
+    /// Creates an open water swim analyzer and feeds it a point every second. The function returns the point's latitude and longitude.
+    fn make_swim(num_points: u64, point: impl Fn(u64) -> (f64, f64)) -> LocationAnalyzer {
+        let mut analyzer = LocationAnalyzer::new();
+        analyzer.set_activity_type(TYPE_OPEN_WATER_SWIMMING_KEY.to_string());
+        for index in 0..num_points {
+            let (lat, lon) = point(index);
+            analyzer.append_location(START_TIME_MS + index * 1000, lat, lon, None);
+        }
+        analyzer
+    }
+
+    #[test]
+    fn test_moving_average() {
+        assert_eq!(LocationAnalyzer::moving_average(&[0.0, 0.0, 9.0, 0.0, 0.0], 3), vec![0.0, 3.0, 3.0, 3.0, 0.0]);
+
+        // The window shrinks at the ends rather than running off them, and stays centered.
+        assert_eq!(LocationAnalyzer::moving_average(&[1.0, 2.0, 3.0], 9), vec![1.0, 2.0, 3.0]);
+        assert_eq!(LocationAnalyzer::moving_average(&[1.0, 5.0, 3.0, 4.0, 9.0], 5), vec![1.0, 3.0, 4.4, 16.0 / 3.0, 9.0]);
+        assert!(LocationAnalyzer::moving_average(&[], 9).is_empty());
+    }
+
+    #[test]
+    fn test_open_water_straight_line() {
+        let mut analyzer = make_swim(300, |index| (40.0 + index as f64 * DEGREES_PER_SECOND, -75.0));
+        analyzer.analyze();
+
+        assert!((analyzer.swim_efficiency - 1.0).abs() < 0.01);
+        assert!(analyzer.sighting_efficiencies.iter().all(|efficiency| (efficiency - 1.0).abs() < 0.01));
+        assert_eq!(analyzer.swim_splits.len(), 3);
+        assert!(analyzer.swim_splits.iter().all(|split| (88..=92).contains(split)));
+    }
+
+    #[test]
+    fn test_open_water_zig_zag() {
+        let raw = make_swim(300, |index| (40.0 + index as f64 * DEGREES_PER_SECOND, -75.0));
+        let mut analyzer = make_swim(300, |index| (40.0 + index as f64 * DEGREES_PER_SECOND, if index % 2 == 0 { -75.0003 } else { -74.9997 }));
+        let zig_zag_distance = analyzer.total_distance;
+        analyzer.analyze();
+
+        // Smoothing takes out most, but not all, of the zig-zag.
+        assert!(analyzer.total_distance < zig_zag_distance / 2.0);
+        assert!(analyzer.total_distance > raw.total_distance);
+        assert!(analyzer.swim_efficiency > 0.0 && analyzer.swim_efficiency < 0.9);
+    }
+
+    #[test]
+    fn test_open_water_splits_exclude_pauses() {
+        let mut analyzer = LocationAnalyzer::new();
+        analyzer.set_activity_type(TYPE_OPEN_WATER_SWIMMING_KEY.to_string());
+
+        // Swim for 150 seconds, stop for a minute, then swim for another 150 seconds.
+        for index in 0..300 {
+            let time_ms = START_TIME_MS + index * 1000 + if index >= 150 { 60000 } else { 0 };
+            if index == 150 {
+                analyzer.stop_timer(time_ms - 60000);
+                analyzer.start_timer(time_ms);
+            }
+            analyzer.append_location(time_ms, 40.0 + index as f64 * DEGREES_PER_SECOND, -75.0, None);
+        }
+        analyzer.analyze();
+
+        assert_eq!(analyzer.swim_splits.len(), 3);
+        assert!(analyzer.swim_splits.iter().all(|split| (88..=92).contains(split)));
+    }
+
+    #[test]
+    fn test_open_water_distances_from_smoothed_track() {
+        let mut analyzer = make_swim(1200, |index| (40.0 + index as f64 * DEGREES_PER_SECOND, if index % 2 == 0 { -75.0003 } else { -74.9997 }));
+        let zig_zag_km_splits = analyzer.km_splits.len();
+        analyzer.analyze();
+
+        // Everything distance based agrees with the smoothed distance.
+        assert_eq!(analyzer.distance_readings.len(), 1200);
+        assert_eq!(*analyzer.distance_readings.last().unwrap(), analyzer.total_distance);
+        assert!(analyzer.distance_readings.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert_eq!(analyzer.km_splits.len(), analyzer.total_distance as usize / 1000 + 1);
+        assert!(analyzer.km_splits.len() < zig_zag_km_splits);
+        assert_eq!(analyzer.bests.contains_key(BEST_1K), analyzer.total_distance >= 1000.0);
+        assert!((analyzer.avg_speed - analyzer.total_distance / 1199.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_open_water_distance_excludes_pauses() {
+        let mut analyzer = LocationAnalyzer::new();
+        analyzer.set_activity_type(TYPE_OPEN_WATER_SWIMMING_KEY.to_string());
+
+        // Swim 150 seconds north, stop the timer and walk 500 meters east along the beach, then swim another 150 seconds.
+        for index in 0..300 {
+            let time_ms = START_TIME_MS + index * 1000 + if index >= 150 { 600000 } else { 0 };
+            let lon = if index >= 150 { -74.994 } else { -75.0 };
+            if index == 150 {
+                analyzer.stop_timer(time_ms - 600000);
+                analyzer.start_timer(time_ms);
+            }
+            analyzer.append_location(time_ms, 40.0 + index as f64 * DEGREES_PER_SECOND, lon, None);
+        }
+        analyzer.analyze();
+
+        // Only the swimming counts, about 1.1 meters/second for 298 seconds.
+        assert!((analyzer.total_distance - 298.0 * 1.11).abs() < 5.0);
+        assert_eq!(analyzer.distance_readings[150], analyzer.distance_readings[149]);
+        assert!(analyzer.sighting_efficiencies.iter().all(|efficiency| (efficiency - 1.0).abs() < 0.01));
+    }
+
+    #[test]
+    fn test_run_excludes_pauses() {
+        let mut analyzer = LocationAnalyzer::new();
+        analyzer.set_activity_type(TYPE_RUNNING_KEY.to_string());
+
+        // Run north at about 2.5 meters/second for ten minutes, uphill. Stop the timer for ten minutes and walk 500 meters on,
+        // then run another ten minutes.
+        for index in 0..1200 {
+            let time_ms = START_TIME_MS + index * 1000 + if index >= 600 { 600000 } else { 0 };
+            let lat = 40.0 + index as f64 * 0.00002247 + if index >= 600 { 0.0045 } else { 0.0 };
+            if index == 600 {
+                analyzer.stop_timer(time_ms - 600000);
+                analyzer.start_timer(time_ms);
+            }
+            analyzer.append_location(time_ms, lat, -75.0, Some(100.0 + index as f64 * 0.1));
+            analyzer.update_speeds();
+        }
+        analyzer.analyze();
+
+        assert_eq!(analyzer.get_timer_seconds(), 1199);
+        assert!((analyzer.total_distance - 1198.0 * 2.5).abs() < 10.0);
+
+        // Each kilometer takes about 400 seconds of running, including the one that spans the pause.
+        assert_eq!(analyzer.km_splits.len(), 3);
+        assert!((395.0..=405.0).contains(&analyzer.km_splits[0]));
+        assert!((795.0..=805.0).contains(&analyzer.km_splits[1]));
+        assert!((399..=402).contains(&analyzer.get_best_time(BEST_1K)));
+
+        // No gradient for the point where the timer restarted.
+        assert_eq!(analyzer.gradient_curve.len(), 1198);
+        assert!(analyzer.gradient_curve.iter().all(|gradient| gradient.is_finite()));
+    }
+}
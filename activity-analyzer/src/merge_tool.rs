@@ -1,258 +1,717 @@
 // Copyright (c) 2021 Michael J. Simms. All rights reserved.
 
 use crate::analyzer_context::AnalyzerContext;
+use crate::event::Event;
+use crate::sample_table::{Column, Lookup, SampleTable};
+use std::ops::{Range, RangeInclusive};
+
+const MERGE_WINDOW_MS: u64 = 1000; // Readings closer together than this are averaged together, and events closer together than this are duplicates
+const MAX_GAP_MS: u64 = 10000; // Readings further apart than this leave a gap that can be filled from the other activity
+const CORRELATION_INTERVAL_MS: u64 = 1000; // Tables are resampled to this interval before looking for the clock offset
+const MAX_CLOCK_OFFSET_SECS: i64 = 300; // The largest clock difference we'll look for, in either direction
+const MAX_CLOCK_DRIFT_SECS: i64 = 30; // The largest change in the clock difference over the course of the activity
+const COARSE_LAG_STEP: i64 = 10; // The clock difference is first found to within this many seconds, using the average of each block of this many readings
+const MIN_CORRELATION_SAMPLES: usize = 60; // Offsets that leave fewer overlapping readings than this are ignored
+const MIN_CORRELATION: f64 = 0.5; // Offsets with a weaker correlation than this are ignored, rather than risk making things worse
+const CORRELATION_COLUMNS: [Column; 3] = [ Column::HeartRate, Column::Speed, Column::Power ]; // Readings both devices are likely to have
+
+/// A reading taken from one of the tables being merged: its timestamp and the value of each of the requested columns.
+type MergedReading = (u64, Vec<Option<f64>>);
+
+/// Where to take a sensor's readings from when merging.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SensorPriority {
+    Average, // Readings from both activities are interleaved, and averaged where they line up
+    First, // Readings come from the first activity, with any gaps filled from the second
+    Second, // Readings come from the second activity, with any gaps filled from the first
+}
 
-pub struct MergeTool {
+impl SensorPriority {
+    /// Returns the priority with the given name ("average", "first" or "second"), or None if there isn't one.
+    pub fn from_name(name: &str) -> Option<SensorPriority> {
+        match name {
+            "average" => Some(SensorPriority::Average),
+            "first" => Some(SensorPriority::First),
+            "second" => Some(SensorPriority::Second),
+            _ => None,
+        }
+    }
 }
 
-impl MergeTool {
-    pub fn new() -> Self {
-        MergeTool{}
+#[derive(Clone, Debug)]
+pub struct MergeOptions {
+    pub detect_offset: bool, // If true, the second activity's clock is lined up with the first one's before merging
+    pub location: SensorPriority,
+    pub heart_rate: SensorPriority,
+    pub cadence: SensorPriority,
+    pub power: SensorPriority,
+    pub temperature: SensorPriority,
+}
+
+impl MergeOptions {
+    /// Creates a new [`MergeOptions`] with the default settings.
+    pub const fn new() -> Self {
+        MergeOptions{ detect_offset: true, location: SensorPriority::Average, heart_rate: SensorPriority::Average, cadence: SensorPriority::Average, power: SensorPriority::Average,
+            temperature: SensorPriority::Average }
+    }
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    fn merge_locations(&self, context1: &AnalyzerContext, context2: &AnalyzerContext, merged_context: &mut AnalyzerContext) {
-        let loc_data1 = &context1.location_analyzer;
-        let loc_data2 = &context2.location_analyzer;
+/// Converts times from the second activity's clock to the first one's. The offset changes at a steady rate so that drift is corrected too.
+#[derive(Clone, Copy, Debug)]
+struct ClockCorrection {
+    reference_time_ms: f64, // Time at which the offset is 'offset_ms'
+    offset_ms: f64,
+    drift: f64, // Change in the offset per millisecond
+}
 
-        let num_points1 = loc_data1.latitude_readings.len();
-        let num_points2 = loc_data2.latitude_readings.len();
+impl ClockCorrection {
+    const NONE: ClockCorrection = ClockCorrection{ reference_time_ms: 0.0, offset_ms: 0.0, drift: 0.0 };
 
-        let mut point_index1 = 0;
-        let mut point_index2 = 0;
+    fn apply(&self, time_ms: u64) -> u64 {
+        let offset_ms = self.offset_ms + self.drift * (time_ms as f64 - self.reference_time_ms);
+        time_ms.saturating_add_signed(offset_ms.round() as i64)
+    }
+}
+
+pub struct MergeTool {
+    options: MergeOptions,
+}
 
-        while point_index1 < num_points1 && point_index2 < num_points2 {
-            let ts1 = loc_data1.times[point_index1];
-            let ts2 = loc_data2.times[point_index2];
+impl MergeTool {
+    pub fn new() -> Self {
+        MergeTool{ options: MergeOptions::new() }
+    }
+    pub fn with_options(options: MergeOptions) -> Self {
+        MergeTool{ options }
+    }
 
-            let time_diff: i64 = (ts1 - ts2) as i64;
-            let abs_time_diff = time_diff.abs();
+    /// Returns the Pearson correlation between the two series when the second is moved by 'lag' rows, using the given rows of the second series.
+    /// Returns None if fewer than 'min_samples' readings overlap, or if either series is flat over the overlap.
+    fn correlation(series1: &[Option<f64>], series2: &[Option<f64>], rows2: &Range<usize>, lag: i64, min_samples: usize) -> Option<f64> {
+        let mut n = 0;
+        let (mut sum1, mut sum2, mut sum11, mut sum22, mut sum12) = (0.0, 0.0, 0.0, 0.0, 0.0);
 
-            // If the points are less than one second apart then average them together.
-            // Otherwise, use the earliest one.
-            if abs_time_diff < 1000 {
-                let lat1 = loc_data1.latitude_readings[point_index1];
-                let lon1 = loc_data1.longitude_readings[point_index1];
-                let alt1 = loc_data1.altitude_graph[point_index1];
+        for row2 in rows2.clone() {
+            let row1 = row2 as i64 + lag;
+            if row1 < 0 || row1 >= series1.len() as i64 {
+                continue;
+            }
+            if let (Some(value1), Some(value2)) = (series1[row1 as usize], series2[row2]) {
+                n += 1;
+                sum1 += value1;
+                sum2 += value2;
+                sum11 += value1 * value1;
+                sum22 += value2 * value2;
+                sum12 += value1 * value2;
+            }
+        }
+        if n < min_samples {
+            return None;
+        }
 
-                let lat2 = loc_data2.latitude_readings[point_index2];
-                let lon2 = loc_data2.longitude_readings[point_index2];
-                let alt2 = loc_data2.altitude_graph[point_index2];
+        let n = n as f64;
+        let covariance = sum12 - sum1 * sum2 / n;
+        let variance1 = sum11 - sum1 * sum1 / n;
+        let variance2 = sum22 - sum2 * sum2 / n;
+        if variance1 <= f64::EPSILON || variance2 <= f64::EPSILON {
+            return None;
+        }
+        Some(covariance / (variance1 * variance2).sqrt())
+    }
 
-                let new_ts = (ts1 + ts2) / 2;
-                let new_lat = (lat1 + lat2) / 2.0;
-                let new_lon = (lon1 + lon2) / 2.0;
-                let new_alt = (alt1 + alt2) / 2.0;
+    /// Returns the lag, from the given range, at which the two series are most strongly correlated, along with the correlation.
+    fn best_lag(series1: &[Option<f64>], series2: &[Option<f64>], rows2: &Range<usize>, lags: RangeInclusive<i64>, min_samples: usize) -> Option<(i64, f64)> {
+        let mut best: Option<(i64, f64)> = None;
 
-                merged_context.location_analyzer.append_location(new_ts, new_lat, new_lon, new_alt);
+        for lag in lags {
+            if let Some(correlation) = MergeTool::correlation(series1, series2, rows2, lag, min_samples) {
+                if !matches!(best, Some((_, best_correlation)) if best_correlation >= correlation) {
+                    best = Some((lag, correlation));
+                }
+            }
+        }
+        best
+    }
 
-                point_index1 += 1;
-                point_index2 += 1;
+    /// Averages each block of readings, skipping the missing ones. Blocks without any readings are None.
+    fn block_averages(series: &[Option<f64>], block_len: usize) -> Vec<Option<f64>> {
+        series.chunks(block_len).map(|block| {
+            let values: Vec<f64> = block.iter().flatten().copied().collect();
+            if values.is_empty() {
+                return None;
             }
-            else if ts1 < ts2 {
-                merged_context.location_analyzer.append_location(ts1, loc_data1.latitude_readings[point_index1], loc_data1.longitude_readings[point_index1], loc_data1.altitude_graph[point_index1]);
-                point_index1 += 1;
+            Some(values.iter().sum::<f64>() / values.len() as f64)
+        }).collect()
+    }
+
+    /// Looks for the clock offset (in milliseconds, to be added to the second table's times) at which the readings over the given rows
+    /// of the second table best match the first table, trying offsets in the given range (in seconds). Both tables must have been
+    /// resampled to CORRELATION_INTERVAL_MS. Rather than try every offset, the best one is found to within COARSE_LAG_STEP
+    /// using block averages, and then only the offsets around it are tried with the full readings.
+    fn find_offset(resampled1: &SampleTable, resampled2: &SampleTable, rows2: &Range<usize>, offset_secs: RangeInclusive<i64>) -> Option<i64> {
+        // The number of rows between the start of the first table and the start of the second, if the clocks agree.
+        let base_lag = (resampled2.times[0] as i64 - resampled1.times[0] as i64) / CORRELATION_INTERVAL_MS as i64;
+        let min_lag = base_lag + offset_secs.start();
+        let max_lag = base_lag + offset_secs.end();
+        let coarse_rows2 = rows2.start / COARSE_LAG_STEP as usize..rows2.end.div_ceil(COARSE_LAG_STEP as usize);
+        let coarse_lags = min_lag.div_euclid(COARSE_LAG_STEP)..=(max_lag + COARSE_LAG_STEP - 1).div_euclid(COARSE_LAG_STEP);
+        let mut best: Option<(i64, f64)> = None;
+
+        for column in CORRELATION_COLUMNS.iter() {
+            if !resampled1.has_column(*column) || !resampled2.has_column(*column) {
+                continue;
             }
-            else {
-                merged_context.location_analyzer.append_location(ts2, loc_data2.latitude_readings[point_index2], loc_data2.longitude_readings[point_index2], loc_data2.altitude_graph[point_index2]);
-                point_index2 += 1;
+
+            let series1 = resampled1.column(*column);
+            let series2 = resampled2.column(*column);
+            let coarse1 = MergeTool::block_averages(series1, COARSE_LAG_STEP as usize);
+            let coarse2 = MergeTool::block_averages(series2, COARSE_LAG_STEP as usize);
+
+            let coarse_lag = match MergeTool::best_lag(&coarse1, &coarse2, &coarse_rows2, coarse_lags.clone(), MIN_CORRELATION_SAMPLES / COARSE_LAG_STEP as usize) {
+                Some((coarse_lag, _)) => coarse_lag * COARSE_LAG_STEP,
+                None => continue,
+            };
+            let fine_lags = i64::max(min_lag, coarse_lag - COARSE_LAG_STEP)..=i64::min(max_lag, coarse_lag + COARSE_LAG_STEP);
+
+            if let Some((lag, correlation)) = MergeTool::best_lag(series1, series2, rows2, fine_lags, MIN_CORRELATION_SAMPLES) {
+                if correlation >= MIN_CORRELATION && !matches!(best, Some((_, best_correlation)) if best_correlation >= correlation) {
+                    best = Some((lag, correlation));
+                }
             }
         }
-        while point_index1 < num_points1 {
-            merged_context.location_analyzer.append_location(loc_data1.times[point_index1], loc_data1.latitude_readings[point_index1], loc_data1.longitude_readings[point_index1], loc_data1.altitude_graph[point_index1]);
-            point_index1 += 1;
+        best.map(|(lag, _)| (lag - base_lag) * CORRELATION_INTERVAL_MS as i64)
+    }
+
+    /// Works out how to line up the second table's clock with the first one's by cross-correlating the readings the two have in common.
+    /// The offset is found separately for each half of the second activity so that a clock that drifted during the
+    /// activity is corrected too, with the offset interpolated (or extrapolated) between the middles of the two halves.
+    fn find_clock_correction(table1: &SampleTable, table2: &SampleTable) -> ClockCorrection {
+        let resampled1 = table1.resample(CORRELATION_INTERVAL_MS, Lookup::Linear);
+        let resampled2 = table2.resample(CORRELATION_INTERVAL_MS, Lookup::Linear);
+        if resampled1.is_empty() || resampled2.is_empty() {
+            return ClockCorrection::NONE;
         }
-        while point_index2 < num_points2 {
-            merged_context.location_analyzer.append_location(loc_data2.times[point_index2], loc_data2.latitude_readings[point_index2], loc_data2.longitude_readings[point_index2], loc_data2.altitude_graph[point_index2]);
-            point_index2 += 1;
+
+        let num_rows = resampled2.len();
+        let offset = match MergeTool::find_offset(&resampled1, &resampled2, &(0..num_rows), -MAX_CLOCK_OFFSET_SECS..=MAX_CLOCK_OFFSET_SECS) {
+            Some(offset) => offset,
+            None => return ClockCorrection::NONE,
+        };
+
+        // The clocks won't have drifted far apart, so each half only needs to be searched near the overall offset.
+        let offset_secs = offset / CORRELATION_INTERVAL_MS as i64;
+        let half_offset_secs = offset_secs - MAX_CLOCK_DRIFT_SECS..=offset_secs + MAX_CLOCK_DRIFT_SECS;
+
+        let first_half = 0..num_rows / 2;
+        let second_half = num_rows / 2..num_rows;
+        let first_middle_ms = resampled2.times[first_half.start + first_half.len() / 2] as f64;
+        let second_middle_ms = resampled2.times[second_half.start + second_half.len() / 2] as f64;
+
+        match (MergeTool::find_offset(&resampled1, &resampled2, &first_half, half_offset_secs.clone()), MergeTool::find_offset(&resampled1, &resampled2, &second_half, half_offset_secs)) {
+            (Some(first_offset), Some(second_offset)) if second_middle_ms > first_middle_ms => {
+                let drift = (second_offset - first_offset) as f64 / (second_middle_ms - first_middle_ms);
+                ClockCorrection{ reference_time_ms: first_middle_ms, offset_ms: first_offset as f64, drift }
+            }
+            _ => ClockCorrection{ reference_time_ms: 0.0, offset_ms: offset as f64, drift: 0.0 },
         }
     }
 
-    fn merge_hr(&self, context1: &AnalyzerContext, context2: &AnalyzerContext, merged_context: &mut AnalyzerContext) {
-        let data1 = &context1.hr_analyzer;
-        let data2 = &context2.hr_analyzer;
-
-        let num_points1 = data1.readings.len();
-        let num_points2 = data2.readings.len();
+    /// Returns the readings from the table, keyed on the rows that have a reading in the first column.
+    fn table_readings(table: &SampleTable, columns: &[Column]) -> Vec<MergedReading> {
+        table.reading_rows(columns[0]).iter().map(|row| (table.times[*row], columns.iter().map(|column| table.get(*column, *row)).collect())).collect()
+    }
 
-        let mut point_index1 = 0;
-        let mut point_index2 = 0;
+    /// Averages two readings, ignoring any missing values.
+    fn average_values(values1: &[Option<f64>], values2: &[Option<f64>]) -> Vec<Option<f64>> {
+        values1.iter().zip(values2.iter()).map(|(value1, value2)| {
+            match (value1, value2) {
+                (Some(value1), Some(value2)) => Some((value1 + value2) / 2.0),
+                _ => value1.or(*value2),
+            }
+        }).collect()
+    }
 
-        while point_index1 < num_points1 && point_index2 < num_points2 {
-            let ts1 = data1.time_readings[point_index1];
-            let ts2 = data2.time_readings[point_index2];
+    /// Returns the readings from the preferred table, along with any readings from the other table that fall in a gap
+    /// in the preferred table's readings (including before its first reading and after its last).
+    fn fill_gaps(preferred: &SampleTable, other: &SampleTable, columns: &[Column]) -> Vec<MergedReading> {
+        let mut result = MergeTool::table_readings(preferred, columns);
+        let preferred_times: Vec<u64> = result.iter().map(|(ts, _)| *ts).collect();
+
+        for reading in MergeTool::table_readings(other, columns) {
+            let index = preferred_times.partition_point(|ts| *ts < reading.0);
+            let in_gap = match (index.checked_sub(1).map(|before| preferred_times[before]), preferred_times.get(index)) {
+                (Some(before), Some(after)) => after - before > MAX_GAP_MS,
+                _ => true,
+            };
+            if in_gap {
+                result.push(reading);
+            }
+        }
+        result.sort_by_key(|(ts, _)| *ts);
+        result
+    }
 
-            let time_diff: i64 = (ts1 - ts2) as i64;
-            let abs_time_diff = time_diff.abs();
+    /// Returns the readings to use for the columns. With [`SensorPriority::Average`], the readings from the two tables are
+    /// interleaved in time order, and if they are less than one second apart then they are averaged together. Otherwise,
+    /// the readings come from the preferred table, with any gaps filled from the other one.
+    fn merge_columns(table1: &SampleTable, table2: &SampleTable, columns: &[Column], priority: SensorPriority) -> Vec<MergedReading> {
+        match priority {
+            SensorPriority::First => return MergeTool::fill_gaps(table1, table2, columns),
+            SensorPriority::Second => return MergeTool::fill_gaps(table2, table1, columns),
+            SensorPriority::Average => {}
+        }
 
-            let value1 = data1.readings[point_index1];
-            let value2 = data2.readings[point_index2];
+        let readings1 = MergeTool::table_readings(table1, columns);
+        let readings2 = MergeTool::table_readings(table2, columns);
+        let mut result = Vec::with_capacity(readings1.len() + readings2.len());
 
-            // If the points are less than one second apart then average them together.
-            // Otherwise, use the earliest one.
-            if abs_time_diff < 1000 {
-                let new_ts = (ts1 + ts2) / 2;
-                let new_value = (value1 + value2) / 2.0;
+        let mut index1 = 0;
+        let mut index2 = 0;
 
-                merged_context.hr_analyzer.append_sensor_value(new_ts, new_value);
+        while index1 < readings1.len() && index2 < readings2.len() {
+            let (ts1, values1) = &readings1[index1];
+            let (ts2, values2) = &readings2[index2];
 
-                point_index1 += 1;
-                point_index2 += 1;
+            if ts1.abs_diff(*ts2) < MERGE_WINDOW_MS {
+                result.push(((ts1 + ts2) / 2, MergeTool::average_values(values1, values2)));
+                index1 += 1;
+                index2 += 1;
             }
             else if ts1 < ts2 {
-                merged_context.hr_analyzer.append_sensor_value(ts1, value1);
-                point_index1 += 1;
+                result.push(readings1[index1].clone());
+                index1 += 1;
             }
             else {
-                merged_context.hr_analyzer.append_sensor_value(ts2, value2);
-                point_index2 += 1;
+                result.push(readings2[index2].clone());
+                index2 += 1;
             }
         }
 
         // Merge any leftover readings, as the files might not end at the same time.
-        while point_index1 < num_points1 {
-            let ts1 = data1.time_readings[point_index1];
-            let value1 = data1.readings[point_index1];
+        result.extend_from_slice(&readings1[index1..]);
+        result.extend_from_slice(&readings2[index2..]);
+        result
+    }
 
-            merged_context.hr_analyzer.append_sensor_value(ts1, value1);
-            point_index1 += 1;
+    /// Combines the events from both activities, with the second activity's moved onto the first one's clock. Timer and lap events
+    /// only come from one activity (the first one that has any), since pauses and laps recorded by two devices won't agree.
+    /// Other events that appear in both are only kept once.
+    fn merge_events(context1: &AnalyzerContext, context2: &AnalyzerContext, correction: &ClockCorrection) -> Vec<Event> {
+        let is_timer_event = |event: &Event| matches!(event, Event::TimerStart { .. } | Event::TimerStop { .. } | Event::Lap { .. });
+        let timer_events_from_first = context1.events.iter().any(is_timer_event);
+
+        let mut events = context1.events.clone();
+        for event in context2.events.iter() {
+            if timer_events_from_first && is_timer_event(event) {
+                continue;
+            }
+
+            let mut event = event.clone();
+            event.set_timestamp_ms(correction.apply(event.timestamp_ms()));
+
+            let is_duplicate = !is_timer_event(&event) && context1.events.iter().any(|existing| {
+                std::mem::discriminant(existing) == std::mem::discriminant(&event) && existing.timestamp_ms().abs_diff(event.timestamp_ms()) < MERGE_WINDOW_MS
+            });
+            if !is_duplicate {
+                events.push(event);
+            }
         }
-        while point_index2 < num_points2 {
-            let ts2 = data2.time_readings[point_index2];
-            let value2 = data2.readings[point_index2];
+        events.sort_by_key(|event| event.timestamp_ms());
+        events
+    }
 
-            merged_context.hr_analyzer.append_sensor_value(ts2, value2);
-            point_index2 += 1;
+    /// Merges the locations, replaying the events along with them so that pauses fall between the right points.
+    fn merge_locations(&self, table1: &SampleTable, table2: &SampleTable, events: &[Event], merged_context: &mut AnalyzerContext) {
+        let columns = [ Column::Latitude, Column::Longitude, Column::Altitude ];
+        let mut event_iter = events.iter().peekable();
+
+        for (ts, values) in MergeTool::merge_columns(table1, table2, &columns, self.options.location) {
+            if let (Some(lat), Some(lon)) = (values[0], values[1]) {
+                while let Some(event) = event_iter.next_if(|event| event.timestamp_ms() <= ts) {
+                    merged_context.replay_event(event);
+                }
+                merged_context.location_analyzer.append_location(ts, lat, lon, values[2]);
+                merged_context.location_analyzer.update_speeds();
+            }
+        }
+        for event in event_iter {
+            merged_context.replay_event(event);
         }
     }
 
-    fn merge_cadence(&self, context1: &AnalyzerContext, context2: &AnalyzerContext, merged_context: &mut AnalyzerContext) {
-        let data1 = &context1.cadence_analyzer;
-        let data2 = &context2.cadence_analyzer;
+    fn merge_hr(&self, table1: &SampleTable, table2: &SampleTable, merged_context: &mut AnalyzerContext) {
+        for (ts, values) in MergeTool::merge_columns(table1, table2, &[Column::HeartRate], self.options.heart_rate) {
+            if let Some(value) = values[0] {
+                merged_context.hr_analyzer.append_sensor_value(ts, value);
+            }
+        }
+    }
 
-        let num_points1 = data1.readings.len();
-        let num_points2 = data2.readings.len();
+    fn merge_cadence(&self, table1: &SampleTable, table2: &SampleTable, merged_context: &mut AnalyzerContext) {
+        for (ts, values) in MergeTool::merge_columns(table1, table2, &[Column::Cadence], self.options.cadence) {
+            if let Some(value) = values[0] {
+                merged_context.cadence_analyzer.append_sensor_value(ts, value);
+            }
+        }
+    }
 
-        let mut point_index1 = 0;
-        let mut point_index2 = 0;
+    fn merge_power(&self, table1: &SampleTable, table2: &SampleTable, merged_context: &mut AnalyzerContext) {
+        for (ts, values) in MergeTool::merge_columns(table1, table2, &[Column::Power], self.options.power) {
+            if let Some(value) = values[0] {
+                merged_context.power_analyzer.append_sensor_value(ts, value);
+            }
+        }
+    }
 
-        while point_index1 < num_points1 && point_index2 < num_points2 {
-            let ts1 = data1.time_readings[point_index1];
-            let ts2 = data2.time_readings[point_index2];
+    fn merge_temperature(&self, table1: &SampleTable, table2: &SampleTable, merged_context: &mut AnalyzerContext) {
+        for (ts, values) in MergeTool::merge_columns(table1, table2, &[Column::Temperature], self.options.temperature) {
+            if let Some(value) = values[0] {
+                merged_context.temperature_analyzer.append_sensor_value(ts, value);
+            }
+        }
+    }
 
-            let time_diff: i64 = (ts1 - ts2) as i64;
-            let abs_time_diff = time_diff.abs();
+    /// Returns the activity to take readings that can't be averaged from: the first one, unless only the second one has them.
+    /// Also returns the correction that moves the chosen activity's times onto the first activity's clock.
+    fn pick_source<'a>(context1: &'a AnalyzerContext, context2: &'a AnalyzerContext, correction: &ClockCorrection, has_data: impl Fn(&AnalyzerContext) -> bool) -> (&'a AnalyzerContext, ClockCorrection) {
+        if has_data(context1) || !has_data(context2) {
+            (context1, ClockCorrection::NONE)
+        }
+        else {
+            (context2, *correction)
+        }
+    }
 
-            let value1 = data1.readings[point_index1];
-            let value2 = data2.readings[point_index2];
+    /// Swim lengths can't be averaged, so they come from whichever activity has them.
+    fn merge_swim(context1: &AnalyzerContext, context2: &AnalyzerContext, correction: &ClockCorrection, merged_context: &mut AnalyzerContext) {
+        let (source, correction) = MergeTool::pick_source(context1, context2, correction, |context| !context.swim_analyzer.time_readings.is_empty());
+        let swim_data = &source.swim_analyzer;
 
-            // If the points are less than one second apart then average them together.
-            // Otherwise, use the earliest one.
-            if abs_time_diff < 1000 {
-                let new_ts = (ts1 + ts2) / 2;
-                let new_value = (value1 + value2) / 2.0;
+        merged_context.swim_analyzer.set_pool_length(swim_data.pool_length);
+        merged_context.swim_analyzer.set_pool_length_units(swim_data.pool_length_units);
+        for (ts, strokes) in swim_data.time_readings.iter().zip(swim_data.strokes.iter()) {
+            merged_context.swim_analyzer.append_sensor_value(correction.apply(*ts), *strokes);
+        }
+    }
 
-                merged_context.power_analyzer.append_sensor_value(new_ts, new_value);
+    /// Pedal metrics come from whichever activity has them, as they're only meaningful alongside the power meter that measured them.
+    fn merge_cycling_dynamics(context1: &AnalyzerContext, context2: &AnalyzerContext, correction: &ClockCorrection, merged_context: &mut AnalyzerContext) {
+        let (source, correction) = MergeTool::pick_source(context1, context2, correction, |context| !context.power_analyzer.cycling_dynamics_times.is_empty());
+        let power_data = &source.power_analyzer;
 
-                point_index1 += 1;
-                point_index2 += 1;
-            }
-            else if ts1 < ts2 {
-                merged_context.power_analyzer.append_sensor_value(ts1, value1);
-                point_index1 += 1;
-            }
-            else
-            {
-                merged_context.power_analyzer.append_sensor_value(ts2, value2);
-                point_index2 += 1;
-            }
+        for (index, ts) in power_data.cycling_dynamics_times.iter().enumerate() {
+            merged_context.power_analyzer.append_cycling_dynamics(correction.apply(*ts), power_data.left_right_balance_readings[index], power_data.left_torque_effectiveness_readings[index],
+                power_data.right_torque_effectiveness_readings[index], power_data.left_pedal_smoothness_readings[index], power_data.right_pedal_smoothness_readings[index]);
         }
+    }
 
-        // Merge any leftover readings, as the files might not end at the same time.
-        while point_index1 < num_points1 {
-            let ts1 = data1.time_readings[point_index1];
-            let value1 = data1.readings[point_index1];
+    /// Running dynamics come from whichever activity has them, as they all come from the same sensor.
+    fn merge_running_dynamics(context1: &AnalyzerContext, context2: &AnalyzerContext, correction: &ClockCorrection, merged_context: &mut AnalyzerContext) {
+        let (source, correction) = MergeTool::pick_source(context1, context2, correction, |context| context.running_dynamics_analyzer.has_data());
+        let dynamics = &source.running_dynamics_analyzer;
+        let merged_dynamics = &mut merged_context.running_dynamics_analyzer;
 
-            merged_context.power_analyzer.append_sensor_value(ts1, value1);
-            point_index1 += 1;
+        for (ts, value) in dynamics.ground_contact_time_times.iter().zip(dynamics.ground_contact_time_readings.iter()) {
+            merged_dynamics.append_ground_contact_time(correction.apply(*ts), *value);
+        }
+        for (ts, value) in dynamics.vertical_oscillation_times.iter().zip(dynamics.vertical_oscillation_readings.iter()) {
+            merged_dynamics.append_vertical_oscillation(correction.apply(*ts), *value);
         }
-        while point_index2 < num_points2 {
-            let ts2 = data2.time_readings[point_index2];
-            let value2 = data2.readings[point_index2];
+        for (ts, value) in dynamics.vertical_ratio_times.iter().zip(dynamics.vertical_ratio_readings.iter()) {
+            merged_dynamics.append_vertical_ratio(correction.apply(*ts), *value);
+        }
+        for (ts, value) in dynamics.step_length_times.iter().zip(dynamics.step_length_readings.iter()) {
+            merged_dynamics.append_step_length(correction.apply(*ts), *value);
+        }
+        for (ts, value) in dynamics.ground_contact_time_balance_times.iter().zip(dynamics.ground_contact_time_balance_readings.iter()) {
+            merged_dynamics.append_ground_contact_time_balance(correction.apply(*ts), *value);
+        }
+    }
 
-            merged_context.power_analyzer.append_sensor_value(ts2, value2);
-            point_index2 += 1;
+    /// Merges the two activities, such as a bike computer's recording with a watch's.
+    pub fn merge(&self, context1: &AnalyzerContext, context2: &AnalyzerContext) -> AnalyzerContext {
+        let mut merged_context = AnalyzerContext::new();
+        let table1 = context1.sample_table();
+        let mut table2 = context2.sample_table();
+
+        let correction = if self.options.detect_offset { MergeTool::find_clock_correction(&table1, &table2) } else { ClockCorrection::NONE };
+        for time in table2.times.iter_mut() {
+            *time = correction.apply(*time);
         }
+
+        merged_context.name = context1.name.clone();
+        merged_context.location_analyzer.set_activity_type(context1.location_analyzer.activity_type.clone());
+
+        let events = MergeTool::merge_events(context1, context2, &correction);
+        self.merge_locations(&table1, &table2, &events, &mut merged_context);
+        self.merge_hr(&table1, &table2, &mut merged_context);
+        self.merge_cadence(&table1, &table2, &mut merged_context);
+        self.merge_power(&table1, &table2, &mut merged_context);
+        self.merge_temperature(&table1, &table2, &mut merged_context);
+        MergeTool::merge_cycling_dynamics(context1, context2, &correction, &mut merged_context);
+        MergeTool::merge_running_dynamics(context1, context2, &correction, &mut merged_context);
+        MergeTool::merge_swim(context1, context2, &correction, &mut merged_context);
+
+        merged_context.analyze();
+
+        merged_context
     }
 
-    fn merge_power(&self, context1: &AnalyzerContext, context2: &AnalyzerContext, merged_context: &mut AnalyzerContext) {
-        let data1 = &context1.power_analyzer;
-        let data2 = &context2.power_analyzer;
+    /// Merges any number of activities, one at a time in the order given. So with [`SensorPriority::First`] the earlier activities
+    /// are preferred, and with [`SensorPriority::Second`] the later ones are.
+    pub fn merge_all(&self, contexts: &[&AnalyzerContext]) -> Option<AnalyzerContext> {
+        let (first, rest) = contexts.split_first()?;
+        let mut merged_context = self.merge(first, rest.first()?);
 
-        let num_points1 = data1.readings.len();
-        let num_points2 = data2.readings.len();
+        for context in rest.iter().skip(1) {
+            merged_context = self.merge(&merged_context, context);
+        }
+        Some(merged_context)
+    }
+}
 
-        let mut point_index1 = 0;
-        let mut point_index2 = 0;
+impl Default for MergeTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        while point_index1 < num_points1 && point_index2 < num_points2 {
-            let ts1 = data1.time_readings[point_index1];
-            let ts2 = data2.time_readings[point_index2];
+#[cfg(test)]
+mod tests {
+    use crate::analyzer_context::AnalyzerContext;
+    use crate::event::Event;
+    use crate::merge_tool::MergeTool;
+    use crate::sample_table::SampleTable;
 
-            let time_diff: i64 = (ts1 - ts2) as i64;
-            let abs_time_diff = time_diff.abs();
+    const START_TIME_MS: u64 = 1600000000000;
+    const NUM_READINGS: u64 = 30;
 
-            let value1 = data1.readings[point_index1];
-            let value2 = data2.readings[point_index2];
+    /// Returns a timestamp for each reading, one second apart.
+    fn reading_times() -> Vec<u64> {
+        (0..NUM_READINGS).map(|index| START_TIME_MS + index * 1000).collect()
+    }
 
-            // If the points are less than one second apart then average them together.
-            // Otherwise, use the earliest one.
-            if abs_time_diff < 1000 {
-                let new_ts = (ts1 + ts2) / 2;
-                let new_value = (value1 + value2) / 2.0;
+    /// Returns a series of readings that vary, so that none of them are mistaken for another.
+    fn reading_values(base: f64) -> Vec<f64> {
+        (0..NUM_READINGS).map(|index| base + (index % 7) as f64 * 1.5).collect()
+    }
 
-                merged_context.power_analyzer.append_sensor_value(new_ts, new_value);
+    /// Merges the context with an empty one, in both orders, and checks each result with the given function.
+    fn check_merges(context: &AnalyzerContext, check: impl Fn(&AnalyzerContext)) {
+        let merge_tool = MergeTool::new();
+        let empty_context = AnalyzerContext::new();
 
-                point_index1 += 1;
-                point_index2 += 1;
-            }
-            else if ts1 < ts2 {
-                merged_context.power_analyzer.append_sensor_value(ts1, value1);
-                point_index1 += 1;
-            }
-            else {
-                merged_context.power_analyzer.append_sensor_value(ts2, value2);
-                point_index2 += 1;
-            }
+        check(&merge_tool.merge(context, &empty_context));
+        check(&merge_tool.merge(&empty_context, context));
+    }
+
+    #[test]
+    fn test_merge_location() {
+        let mut context = AnalyzerContext::new();
+        for (index, ts) in reading_times().iter().enumerate() {
+            context.location_analyzer.append_location(*ts, 40.0 + index as f64 * 0.0001, -75.0 + index as f64 * 0.00005, Some(100.0 + index as f64));
         }
+        context.analyze();
+
+        check_merges(&context, |merged| {
+            assert_eq!(merged.location_analyzer.times, context.location_analyzer.times);
+            assert_eq!(merged.location_analyzer.latitude_readings, context.location_analyzer.latitude_readings);
+            assert_eq!(merged.location_analyzer.longitude_readings, context.location_analyzer.longitude_readings);
+            assert_eq!(merged.location_analyzer.get_altitude_readings(), context.location_analyzer.get_altitude_readings());
+        });
+    }
 
-        // Merge any leftover readings, as the files might not end at the same time.
-        while point_index1 < num_points1 {
-            let ts1 = data1.time_readings[point_index1];
-            let value1 = data1.readings[point_index1];
+    #[test]
+    fn test_merge_heart_rate() {
+        let mut context = AnalyzerContext::new();
+        for (ts, value) in reading_times().iter().zip(reading_values(120.0)) {
+            context.hr_analyzer.append_sensor_value(*ts, value);
+        }
+
+        check_merges(&context, |merged| {
+            assert_eq!(merged.hr_analyzer.time_readings, context.hr_analyzer.time_readings);
+            assert_eq!(merged.hr_analyzer.readings, context.hr_analyzer.readings);
+        });
+    }
 
-            merged_context.power_analyzer.append_sensor_value(ts1, value1);
-            point_index1 += 1;
+    #[test]
+    fn test_merge_cadence() {
+        let mut context = AnalyzerContext::new();
+        for (ts, value) in reading_times().iter().zip(reading_values(85.0)) {
+            context.cadence_analyzer.append_sensor_value(*ts, value);
         }
-        while point_index2 < num_points2 {
-            let ts2 = data2.time_readings[point_index2];
-            let value2 = data2.readings[point_index2];
 
-            merged_context.power_analyzer.append_sensor_value(ts2, value2);
-            point_index2 += 1;
+        check_merges(&context, |merged| {
+            assert_eq!(merged.cadence_analyzer.time_readings, context.cadence_analyzer.time_readings);
+            assert_eq!(merged.cadence_analyzer.readings, context.cadence_analyzer.readings);
+            assert!(merged.power_analyzer.readings.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_merge_power() {
+        let mut context = AnalyzerContext::new();
+        for (ts, value) in reading_times().iter().zip(reading_values(200.0)) {
+            context.power_analyzer.append_sensor_value(*ts, value);
         }
+
+        check_merges(&context, |merged| {
+            assert_eq!(merged.power_analyzer.time_readings, context.power_analyzer.time_readings);
+            assert_eq!(merged.power_analyzer.readings, context.power_analyzer.readings);
+            assert!(merged.cadence_analyzer.readings.is_empty());
+        });
     }
 
-    pub fn merge(&self, context1: &AnalyzerContext, context2: &AnalyzerContext) -> AnalyzerContext {
-        let mut merged_context = AnalyzerContext::new();
+    #[test]
+    fn test_merge_temperature() {
+        let mut context = AnalyzerContext::new();
+        for (ts, value) in reading_times().iter().zip(reading_values(18.0)) {
+            context.temperature_analyzer.append_sensor_value(*ts, value);
+        }
 
-        self.merge_locations(context1, context2, &mut merged_context);
-        self.merge_hr(context1, context2, &mut merged_context);
-        self.merge_cadence(context1, context2, &mut merged_context);
-        self.merge_power(context1, context2, &mut merged_context);
+        check_merges(&context, |merged| {
+            assert_eq!(merged.temperature_analyzer.time_readings, context.temperature_analyzer.time_readings);
+            assert_eq!(merged.temperature_analyzer.readings, context.temperature_analyzer.readings);
+        });
+    }
 
-        merged_context.location_analyzer.analyze();
-        merged_context.power_analyzer.analyze();
+    #[test]
+    fn test_merge_cycling_dynamics() {
+        let mut context = AnalyzerContext::new();
+        for (ts, value) in reading_times().iter().zip(reading_values(48.0)) {
+            context.power_analyzer.append_cycling_dynamics(*ts, Some(value), Some(value + 20.0), Some(value + 22.0), None, Some(value - 30.0));
+        }
 
-        merged_context
+        check_merges(&context, |merged| {
+            let power_data = &context.power_analyzer;
+            let merged_power_data = &merged.power_analyzer;
+            assert_eq!(merged_power_data.cycling_dynamics_times, power_data.cycling_dynamics_times);
+            assert_eq!(merged_power_data.left_right_balance_readings, power_data.left_right_balance_readings);
+            assert_eq!(merged_power_data.left_torque_effectiveness_readings, power_data.left_torque_effectiveness_readings);
+            assert_eq!(merged_power_data.right_torque_effectiveness_readings, power_data.right_torque_effectiveness_readings);
+            assert_eq!(merged_power_data.left_pedal_smoothness_readings, power_data.left_pedal_smoothness_readings);
+            assert_eq!(merged_power_data.right_pedal_smoothness_readings, power_data.right_pedal_smoothness_readings);
+        });
+    }
+
+    #[test]
+    fn test_merge_running_dynamics() {
+        let mut context = AnalyzerContext::new();
+        for (ts, value) in reading_times().iter().zip(reading_values(250.0)) {
+            context.running_dynamics_analyzer.append_ground_contact_time(*ts, value);
+            context.running_dynamics_analyzer.append_vertical_oscillation(*ts, value / 3.0);
+            context.running_dynamics_analyzer.append_vertical_ratio(*ts, value / 30.0);
+            context.running_dynamics_analyzer.append_step_length(*ts, value * 4.0);
+            context.running_dynamics_analyzer.append_ground_contact_time_balance(*ts, value / 5.0);
+        }
+
+        check_merges(&context, |merged| {
+            let dynamics = &context.running_dynamics_analyzer;
+            let merged_dynamics = &merged.running_dynamics_analyzer;
+            assert_eq!(merged_dynamics.ground_contact_time_times, dynamics.ground_contact_time_times);
+            assert_eq!(merged_dynamics.ground_contact_time_readings, dynamics.ground_contact_time_readings);
+            assert_eq!(merged_dynamics.vertical_oscillation_times, dynamics.vertical_oscillation_times);
+            assert_eq!(merged_dynamics.vertical_oscillation_readings, dynamics.vertical_oscillation_readings);
+            assert_eq!(merged_dynamics.vertical_ratio_times, dynamics.vertical_ratio_times);
+            assert_eq!(merged_dynamics.vertical_ratio_readings, dynamics.vertical_ratio_readings);
+            assert_eq!(merged_dynamics.step_length_times, dynamics.step_length_times);
+            assert_eq!(merged_dynamics.step_length_readings, dynamics.step_length_readings);
+            assert_eq!(merged_dynamics.ground_contact_time_balance_times, dynamics.ground_contact_time_balance_times);
+            assert_eq!(merged_dynamics.ground_contact_time_balance_readings, dynamics.ground_contact_time_balance_readings);
+        });
+    }
+
+    #[test]
+    fn test_merge_swim() {
+        let mut context = AnalyzerContext::new();
+        context.swim_analyzer.set_pool_length(25);
+        context.swim_analyzer.set_pool_length_units(100);
+        for (index, ts) in reading_times().iter().enumerate().step_by(10) {
+            context.swim_analyzer.append_sensor_value(*ts, 14 + index as u16);
+        }
+
+        check_merges(&context, |merged| {
+            assert_eq!(merged.swim_analyzer.pool_length, 25);
+            assert_eq!(merged.swim_analyzer.pool_length_units, 100);
+            assert_eq!(merged.swim_analyzer.time_readings, context.swim_analyzer.time_readings);
+            assert_eq!(merged.swim_analyzer.strokes, context.swim_analyzer.strokes);
+        });
+    }
+
+    #[test]
+    fn test_merge_events() {
+        let mut context = AnalyzerContext::new();
+        context.events.push(Event::TimerStart{ timestamp_ms: START_TIME_MS });
+        context.events.push(Event::Lap{ timestamp_ms: START_TIME_MS + 10000 });
+        context.events.push(Event::Marker{ timestamp_ms: START_TIME_MS + 12000, name: "Summit".to_string() });
+        context.events.push(Event::GearChange{ timestamp_ms: START_TIME_MS + 15000, front_gear_num: 2, front_gear_teeth: 50, rear_gear_num: 5, rear_gear_teeth: 17 });
+        context.events.push(Event::BatteryLow{ timestamp_ms: START_TIME_MS + 20000 });
+
+        check_merges(&context, |merged| {
+            assert_eq!(serde_json::json!(merged.events), serde_json::json!(context.events));
+        });
+    }
+
+    /// Returns twenty minutes of heart rate readings that wander up and down, without repeating, so that there's only one
+    /// offset at which two copies of it line up.
+    fn wandering_heart_rate() -> Vec<f64> {
+        let mut seed: u64 = 12345;
+        let mut value = 140.0;
+
+        (0..1200).map(|_| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            value += ((seed >> 33) % 7) as f64 - 3.0;
+            value = f64::clamp(value, 100.0, 180.0);
+            value
+        }).collect()
+    }
+
+    /// Returns a table of the readings, with the time of each one given by the function.
+    fn heart_rate_table(values: &[f64], time_ms: impl Fn(u64) -> u64) -> SampleTable {
+        let mut context = AnalyzerContext::new();
+        for (index, value) in values.iter().enumerate() {
+            context.hr_analyzer.append_sensor_value(time_ms(index as u64), *value);
+        }
+        context.sample_table()
+    }
+
+    #[test]
+    fn test_find_clock_correction() {
+        let values = wandering_heart_rate();
+        let table1 = heart_rate_table(&values, |index| START_TIME_MS + index * 1000);
+
+        // The second device's clock is 137 seconds ahead.
+        let table2 = heart_rate_table(&values, |index| START_TIME_MS + 137000 + index * 1000);
+        let correction = MergeTool::find_clock_correction(&table1, &table2);
+        assert_eq!(correction.apply(START_TIME_MS + 137000), START_TIME_MS);
+        assert_eq!(correction.apply(START_TIME_MS + 137000 + 1199000), START_TIME_MS + 1199000);
+
+        // The second device's clock starts 60 seconds behind and loses another 12 seconds over the twenty minutes.
+        let table2 = heart_rate_table(&values, |index| START_TIME_MS - 60000 + index * 990);
+        let correction = MergeTool::find_clock_correction(&table1, &table2);
+        assert!(correction.apply(START_TIME_MS - 60000).abs_diff(START_TIME_MS) <= 2000);
+        assert!(correction.apply(START_TIME_MS - 60000 + 1199 * 990).abs_diff(START_TIME_MS + 1199000) <= 2000);
+    }
+
+    #[test]
+    fn test_find_clock_correction_without_a_match() {
+        let values = wandering_heart_rate();
+        let table1 = heart_rate_table(&values, |index| START_TIME_MS + index * 1000);
+
+        // Unrelated readings are left alone.
+        let mut reversed = values.clone();
+        reversed.reverse();
+        let table2 = heart_rate_table(&reversed, |index| START_TIME_MS + index * 1000);
+        assert_eq!(MergeTool::find_clock_correction(&table1, &table2).apply(START_TIME_MS), START_TIME_MS);
+
+        // So are clocks that are further apart than we look.
+        let table2 = heart_rate_table(&values, |index| START_TIME_MS + 600000 + index * 1000);
+        assert_eq!(MergeTool::find_clock_correction(&table1, &table2).apply(START_TIME_MS + 600000), START_TIME_MS + 600000);
     }
 }
@@ -41,13 +41,53 @@ pub struct PowerAnalyzer {
     pub bests: HashMap<String, f64>,
     pub significant_intervals: Vec<PowerIntervalDescription>,
     start_time_ms: u64,
-    end_time_ms: u64
+    end_time_ms: u64,
+    pub cycling_dynamics_times: Vec<u64>, // Time of each cycling dynamics reading, the readings themselves may be missing for individual sensors
+    pub left_right_balance_readings: Vec<Option<f64>>, // Percentage of the power from the right leg
+    pub left_torque_effectiveness_readings: Vec<Option<f64>>, // Percent
+    pub right_torque_effectiveness_readings: Vec<Option<f64>>, // Percent
+    pub left_pedal_smoothness_readings: Vec<Option<f64>>, // Percent
+    pub right_pedal_smoothness_readings: Vec<Option<f64>>, // Percent
 }
 
 impl PowerAnalyzer {
     pub fn new() -> Self {
         PowerAnalyzer{ readings: Vec::new(), time_readings: Vec::new(), max_power: 0.0, avg_power: 0.0, np_buf: Vec::new(), np: 0.0, vi: 0.0,
-            current_30_sec_buf: Vec::new(), current_30_sec_buf_start_time: 0, bests: HashMap::new(), significant_intervals: Vec::new(), start_time_ms: 0, end_time_ms: 0 }
+            current_30_sec_buf: Vec::new(), current_30_sec_buf_start_time: 0, bests: HashMap::new(), significant_intervals: Vec::new(), start_time_ms: 0, end_time_ms: 0,
+            cycling_dynamics_times: Vec::new(), left_right_balance_readings: Vec::new(), left_torque_effectiveness_readings: Vec::new(), right_torque_effectiveness_readings: Vec::new(),
+            left_pedal_smoothness_readings: Vec::new(), right_pedal_smoothness_readings: Vec::new() }
+    }
+
+    /// Computes the average of the readings that are present, used for the cycling dynamics data.
+    pub fn compute_optional_average(readings: &[Option<f64>]) -> f64 {
+        let mut sum = 0.0;
+        let mut count = 0;
+
+        for reading in readings.iter().flatten() {
+            sum += reading;
+            count += 1;
+        }
+        if count > 0 {
+            return sum / (count as f64);
+        }
+        0.0
+    }
+
+    /// Adds another set of cycling dynamics readings to the analyzer. Not every power meter records every value.
+    pub fn append_cycling_dynamics(&mut self, date_time_ms: u64, left_right_balance: Option<f64>, left_torque_effectiveness: Option<f64>, right_torque_effectiveness: Option<f64>,
+        left_pedal_smoothness: Option<f64>, right_pedal_smoothness: Option<f64>) {
+
+        if left_right_balance.is_none() && left_torque_effectiveness.is_none() && right_torque_effectiveness.is_none() &&
+            left_pedal_smoothness.is_none() && right_pedal_smoothness.is_none() {
+            return;
+        }
+
+        self.cycling_dynamics_times.push(date_time_ms);
+        self.left_right_balance_readings.push(left_right_balance);
+        self.left_torque_effectiveness_readings.push(left_torque_effectiveness);
+        self.right_torque_effectiveness_readings.push(right_torque_effectiveness);
+        self.left_pedal_smoothness_readings.push(left_pedal_smoothness);
+        self.right_pedal_smoothness_readings.push(right_pedal_smoothness);
     }
 
     /// Computes the average value.
@@ -60,6 +100,27 @@ impl PowerAnalyzer {
         0.0
     }
 
+    /// Computes the average of the readings from the last 'seconds' seconds, ending at the given time. Returns None if there aren't any.
+    pub fn compute_trailing_average(&self, end_time_ms: u64, seconds: u64) -> Option<f64> {
+        let start_time_ms = end_time_ms.saturating_sub(seconds * 1000);
+        let mut sum = 0.0;
+        let mut count = 0;
+
+        for (reading, time) in self.readings.iter().zip(self.time_readings.iter()).rev() {
+            if *time <= start_time_ms {
+                break;
+            }
+            if *time <= end_time_ms {
+                sum += reading;
+                count += 1;
+            }
+        }
+        if count > 0 {
+            return Some(sum / (count as f64));
+        }
+        None
+    }
+
     /// Returns the time associated with the specified record, or None if not found.
     pub fn get_best_power(&self, record_name: &str) -> f64 {
         match self.bests.get(record_name) {
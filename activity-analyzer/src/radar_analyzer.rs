@@ -0,0 +1,156 @@
+// Copyright (c) 2021 Michael J. Simms. All rights reserved.
+
+use crate::location_analyzer::LocationAnalyzer;
+use serde::Serialize;
+
+const MS_PER_HOUR: f64 = 3600000.0;
+const THREAT_LEVEL_APPROACHING: u8 = 2; // FIT radar_threat_level_type, levels from here up have a vehicle approaching
+
+#[derive(Clone, Copy, Serialize)]
+pub struct RadarThreat {
+    pub timestamp_ms: u64,
+    pub threat_level: u8, // 0 == unknown, 1 == no threat, 2 == vehicle approaching, 3 == vehicle approaching fast
+    pub threat_count: u8, // Number of vehicles being tracked
+    pub approach_speed: Option<f64>, // Average approach speed (in meters/second), if the radar recorded it
+    pub latitude: Option<f64>, // Location of the nearest point on the track
+    pub longitude: Option<f64>, // Location of the nearest point on the track
+}
+
+impl RadarThreat {
+    /// Decodes the data field of a FIT radar threat event.
+    /// The data field packs the maximum threat level, threat count, average approach speed, and maximum approach speed, from the lowest byte to the highest.
+    pub fn from_event_data(date_time_ms: u64, event_data: u32) -> Self {
+        let threat_level = (event_data & 0xFF) as u8;
+        let threat_count = ((event_data >> 8) & 0xFF) as u8;
+        let raw_approach_speed = (event_data >> 16) & 0xFF;
+
+        // Approach speed is stored in tenths of a meter per second, and is not recorded by every radar.
+        let mut approach_speed = None;
+        if raw_approach_speed != 0 && raw_approach_speed != 0xFF {
+            approach_speed = Some(raw_approach_speed as f64 / 10.0);
+        }
+
+        RadarThreat{ timestamp_ms: date_time_ms, threat_level, threat_count, approach_speed, latitude: None, longitude: None }
+    }
+}
+
+pub struct RadarAnalyzer {
+    pub threats: Vec<RadarThreat>,
+    pub threat_count: u64, // Number of alerts where a vehicle was approaching
+    pub threats_per_hour: f64,
+}
+
+impl RadarAnalyzer {
+    /// Creates a new [`RadarAnalyzer`].
+    pub fn new() -> Self {
+        RadarAnalyzer{ threats: Vec::new(), threat_count: 0, threats_per_hour: 0.0 }
+    }
+
+    /// Adds a radar threat alert, such as one decoded with [`RadarThreat::from_event_data`]. It's placed on the track by analyze.
+    pub fn append_threat(&mut self, threat: RadarThreat) -> RadarThreat {
+        self.threats.push(threat);
+        threat
+    }
+
+    /// Called after all data is loaded. Places each threat on the track and computes the summary statistics.
+    pub fn analyze(&mut self, location_analyzer: &LocationAnalyzer) {
+        self.threat_count = 0;
+
+        for threat in self.threats.iter_mut() {
+            if let Some(index) = location_analyzer.find_nearest_index(threat.timestamp_ms) {
+                threat.latitude = Some(location_analyzer.latitude_readings[index]);
+                threat.longitude = Some(location_analyzer.longitude_readings[index]);
+            }
+            if threat.threat_level >= THREAT_LEVEL_APPROACHING {
+                self.threat_count += 1;
+            }
+        }
+
+        let duration_ms = location_analyzer.last_time_ms - location_analyzer.start_time_ms;
+        if duration_ms > 0 {
+            self.threats_per_hour = self.threat_count as f64 / (duration_ms as f64 / MS_PER_HOUR);
+        }
+    }
+}
+
+impl Default for RadarAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::location_analyzer::LocationAnalyzer;
+    use crate::radar_analyzer::{RadarAnalyzer, RadarThreat};
+
+    const START_TIME_MS: u64 = 1600000000000;
+
+    fn threat(timestamp_ms: u64, threat_level: u8, threat_count: u8) -> RadarThreat {
+        RadarThreat{ timestamp_ms, threat_level, threat_count, approach_speed: None, latitude: None, longitude: None }
+    }
+
+    #[test]
+    fn test_from_event_data() {
+
+        // Threat level 2, three vehicles, approaching at 12.5 m/s.
+        let threat = RadarThreat::from_event_data(1000, (125 << 16) | (3 << 8) | 2);
+        assert_eq!(threat.timestamp_ms, 1000);
+        assert_eq!(threat.threat_level, 2);
+        assert_eq!(threat.threat_count, 3);
+        assert_eq!(threat.approach_speed, Some(12.5));
+
+        // Radars that don't measure the approach speed leave it zero or invalid.
+        assert_eq!(RadarThreat::from_event_data(2000, (1 << 8) | 1).approach_speed, None);
+        assert_eq!(RadarThreat::from_event_data(3000, (0xFF << 16) | (1 << 8) | 1).approach_speed, None);
+    }
+
+    #[test]
+    fn test_place_threats() {
+        let mut analyzer = RadarAnalyzer::new();
+        let mut location_analyzer = LocationAnalyzer::new();
+
+        for i in 0..10 {
+            location_analyzer.append_location(START_TIME_MS + i * 10000, 45.0 + i as f64 * 0.001, -75.0, None);
+        }
+        analyzer.append_threat(threat(START_TIME_MS + 24000, 2, 1)); // Closer to the third point than the fourth
+        analyzer.append_threat(threat(START_TIME_MS + 26000, 2, 1)); // Closer to the fourth point
+        analyzer.append_threat(threat(START_TIME_MS - 5000, 2, 1)); // Before the track starts
+        analyzer.append_threat(threat(START_TIME_MS + 200000, 2, 1)); // After the track ends
+        analyzer.analyze(&location_analyzer);
+
+        let latitudes: Vec<f64> = analyzer.threats.iter().map(|threat| threat.latitude.unwrap()).collect();
+        let expected = [45.002, 45.003, 45.0, 45.009];
+        for (latitude, expected) in latitudes.iter().zip(expected.iter()) {
+            assert!((latitude - expected).abs() < 0.000001);
+        }
+        assert!(analyzer.threats.iter().all(|threat| threat.longitude == Some(-75.0)));
+
+        // Without a track there's nowhere to put them.
+        let mut analyzer = RadarAnalyzer::new();
+        analyzer.append_threat(threat(START_TIME_MS, 2, 1));
+        analyzer.analyze(&LocationAnalyzer::new());
+        assert_eq!(analyzer.threats[0].latitude, None);
+        assert_eq!(analyzer.threats[0].longitude, None);
+    }
+
+    #[test]
+    fn test_analyze() {
+        let mut analyzer = RadarAnalyzer::new();
+        let mut location_analyzer = LocationAnalyzer::new();
+
+        for i in 0..=360 {
+            location_analyzer.append_location(START_TIME_MS + i * 10000, 45.0 + i as f64 * 0.0001, -75.0, None);
+        }
+        analyzer.append_threat(threat(START_TIME_MS + 100000, 2, 1));
+        analyzer.append_threat(threat(START_TIME_MS + 200000, 1, 0)); // All clear
+        analyzer.append_threat(threat(START_TIME_MS + 300000, 3, 1));
+        analyzer.analyze(&location_analyzer);
+
+        // Only alerts with an approaching vehicle count, over the hour long track.
+        assert_eq!(analyzer.threat_count, 2);
+        assert!((analyzer.threats_per_hour - 2.0).abs() < 0.000001);
+        assert!((analyzer.threats[0].latitude.unwrap() - 45.001).abs() < 0.000001);
+        assert_eq!(analyzer.threats[0].longitude, Some(-75.0));
+    }
+}
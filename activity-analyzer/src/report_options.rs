@@ -0,0 +1,205 @@
+// Copyright (c) 2021 Michael J. Simms. All rights reserved.
+
+use crate::downsample;
+use serde_json::Value;
+
+/// The time series in the report, each listed along with the series that share its timestamps.
+/// Series in the same group have to be thinned out the same way so that they stay aligned.
+const SERIES_GROUPS: &[(&str, &[&str])] = &[
+    ("Times", &["Latitude Readings", "Longitude Readings", "Altitude Readings", "Distance Readings", "Smoothed Latitude Readings", "Smoothed Longitude Readings"]),
+    ("Speed Times", &["Speeds"]),
+    ("Power Times", &["Power Readings"]),
+    ("Cadence Times", &["Cadence Readings"]),
+    ("Heart Rate Times", &["Heart Rate Readings"]),
+    ("Temperature Times", &["Temperature Readings"]),
+    ("Cycling Dynamics Times", &["Left/Right Balance Readings", "Left Torque Effectiveness Readings", "Right Torque Effectiveness Readings", "Left Pedal Smoothness Readings", "Right Pedal Smoothness Readings"]),
+    ("Ground Contact Time Times", &["Ground Contact Time Readings"]),
+    ("Vertical Oscillation Times", &["Vertical Oscillation Readings"]),
+    ("Vertical Ratio Times", &["Vertical Ratio Readings"]),
+    ("Step Length Times", &["Step Length Readings"]),
+    ("Ground Contact Time Balance Times", &["Ground Contact Time Balance Readings"]),
+    ("Swim Stroke Times", &["Swim Stroke Readings"]),
+];
+
+/// Series that don't have their own timestamps.
+const UNTIMED_SERIES: &[&str] = &["Gradient Curve"];
+
+/// Controls how much of the raw data ends up in the report. The summary values are always computed from the full data.
+pub struct ReportOptions {
+    pub include_series: bool, // If false, the time series are left out of the report
+    pub max_series_points: usize, // If non-zero, longer time series are downsampled to this many points
+}
+
+impl ReportOptions {
+    /// Creates a new [`ReportOptions`] that includes everything.
+    pub const fn new() -> Self {
+        ReportOptions{ include_series: true, max_series_points: 0 }
+    }
+
+    /// Returns the values of the series as numbers, or None if it isn't an array of the expected length.
+    /// Missing readings (nulls) are treated as zero.
+    fn series_values(value: Option<&Value>, num_points: usize) -> Option<Vec<f64>> {
+        match value {
+            Some(Value::Array(array)) if array.len() == num_points => Some(array.iter().map(|item| item.as_f64().unwrap_or(0.0)).collect()),
+            _ => None,
+        }
+    }
+
+    /// Returns the indices of the points to keep when reducing the group to the maximum number of points.
+    /// The location track is simplified with Douglas-Peucker so the shape of the route is kept. Everything else uses
+    /// Largest-Triangle-Three-Buckets on the first series in the group, against its timestamps (or index, if there aren't any).
+    fn select_indices(&self, map: &serde_json::Map<String, Value>, times_key: &str, series_keys: &[&str], num_points: usize) -> Vec<usize> {
+        if times_key == "Times" {
+            let lats = ReportOptions::series_values(map.get("Latitude Readings"), num_points);
+            let lons = ReportOptions::series_values(map.get("Longitude Readings"), num_points);
+
+            if let (Some(lats), Some(lons)) = (lats, lons) {
+                return downsample::douglas_peucker(&lats, &lons, self.max_series_points);
+            }
+        }
+
+        let x = ReportOptions::series_values(map.get(times_key), num_points).unwrap_or_else(|| (0..num_points).map(|index| index as f64).collect());
+        let y = series_keys.iter().find_map(|key| ReportOptions::series_values(map.get(*key), num_points));
+
+        match y {
+            Some(y) => downsample::lttb(&x, &y, self.max_series_points),
+            None => downsample::lttb(&x, &x, self.max_series_points),
+        }
+    }
+
+    /// Replaces the array with the values at the given indices. Does nothing if the value isn't an array of the expected length.
+    fn keep_indices(value: Option<&mut Value>, num_points: usize, indices: &[usize]) {
+        if let Some(Value::Array(array)) = value {
+            if array.len() == num_points {
+                let selected: Vec<Value> = indices.iter().map(|index| array[*index].take()).collect();
+                *array = selected;
+            }
+        }
+    }
+
+    /// Returns the length of the array, or None if the value isn't an array.
+    fn array_len(value: Option<&Value>) -> Option<usize> {
+        match value {
+            Some(Value::Array(array)) => Some(array.len()),
+            _ => None,
+        }
+    }
+
+    /// Downsamples the time series in the report. Leaving the series out is up to whoever builds the report,
+    /// since there's no point serializing them only to throw them away.
+    pub fn apply(&self, report: &mut Value) {
+        if let Value::Object(map) = report {
+            if self.max_series_points == 0 {
+                return;
+            }
+
+            for (times_key, series_keys) in SERIES_GROUPS.iter() {
+
+                // Route reports don't have timestamps, so fall back to the length of the first series.
+                let keys: Vec<&str> = std::iter::once(*times_key).chain(series_keys.iter().copied()).collect();
+                let num_points = keys.iter().find_map(|key| ReportOptions::array_len(map.get(*key)));
+
+                if let Some(num_points) = num_points {
+                    let indices = self.select_indices(map, times_key, series_keys, num_points);
+
+                    for key in keys.iter() {
+                        ReportOptions::keep_indices(map.get_mut(*key), num_points, &indices);
+                    }
+                }
+            }
+            for series_key in UNTIMED_SERIES.iter() {
+                if let Some(num_points) = ReportOptions::array_len(map.get(*series_key)) {
+                    let indices = self.select_indices(map, "", &[*series_key], num_points);
+                    ReportOptions::keep_indices(map.get_mut(*series_key), num_points, &indices);
+                }
+            }
+        }
+    }
+}
+
+impl Default for ReportOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::report_options::ReportOptions;
+    use serde_json::{json, Value};
+
+    fn values(report: &Value, key: &str) -> Vec<f64> {
+        report[key].as_array().unwrap().iter().map(|value| value.as_f64().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_apply_keeps_groups_aligned() {
+        let times: Vec<u64> = (0..100).map(|index| index * 1000).collect();
+        let distances: Vec<f64> = (0..100).map(|index| index as f64 * 3.0).collect();
+        let heart_rates: Vec<f64> = (0..50).map(|index| if index == 20 { 190.0 } else { 120.0 }).collect();
+        let heart_rate_times: Vec<u64> = (0..50).map(|index| index * 2000).collect();
+        let mut report = json!({
+            "Times": times,
+            "Latitude Readings": (0..100).map(|index| 40.0 + index as f64 * 0.0001).collect::<Vec<f64>>(),
+            "Longitude Readings": (0..100).map(|index| -75.0 + if index < 50 { 0.0 } else { (index - 50) as f64 * 0.0001 }).collect::<Vec<f64>>(),
+            "Distance Readings": distances,
+            "Heart Rate Times": heart_rate_times,
+            "Heart Rate Readings": heart_rates,
+            "Gradient Curve": vec![0.0; 30],
+            "Total Distance": 297.0,
+        });
+
+        ReportOptions{ include_series: true, max_series_points: 10 }.apply(&mut report);
+
+        // The track keeps its ends and its corner, and the rest of its group follows it.
+        let times = values(&report, "Times");
+        assert!(times.len() <= 10);
+        assert_eq!(times.first(), Some(&0.0));
+        assert_eq!(times.last(), Some(&99000.0));
+        assert!(times.contains(&49000.0) || times.contains(&50000.0));
+        let distances = values(&report, "Distance Readings");
+        assert_eq!(distances.len(), times.len());
+        assert!(times.iter().zip(distances.iter()).all(|(time, distance)| *distance == time / 1000.0 * 3.0));
+        assert_eq!(values(&report, "Latitude Readings").len(), times.len());
+
+        // The heart rate spike is kept, at its own time.
+        let heart_rate_times = values(&report, "Heart Rate Times");
+        let heart_rates = values(&report, "Heart Rate Readings");
+        assert_eq!(heart_rates.len(), 10);
+        assert_eq!(heart_rate_times.len(), 10);
+        let spike = heart_rates.iter().position(|value| *value == 190.0).unwrap();
+        assert_eq!(heart_rate_times[spike], 40000.0);
+
+        // Series without timestamps are thinned out too, and the summary values are left alone.
+        assert_eq!(values(&report, "Gradient Curve").len(), 10);
+        assert_eq!(report["Total Distance"], 297.0);
+    }
+
+    #[test]
+    fn test_apply_route_and_short_series() {
+
+        // Route reports have a track, but no timestamps.
+        let mut report = json!({
+            "Latitude Readings": (0..100).map(|index| 40.0 + index as f64 * 0.0001).collect::<Vec<f64>>(),
+            "Longitude Readings": vec![-75.0; 100],
+            "Distance Readings": (0..100).map(|index| index as f64).collect::<Vec<f64>>(),
+            "Heart Rate Times": [0, 1000],
+            "Heart Rate Readings": [120.0, 130.0],
+        });
+        ReportOptions{ include_series: true, max_series_points: 10 }.apply(&mut report);
+
+        let distances = values(&report, "Distance Readings");
+        assert!(distances.len() <= 10);
+        assert_eq!(values(&report, "Latitude Readings").len(), distances.len());
+        assert_eq!(distances.first(), Some(&0.0));
+        assert_eq!(distances.last(), Some(&99.0));
+
+        // Series that are already short enough are untouched.
+        assert_eq!(values(&report, "Heart Rate Readings"), vec![120.0, 130.0]);
+
+        // Without a limit, nothing is thinned out.
+        let mut report = json!({ "Heart Rate Times": (0..100).collect::<Vec<u64>>(), "Heart Rate Readings": vec![120.0; 100] });
+        ReportOptions::new().apply(&mut report);
+        assert_eq!(values(&report, "Heart Rate Readings").len(), 100);
+    }
+}
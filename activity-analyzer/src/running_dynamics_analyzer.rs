@@ -0,0 +1,281 @@
+// Copyright (c) 2021 Michael J. Simms. All rights reserved.
+
+use crate::location_analyzer::LocationAnalyzer;
+use fit_file::fit_file::FitRecordMsg;
+use serde::Serialize;
+
+/// Running dynamics averages for a single split.
+#[derive(Clone, Copy, Serialize)]
+pub struct RunningDynamicsSplit {
+    pub ground_contact_time: f64,
+    pub vertical_oscillation: f64,
+    pub vertical_ratio: f64,
+    pub step_length: f64,
+    pub ground_contact_time_balance: f64,
+}
+
+pub struct RunningDynamicsAnalyzer {
+    pub ground_contact_time_readings: Vec<f64>, // Stance time (in milliseconds)
+    pub ground_contact_time_times: Vec<u64>,
+    pub vertical_oscillation_readings: Vec<f64>, // Vertical oscillation (in millimeters)
+    pub vertical_oscillation_times: Vec<u64>,
+    pub vertical_ratio_readings: Vec<f64>, // Vertical oscillation divided by step length (percent)
+    pub vertical_ratio_times: Vec<u64>,
+    pub step_length_readings: Vec<f64>, // Step length (in millimeters)
+    pub step_length_times: Vec<u64>,
+    pub ground_contact_time_balance_readings: Vec<f64>, // Percentage of the stance time spent on the left foot
+    pub ground_contact_time_balance_times: Vec<u64>,
+    pub km_splits: Vec<RunningDynamicsSplit>, // Averages for each kilometer
+    pub mile_splits: Vec<RunningDynamicsSplit>, // Averages for each mile
+}
+
+impl RunningDynamicsAnalyzer {
+    /// Creates a new [`RunningDynamicsAnalyzer`].
+    pub fn new() -> Self {
+        RunningDynamicsAnalyzer{ ground_contact_time_readings: Vec::new(), ground_contact_time_times: Vec::new(), vertical_oscillation_readings: Vec::new(),
+            vertical_oscillation_times: Vec::new(), vertical_ratio_readings: Vec::new(), vertical_ratio_times: Vec::new(), step_length_readings: Vec::new(),
+            step_length_times: Vec::new(), ground_contact_time_balance_readings: Vec::new(), ground_contact_time_balance_times: Vec::new(),
+            km_splits: Vec::new(), mile_splits: Vec::new() }
+    }
+
+    /// Computes the average of the given readings.
+    pub fn compute_average(readings: &[f64]) -> f64 {
+        let count = readings.len();
+        if count > 0 {
+            let sum: f64 = Iterator::sum(readings.iter());
+            return sum / (count as f64);
+        }
+        0.0
+    }
+
+    /// Computes the average of the readings that fall within the given time range.
+    fn compute_range_average(readings: &[f64], time_readings: &[u64], start_time_ms: u64, end_time_ms: u64) -> f64 {
+        let mut sum = 0.0;
+        let mut count = 0;
+
+        for (reading, time) in readings.iter().zip(time_readings.iter()) {
+            if *time >= start_time_ms && *time <= end_time_ms {
+                sum += reading;
+                count += 1;
+            }
+        }
+        if count > 0 {
+            return sum / (count as f64);
+        }
+        0.0
+    }
+
+    /// Computes the averages for each split. Splits are given as the number of seconds from the start of the activity, as computed by the location analyzer.
+    fn compute_splits(&self, location_analyzer: &LocationAnalyzer, splits: &[f64]) -> Vec<RunningDynamicsSplit> {
+        let mut result = Vec::new();
+        let mut split_start_ms = location_analyzer.start_time_ms;
+
+        for split in splits {
+            let split_end_ms = location_analyzer.moving_seconds_to_timestamp(*split as u64);
+
+            result.push(RunningDynamicsSplit{
+                ground_contact_time: RunningDynamicsAnalyzer::compute_range_average(&self.ground_contact_time_readings, &self.ground_contact_time_times, split_start_ms, split_end_ms),
+                vertical_oscillation: RunningDynamicsAnalyzer::compute_range_average(&self.vertical_oscillation_readings, &self.vertical_oscillation_times, split_start_ms, split_end_ms),
+                vertical_ratio: RunningDynamicsAnalyzer::compute_range_average(&self.vertical_ratio_readings, &self.vertical_ratio_times, split_start_ms, split_end_ms),
+                step_length: RunningDynamicsAnalyzer::compute_range_average(&self.step_length_readings, &self.step_length_times, split_start_ms, split_end_ms),
+                ground_contact_time_balance: RunningDynamicsAnalyzer::compute_range_average(&self.ground_contact_time_balance_readings, &self.ground_contact_time_balance_times, split_start_ms, split_end_ms),
+            });
+            split_start_ms = split_end_ms;
+        }
+        result
+    }
+
+    /// Returns true if any running dynamics data was recorded.
+    pub fn has_data(&self) -> bool {
+        !self.ground_contact_time_readings.is_empty() || !self.vertical_oscillation_readings.is_empty() || !self.step_length_readings.is_empty()
+    }
+
+    /// Adds another ground contact time reading (in milliseconds) to the analyzer.
+    pub fn append_ground_contact_time(&mut self, date_time_ms: u64, value: f64) {
+        self.ground_contact_time_readings.push(value);
+        self.ground_contact_time_times.push(date_time_ms);
+    }
+
+    /// Adds another vertical oscillation reading (in millimeters) to the analyzer.
+    pub fn append_vertical_oscillation(&mut self, date_time_ms: u64, value: f64) {
+        self.vertical_oscillation_readings.push(value);
+        self.vertical_oscillation_times.push(date_time_ms);
+    }
+
+    /// Adds another vertical ratio reading (percent) to the analyzer.
+    pub fn append_vertical_ratio(&mut self, date_time_ms: u64, value: f64) {
+        self.vertical_ratio_readings.push(value);
+        self.vertical_ratio_times.push(date_time_ms);
+    }
+
+    /// Adds another step length reading (in millimeters) to the analyzer.
+    pub fn append_step_length(&mut self, date_time_ms: u64, value: f64) {
+        self.step_length_readings.push(value);
+        self.step_length_times.push(date_time_ms);
+    }
+
+    /// Adds another ground contact time balance reading (percent left) to the analyzer.
+    pub fn append_ground_contact_time_balance(&mut self, date_time_ms: u64, value: f64) {
+        self.ground_contact_time_balance_readings.push(value);
+        self.ground_contact_time_balance_times.push(date_time_ms);
+    }
+
+    /// Adds the running dynamics from a FIT record message, converting them from the units they're stored in. Fields that are
+    /// missing, or set to the FIT invalid value, are skipped.
+    pub fn append_fit_record(&mut self, timestamp_ms: u64, msg: &FitRecordMsg) {
+        if let Some(stance_time) = msg.stance_time {
+
+            // Make sure we have a valid reading.
+            if stance_time < 65535 {
+                // Apply scaling (stored in tenths of a millisecond).
+                self.append_ground_contact_time(timestamp_ms, stance_time as f64 / 10.0);
+            }
+        }
+
+        if let Some(vertical_oscillation) = msg.vertical_oscillation {
+
+            // Make sure we have a valid reading.
+            if vertical_oscillation < 65535 {
+                // Apply scaling (stored in tenths of a millimeter).
+                self.append_vertical_oscillation(timestamp_ms, vertical_oscillation as f64 / 10.0);
+            }
+        }
+
+        if let Some(vertical_ratio) = msg.vertical_ratio {
+
+            // Make sure we have a valid reading.
+            if vertical_ratio < 65535 {
+                // Apply scaling (stored in hundredths of a percent).
+                self.append_vertical_ratio(timestamp_ms, vertical_ratio as f64 / 100.0);
+            }
+        }
+
+        if let Some(step_length) = msg.step_length {
+
+            // Make sure we have a valid reading.
+            if step_length < 65535 {
+                // Apply scaling (stored in tenths of a millimeter).
+                self.append_step_length(timestamp_ms, step_length as f64 / 10.0);
+            }
+        }
+
+        if let Some(stance_time_balance) = msg.stance_time_balance {
+
+            // Make sure we have a valid reading.
+            if stance_time_balance < 65535 {
+                // Apply scaling (stored in hundredths of a percent).
+                self.append_ground_contact_time_balance(timestamp_ms, stance_time_balance as f64 / 100.0);
+            }
+        }
+    }
+
+    /// Called after all data is loaded.
+    pub fn analyze(&mut self, location_analyzer: &LocationAnalyzer) {
+        if self.has_data() {
+            self.km_splits = self.compute_splits(location_analyzer, &location_analyzer.km_splits);
+            self.mile_splits = self.compute_splits(location_analyzer, &location_analyzer.mile_splits);
+        }
+    }
+}
+
+impl Default for RunningDynamicsAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::location_analyzer::LocationAnalyzer;
+    use crate::running_dynamics_analyzer::RunningDynamicsAnalyzer;
+    use fit_file::fit_file::FitRecordMsg;
+
+    const START_TIME_MS: u64 = 1600000000000;
+
+    /// A run at about 2.5 meters/second, with a location every second. If paused, the timer is stopped for ten minutes after 600 seconds.
+    /// Ground contact time is 250 ms for the first 400 seconds, 300 ms until the pause, and 350 ms after it.
+    fn make_run(paused: bool) -> (LocationAnalyzer, RunningDynamicsAnalyzer) {
+        let mut location_analyzer = LocationAnalyzer::new();
+        let mut dynamics_analyzer = RunningDynamicsAnalyzer::new();
+
+        for index in 0..1000 {
+            let time_ms = START_TIME_MS + index * 1000 + if paused && index >= 600 { 600000 } else { 0 };
+            if paused && index == 600 {
+                location_analyzer.stop_timer(time_ms - 600000);
+                location_analyzer.start_timer(time_ms);
+            }
+            location_analyzer.append_location(time_ms, 40.0 + index as f64 * 0.00002247, -75.0, None);
+            location_analyzer.update_speeds();
+
+            let ground_contact_time = match index {
+                0..=399 => 250.0,
+                400..=599 => 300.0,
+                _ => if paused { 350.0 } else { 300.0 },
+            };
+            dynamics_analyzer.append_ground_contact_time(time_ms, ground_contact_time);
+            dynamics_analyzer.append_step_length(time_ms, 1250.0);
+        }
+        location_analyzer.analyze();
+        dynamics_analyzer.analyze(&location_analyzer);
+        (location_analyzer, dynamics_analyzer)
+    }
+
+    #[test]
+    fn test_append_fit_record() {
+        let mut analyzer = RunningDynamicsAnalyzer::new();
+
+        let mut msg = FitRecordMsg::new(Vec::new());
+        msg.stance_time = Some(2505);
+        msg.vertical_oscillation = Some(853);
+        msg.vertical_ratio = Some(812);
+        msg.step_length = Some(11504);
+        msg.stance_time_balance = Some(4987);
+        analyzer.append_fit_record(START_TIME_MS, &msg);
+
+        assert_eq!(analyzer.ground_contact_time_readings, vec![250.5]);
+        assert_eq!(analyzer.vertical_oscillation_readings, vec![85.3]);
+        assert_eq!(analyzer.vertical_ratio_readings, vec![8.12]);
+        assert_eq!(analyzer.step_length_readings, vec![1150.4]);
+        assert_eq!(analyzer.ground_contact_time_balance_readings, vec![49.87]);
+        assert_eq!(analyzer.step_length_times, vec![START_TIME_MS]);
+
+        // Invalid and missing values are skipped.
+        let mut msg = FitRecordMsg::new(Vec::new());
+        msg.stance_time = Some(65535);
+        msg.step_length = Some(65535);
+        analyzer.append_fit_record(START_TIME_MS + 1000, &msg);
+        assert_eq!(analyzer.ground_contact_time_readings.len(), 1);
+        assert_eq!(analyzer.step_length_readings.len(), 1);
+        assert_eq!(analyzer.vertical_oscillation_readings.len(), 1);
+    }
+
+    #[test]
+    fn test_compute_average() {
+        assert_eq!(RunningDynamicsAnalyzer::compute_average(&[]), 0.0);
+        assert_eq!(RunningDynamicsAnalyzer::compute_average(&[250.0]), 250.0);
+        assert_eq!(RunningDynamicsAnalyzer::compute_average(&[1.0, 2.0, 3.0, 6.0]), 3.0);
+    }
+
+    #[test]
+    fn test_splits() {
+        let (location_analyzer, dynamics_analyzer) = make_run(false);
+        assert_eq!(dynamics_analyzer.km_splits.len(), location_analyzer.km_splits.len());
+        assert_eq!(dynamics_analyzer.km_splits.len(), 3);
+        assert!((dynamics_analyzer.km_splits[0].ground_contact_time - 250.0).abs() < 1.0);
+        assert!((dynamics_analyzer.km_splits[1].ground_contact_time - 300.0).abs() < 1.0);
+        assert_eq!(dynamics_analyzer.km_splits[1].step_length, 1250.0);
+        assert_eq!(dynamics_analyzer.km_splits[1].vertical_oscillation, 0.0);
+    }
+
+    #[test]
+    fn test_splits_exclude_pauses() {
+        let (location_analyzer, dynamics_analyzer) = make_run(true);
+        assert_eq!(dynamics_analyzer.km_splits.len(), 3);
+        assert!((dynamics_analyzer.km_splits[0].ground_contact_time - 250.0).abs() < 1.0);
+
+        // The second kilometer is half before the pause and half after it.
+        assert!((dynamics_analyzer.km_splits[1].ground_contact_time - 325.0).abs() < 1.0);
+        assert!((dynamics_analyzer.km_splits[2].ground_contact_time - 350.0).abs() < 1.0);
+        assert!(location_analyzer.moving_seconds_to_timestamp(location_analyzer.km_splits[1] as u64) > START_TIME_MS + 1200000);
+    }
+}
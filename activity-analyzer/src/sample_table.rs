@@ -0,0 +1,275 @@
+// Copyright (c) 2021 Michael J. Simms. All rights reserved.
+
+use crate::analyzer_context::AnalyzerContext;
+
+/// The columns of a [`SampleTable`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Column {
+    Latitude,
+    Longitude,
+    Altitude, // Meters
+    Distance, // Meters traveled since the start
+    Speed, // Meters/second
+    HeartRate, // Beats/minute
+    Cadence, // Revolutions (or steps)/minute
+    Power, // Watts
+    Temperature, // Degrees C
+    LeftRightBalance, // Percentage of the power from the right leg
+    LeftTorqueEffectiveness, // Percent
+    RightTorqueEffectiveness, // Percent
+    LeftPedalSmoothness, // Percent
+    RightPedalSmoothness, // Percent
+}
+
+pub const ALL_COLUMNS: [Column; 14] = [ Column::Latitude, Column::Longitude, Column::Altitude, Column::Distance, Column::Speed, Column::HeartRate,
+    Column::Cadence, Column::Power, Column::Temperature, Column::LeftRightBalance, Column::LeftTorqueEffectiveness, Column::RightTorqueEffectiveness,
+    Column::LeftPedalSmoothness, Column::RightPedalSmoothness ];
+
+/// How to find a value at a time that doesn't have a reading.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Lookup {
+    Nearest, // Use the closest reading
+    Linear, // Interpolate between the readings on either side
+}
+
+/// The readings for one column, as held by an analyzer: the column, the times, and the values.
+type ColumnSeries<'a> = (Column, &'a [u64], Vec<Option<f64>>);
+
+/// All of an activity's readings in one table, with a row for every timestamp at which anything was recorded
+/// and a column for each type of reading. Cells are None where that sensor didn't have a reading at that time.
+pub struct SampleTable {
+    pub times: Vec<u64>, // Timestamps (in milliseconds), sorted, with no duplicates
+    columns: Vec<Vec<Option<f64>>>, // One per entry in ALL_COLUMNS, each the same length as times
+    reading_rows: Vec<Vec<usize>>, // For each column, the rows that have a reading, so lookups don't have to scan past the empty cells
+}
+
+impl SampleTable {
+    /// Creates a new, empty [`SampleTable`].
+    pub fn new() -> Self {
+        SampleTable{ times: Vec::new(), columns: vec![Vec::new(); ALL_COLUMNS.len()], reading_rows: vec![Vec::new(); ALL_COLUMNS.len()] }
+    }
+
+    /// Builds the table from the readings held by each analyzer.
+    pub fn from_context(context: &AnalyzerContext) -> Self {
+        let loc_data = &context.location_analyzer;
+        let power_data = &context.power_analyzer;
+        let (indoor_distance_times, indoor_distances) = loc_data.get_indoor_distances();
+
+        let mut series: Vec<ColumnSeries> = vec![
+            (Column::Latitude, loc_data.times.as_slice(), loc_data.latitude_readings.iter().map(|value| Some(*value)).collect()),
+            (Column::Longitude, loc_data.times.as_slice(), loc_data.longitude_readings.iter().map(|value| Some(*value)).collect()),
+            (Column::Distance, loc_data.times.as_slice(), loc_data.distance_readings.iter().map(|value| Some(*value)).collect()),
+            (Column::Distance, indoor_distance_times.as_slice(), indoor_distances.iter().map(|value| Some(*value)).collect()),
+            (Column::Speed, loc_data.speed_times.as_slice(), loc_data.speed_graph.iter().map(|value| Some(*value)).collect()),
+            (Column::HeartRate, context.hr_analyzer.time_readings.as_slice(), context.hr_analyzer.readings.iter().map(|value| Some(*value)).collect()),
+            (Column::Cadence, context.cadence_analyzer.time_readings.as_slice(), context.cadence_analyzer.readings.iter().map(|value| Some(*value)).collect()),
+            (Column::Power, power_data.time_readings.as_slice(), power_data.readings.iter().map(|value| Some(*value)).collect()),
+            (Column::Temperature, context.temperature_analyzer.time_readings.as_slice(), context.temperature_analyzer.readings.iter().map(|value| Some(*value)).collect()),
+            (Column::LeftRightBalance, power_data.cycling_dynamics_times.as_slice(), power_data.left_right_balance_readings.clone()),
+            (Column::LeftTorqueEffectiveness, power_data.cycling_dynamics_times.as_slice(), power_data.left_torque_effectiveness_readings.clone()),
+            (Column::RightTorqueEffectiveness, power_data.cycling_dynamics_times.as_slice(), power_data.right_torque_effectiveness_readings.clone()),
+            (Column::LeftPedalSmoothness, power_data.cycling_dynamics_times.as_slice(), power_data.left_pedal_smoothness_readings.clone()),
+            (Column::RightPedalSmoothness, power_data.cycling_dynamics_times.as_slice(), power_data.right_pedal_smoothness_readings.clone()),
+        ];
+        if let Some(altitude_readings) = loc_data.get_altitude_readings() {
+            series.push((Column::Altitude, loc_data.times.as_slice(), altitude_readings.iter().map(|value| Some(*value)).collect()));
+        }
+
+        // The rows are the union of all the timestamps.
+        let mut times: Vec<u64> = series.iter().flat_map(|(_, series_times, _)| series_times.iter().copied()).collect();
+        times.sort_unstable();
+        times.dedup();
+
+        let mut table = SampleTable::new();
+        table.columns = vec![vec![None; times.len()]; ALL_COLUMNS.len()];
+        table.times = times;
+
+        for (column, series_times, values) in series.iter() {
+            let cells = &mut table.columns[*column as usize];
+
+            for (time, value) in series_times.iter().zip(values.iter()) {
+                if let Ok(row) = table.times.binary_search(time) {
+                    if value.is_some() {
+                        cells[row] = *value;
+                    }
+                }
+            }
+        }
+        table.index_readings();
+        table
+    }
+
+    /// Notes which rows of each column have a reading.
+    fn index_readings(&mut self) {
+        self.reading_rows = self.columns.iter().map(|cells| (0..cells.len()).filter(|row| cells[*row].is_some()).collect()).collect();
+    }
+
+    /// Returns the number of rows.
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    /// Returns true if the table doesn't have any rows.
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    /// Returns all the cells in the column.
+    pub fn column(&self, column: Column) -> &[Option<f64>] {
+        &self.columns[column as usize]
+    }
+
+    /// Returns true if the column has at least one reading.
+    pub fn has_column(&self, column: Column) -> bool {
+        !self.reading_rows[column as usize].is_empty()
+    }
+
+    /// Returns the rows at which the column has a reading.
+    pub fn reading_rows(&self, column: Column) -> &[usize] {
+        &self.reading_rows[column as usize]
+    }
+
+    /// Returns the cell at the given row.
+    pub fn get(&self, column: Column, row: usize) -> Option<f64> {
+        self.columns[column as usize][row]
+    }
+
+    /// Returns the value of the column at the given time. If there isn't a reading at exactly that time then one is found from
+    /// the readings on either side. Returns None before the column's first reading or after its last.
+    pub fn value_at(&self, column: Column, time_ms: u64, lookup: Lookup) -> Option<f64> {
+        let cells = self.column(column);
+        let rows = &self.reading_rows[column as usize];
+
+        // Find the first reading at or after the requested time.
+        let index = rows.partition_point(|row| self.times[*row] < time_ms);
+        if index < rows.len() && self.times[rows[index]] == time_ms {
+            return cells[rows[index]];
+        }
+
+        // Find the readings before and after the requested time.
+        let before = if index > 0 { Some(rows[index - 1]) } else { None };
+        let after = rows.get(index).copied();
+
+        match (before, after) {
+            (Some(before), Some(after)) => {
+                let before_time = self.times[before];
+                let after_time = self.times[after];
+                let before_value = cells[before].unwrap();
+                let after_value = cells[after].unwrap();
+
+                match lookup {
+                    Lookup::Nearest => {
+                        if time_ms - before_time <= after_time - time_ms {
+                            return Some(before_value);
+                        }
+                        Some(after_value)
+                    }
+                    Lookup::Linear => {
+                        let fraction = (time_ms - before_time) as f64 / (after_time - before_time) as f64;
+                        Some(before_value + (after_value - before_value) * fraction)
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns a new table with a row every 'interval_ms' milliseconds, from the first timestamp to the last,
+    /// with each cell found using the given lookup.
+    pub fn resample(&self, interval_ms: u64, lookup: Lookup) -> SampleTable {
+        let mut result = SampleTable::new();
+
+        if self.times.is_empty() || interval_ms == 0 {
+            return result;
+        }
+
+        let start_time_ms = self.times[0];
+        let end_time_ms = self.times[self.times.len() - 1];
+        let mut time_ms = start_time_ms;
+
+        while time_ms <= end_time_ms {
+            result.times.push(time_ms);
+            for column in ALL_COLUMNS.iter() {
+                result.columns[*column as usize].push(self.value_at(*column, time_ms, lookup));
+            }
+            time_ms += interval_ms;
+        }
+        result.index_readings();
+        result
+    }
+}
+
+impl Default for SampleTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analyzer_context::AnalyzerContext;
+    use crate::sample_table::{Column, Lookup, SampleTable};
+
+    const START_TIME_MS: u64 = 1600000000000;
+
+    /// Heart rate every ten seconds, and power at five seconds and twenty seconds.
+    fn make_table() -> SampleTable {
+        let mut context = AnalyzerContext::new();
+
+        context.hr_analyzer.append_sensor_value(START_TIME_MS, 100.0);
+        context.hr_analyzer.append_sensor_value(START_TIME_MS + 10000, 120.0);
+        context.hr_analyzer.append_sensor_value(START_TIME_MS + 20000, 110.0);
+        context.power_analyzer.append_sensor_value(START_TIME_MS + 5000, 200.0);
+        context.power_analyzer.append_sensor_value(START_TIME_MS + 20000, 350.0);
+        SampleTable::from_context(&context)
+    }
+
+    #[test]
+    fn test_from_context() {
+        let table = make_table();
+
+        // One row for every time at which anything was recorded.
+        assert_eq!(table.times, vec![START_TIME_MS, START_TIME_MS + 5000, START_TIME_MS + 10000, START_TIME_MS + 20000]);
+        assert_eq!(table.column(Column::HeartRate), &[Some(100.0), None, Some(120.0), Some(110.0)]);
+        assert_eq!(table.column(Column::Power), &[None, Some(200.0), None, Some(350.0)]);
+        assert_eq!(table.reading_rows(Column::Power), &[1, 3]);
+        assert!(table.has_column(Column::HeartRate));
+        assert!(!table.has_column(Column::Latitude));
+    }
+
+    #[test]
+    fn test_value_at() {
+        let table = make_table();
+
+        // Exact matches.
+        assert_eq!(table.value_at(Column::HeartRate, START_TIME_MS + 10000, Lookup::Nearest), Some(120.0));
+        assert_eq!(table.value_at(Column::Power, START_TIME_MS + 5000, Lookup::Linear), Some(200.0));
+
+        // Between readings. Ties go to the earlier reading.
+        assert_eq!(table.value_at(Column::HeartRate, START_TIME_MS + 4000, Lookup::Nearest), Some(100.0));
+        assert_eq!(table.value_at(Column::HeartRate, START_TIME_MS + 5000, Lookup::Nearest), Some(100.0));
+        assert_eq!(table.value_at(Column::HeartRate, START_TIME_MS + 6000, Lookup::Nearest), Some(120.0));
+        assert_eq!(table.value_at(Column::HeartRate, START_TIME_MS + 2500, Lookup::Linear), Some(105.0));
+        assert_eq!(table.value_at(Column::Power, START_TIME_MS + 8000, Lookup::Linear), Some(230.0));
+
+        // Nothing before the first reading or after the last.
+        assert_eq!(table.value_at(Column::Power, START_TIME_MS, Lookup::Nearest), None);
+        assert_eq!(table.value_at(Column::HeartRate, START_TIME_MS + 30000, Lookup::Linear), None);
+        assert_eq!(table.value_at(Column::Latitude, START_TIME_MS, Lookup::Linear), None);
+    }
+
+    #[test]
+    fn test_resample() {
+        let table = make_table();
+        let resampled = table.resample(5000, Lookup::Linear);
+
+        assert_eq!(resampled.times, vec![START_TIME_MS, START_TIME_MS + 5000, START_TIME_MS + 10000, START_TIME_MS + 15000, START_TIME_MS + 20000]);
+        assert_eq!(resampled.column(Column::HeartRate), &[Some(100.0), Some(110.0), Some(120.0), Some(115.0), Some(110.0)]);
+        assert_eq!(resampled.column(Column::Power), &[None, Some(200.0), Some(250.0), Some(300.0), Some(350.0)]);
+        assert_eq!(resampled.reading_rows(Column::Power), &[1, 2, 3, 4]);
+
+        // Nothing to resample.
+        assert!(table.resample(0, Lookup::Linear).is_empty());
+        assert!(SampleTable::new().resample(1000, Lookup::Linear).is_empty());
+    }
+}
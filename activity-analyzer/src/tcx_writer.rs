@@ -1,10 +1,19 @@
 // Copyright (c) 2021 Michael J. Simms. All rights reserved.
 
-extern crate chrono;
-
-use chrono::*;
+use crate::utils::format_iso_timestamp;
 use xmlwriter::*;
 
+/// Lap summary values stored in Garmin's ActivityExtension (LX). Anything that is None is left out.
+#[derive(Default)]
+pub struct LapExtension {
+    pub avg_speed: Option<f64>, // Meters/second
+    pub max_bike_cadence: Option<f64>, // Revolutions/minute
+    pub avg_run_cadence: Option<f64>, // Strides/minute
+    pub max_run_cadence: Option<f64>, // Strides/minute
+    pub avg_watts: Option<f64>,
+    pub max_watts: Option<f64>,
+}
+
 pub struct TcxWriter {
     writer: XmlWriter,
 }
@@ -17,12 +26,11 @@ impl TcxWriter {
 
     pub fn open(&mut self) {
         self.writer.start_element("TrainingCenterDatabase");
+        self.writer.write_attribute("xsi:schemaLocation", "http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2 http://www.garmin.com/xmlschemas/TrainingCenterDatabasev2.xsd http://www.garmin.com/xmlschemas/ActivityExtension/v2 http://www.garmin.com/xmlschemas/ActivityExtensionv2.xsd");
         self.writer.write_attribute("xmlns", "http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2");
         self.writer.write_attribute("xmlns:xsd", "http://www.w3.org/2001/XMLSchema");
         self.writer.write_attribute("xmlns:xsi", "http://www.w3.org/2001/XMLSchema-instance");
-        self.writer.write_attribute("xmlns:tc2", "http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2");
-        self.writer.write_attribute("targetNamespace", "http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2");
-        self.writer.write_attribute("elementFormDefault", "qualified");
+        self.writer.write_attribute("xmlns:ns3", "http://www.garmin.com/xmlschemas/ActivityExtension/v2");
     }
     pub fn close(self) -> String {
         self.writer.end_document()
@@ -31,7 +39,7 @@ impl TcxWriter {
     pub fn write_id(&mut self, start_time_ms: u64) {
         self.writer.start_element("Id");
         self.writer.set_preserve_whitespaces(true);
-        self.writer.write_text(&TcxWriter::format_timestamp(start_time_ms));
+        self.writer.write_text(&format_iso_timestamp(start_time_ms));
         self.writer.end_element();
         self.writer.set_preserve_whitespaces(false);
     }
@@ -53,13 +61,12 @@ impl TcxWriter {
 
     pub fn start_lap(&mut self, start_time_ms: u64) {
         self.writer.start_element("Lap");
-        self.writer.write_attribute("StartTime", &TcxWriter::format_timestamp(start_time_ms));
+        self.writer.write_attribute("StartTime", &format_iso_timestamp(start_time_ms));
     }
     pub fn end_lap(&mut self) {
         self.writer.end_element();
     }
-    pub fn store_lap_seconds(&mut self, time_ms: u64) {
-        let time_sec = time_ms as f64 / 1000.0;
+    pub fn store_lap_seconds(&mut self, time_sec: f64) {
         self.writer.start_element("TotalTimeSeconds");
         self.writer.set_preserve_whitespaces(true);
         self.writer.write_text_fmt(format_args!("{:?}", &time_sec));
@@ -81,6 +88,40 @@ impl TcxWriter {
         self.writer.set_preserve_whitespaces(false);
     }
 
+    pub fn store_lap_maximum_speed(&mut self, speed: f64) {
+        self.store_value("MaximumSpeed", speed);
+    }
+    pub fn store_lap_average_heart_rate_bpm(&mut self, heart_rate_bpm: u8) {
+        self.store_heart_rate("AverageHeartRateBpm", heart_rate_bpm);
+    }
+    pub fn store_lap_maximum_heart_rate_bpm(&mut self, heart_rate_bpm: u8) {
+        self.store_heart_rate("MaximumHeartRateBpm", heart_rate_bpm);
+    }
+    /// Either "Active" or "Resting".
+    pub fn store_lap_intensity(&mut self, intensity: &str) {
+        self.store_text("Intensity", intensity);
+    }
+    pub fn store_lap_cadence_rpm(&mut self, cadence_rpm: u8) {
+        self.store_text("Cadence", &cadence_rpm.to_string());
+    }
+    /// One of "Manual", "Distance", "Location", "Time", or "HeartRate".
+    pub fn store_lap_trigger_method(&mut self, trigger_method: &str) {
+        self.store_text("TriggerMethod", trigger_method);
+    }
+    /// Writes the lap's Extensions element, which has to come after the track.
+    pub fn store_lap_extension(&mut self, lap_extension: &LapExtension) {
+        self.writer.start_element("Extensions");
+        self.writer.start_element("ns3:LX");
+        self.store_optional_value("ns3:AvgSpeed", lap_extension.avg_speed);
+        self.store_optional_integer("ns3:MaxBikeCadence", lap_extension.max_bike_cadence);
+        self.store_optional_integer("ns3:AvgRunCadence", lap_extension.avg_run_cadence);
+        self.store_optional_integer("ns3:MaxRunCadence", lap_extension.max_run_cadence);
+        self.store_optional_integer("ns3:AvgWatts", lap_extension.avg_watts);
+        self.store_optional_integer("ns3:MaxWatts", lap_extension.max_watts);
+        self.writer.end_element();
+        self.writer.end_element();
+    }
+
     pub fn start_track(&mut self){
         self.writer.start_element("Track");
     }
@@ -95,19 +136,24 @@ impl TcxWriter {
         self.writer.end_element();
     }
 
-    pub fn start_trackpoint_extensions(&mut self) {
+    pub fn start_extensions(&mut self) {
         self.writer.start_element("Extensions");
-        self.writer.start_element("TPX");
     }
-    pub fn end_trackpoint_extensions(&mut self) {
+    pub fn end_extensions(&mut self) {
         self.writer.end_element();
+    }
+
+    pub fn start_trackpoint_extensions(&mut self) {
+        self.writer.start_element("ns3:TPX");
+    }
+    pub fn end_trackpoint_extensions(&mut self) {
         self.writer.end_element();
     }
 
     pub fn store_time(&mut self, date_time_ms: u64) {
         self.writer.start_element("Time");
         self.writer.set_preserve_whitespaces(true);
-        self.writer.write_text(&TcxWriter::format_timestamp(date_time_ms));
+        self.writer.write_text(&format_iso_timestamp(date_time_ms));
         self.writer.end_element();
         self.writer.set_preserve_whitespaces(false);
     }
@@ -141,13 +187,49 @@ impl TcxWriter {
         self.writer.end_element();
         self.writer.set_preserve_whitespaces(false);
     }
+    pub fn store_speed(&mut self, speed: f64) {
+        self.store_value("ns3:Speed", speed);
+    }
+    pub fn store_run_cadence(&mut self, cadence: u8) {
+        self.store_text("ns3:RunCadence", &cadence.to_string());
+    }
     pub fn store_power_in_watts(&mut self, power_watts: u32) {
-        self.writer.start_element("Watts");
+        self.writer.start_element("ns3:Watts");
         self.writer.set_preserve_whitespaces(true);
         self.writer.write_text_fmt(format_args!("{:?}", &power_watts));
         self.writer.end_element();
         self.writer.set_preserve_whitespaces(false);
     }
+    fn store_heart_rate(&mut self, name: &str, heart_rate_bpm: u8) {
+        self.writer.start_element(name);
+        self.store_text("Value", &heart_rate_bpm.to_string());
+        self.writer.end_element();
+    }
+    fn store_text(&mut self, name: &str, text: &str) {
+        self.writer.start_element(name);
+        self.writer.set_preserve_whitespaces(true);
+        self.writer.write_text(text);
+        self.writer.end_element();
+        self.writer.set_preserve_whitespaces(false);
+    }
+    /// For schema types that are integers, such as cadence and watts.
+    fn store_optional_integer(&mut self, name: &str, value: Option<f64>) {
+        if let Some(value) = value {
+            self.store_text(name, &(value.round() as u32).to_string());
+        }
+    }
+    fn store_value(&mut self, name: &str, value: f64) {
+        self.store_optional_value(name, Some(value));
+    }
+    fn store_optional_value(&mut self, name: &str, value: Option<f64>) {
+        if let Some(value) = value {
+            self.writer.start_element(name);
+            self.writer.set_preserve_whitespaces(true);
+            self.writer.write_text_fmt(format_args!("{:?}", &value));
+            self.writer.end_element();
+            self.writer.set_preserve_whitespaces(false);
+        }
+    }
     pub fn store_position(&mut self, lat: f64, lon: f64) {
         self.writer.start_element("Position");
 
@@ -165,14 +247,4 @@ impl TcxWriter {
 
         self.writer.end_element();
     }
-
-    fn format_timestamp(t: u64) -> String {
-        let sec  = t / 1000;
-        let ms = t % 1000;
-
-        let datetime = DateTime::<Utc>::from_timestamp(sec as i64, 0).unwrap();
-        let buf1 = datetime.format("%Y-%m-%dT%H:%M:%S");
-        let buf2 = format!("{}.{:03}Z", buf1, ms);
-        buf2
-    }
 }
@@ -1,3 +1,5 @@
+use chrono::{DateTime, Utc};
+
 pub fn set_panic_hook() {
     // When the `console_error_panic_hook` feature is enabled, we can call the
     // `set_panic_hook` function at least once during initialization, and then
@@ -8,3 +10,13 @@ pub fn set_panic_hook() {
     #[cfg(feature = "console_error_panic_hook")]
     console_error_panic_hook::set_once();
 }
+
+/// Formats a timestamp (in milliseconds since the epoch) as ISO 8601, with milliseconds, as used by GPX, TCX, KML and CSV files.
+pub fn format_iso_timestamp(t: u64) -> String {
+    let sec  = t / 1000;
+    let ms = t % 1000;
+
+    let datetime = DateTime::<Utc>::from_timestamp(sec as i64, 0).unwrap();
+    let buf1 = datetime.format("%Y-%m-%dT%H:%M:%S");
+    format!("{}.{:03}Z", buf1, ms)
+}